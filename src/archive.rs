@@ -0,0 +1,390 @@
+extern crate zip;
+extern crate tar;
+extern crate flate2;
+
+use std::collections::*;
+use std::fs::File;
+use std::io::{BufReader, Error, ErrorKind, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// File-name endings of the archives walked as virtual directories (--archives), compared case-insensitively.
+pub const EXTENSIONS: [&str; 4] = [".zip", ".tar", ".tar.gz", ".tgz"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind
+{
+	Zip,
+	Tar,
+	TarGz
+}
+
+fn kind(path: &Path) -> Option<Kind>
+{
+	let name = path.file_name()?.to_string_lossy().to_lowercase();
+
+	match EXTENSIONS.iter().find(|e| name.ends_with(*e) && name.len() > e.len())
+	{
+		Some(&".zip") => Some(Kind::Zip),
+		Some(&".tar") => Some(Kind::Tar),
+		Some(_) => Some(Kind::TarGz),
+		None => None
+	}
+}
+
+/// True if the name of the path ends like an archive.
+pub fn is_archive(path: &Path) -> bool
+{
+	kind(path).is_some()
+}
+
+/// Splits a path inside an archive (archive-path followed by the path of the member) into the archive-file and the member.
+/// None for paths that do not lie inside an archive.
+pub fn split(path: &Path) -> Option<(PathBuf, PathBuf)>
+{
+	let archive = path.ancestors().skip(1).find(|a| is_archive(a) && a.is_file())?;
+	let member = path.strip_prefix(archive).ok()?;
+
+	Some((archive.to_path_buf(), member.to_path_buf()))
+}
+
+/// The archive containing the path - the path itself if it does not lie inside an archive.
+/// Members share the size and mtime of their archive, so replacing the archive marks all of them as changed.
+pub fn file_of(path: &Path) -> PathBuf
+{
+	split(path).map_or_else(|| path.to_path_buf(), |(archive, _)| archive)
+}
+
+// member-path without leading ./ - None if it is absolute or leaves the archive (..)
+fn normalized(path: &Path) -> Option<PathBuf>
+{
+	let mut out = PathBuf::new();
+
+	for component in path.components()
+	{
+		match component
+		{
+			Component::Normal(c) => out.push(c),
+			Component::CurDir => {},
+			_ => return None
+		}
+	}
+
+	if out.as_os_str().is_empty() { None } else { Some(out) }
+}
+
+fn invalid(e: impl std::fmt::Display) -> Error
+{
+	Error::new(ErrorKind::InvalidData, e.to_string())
+}
+
+fn open_zip(archive: &Path) -> std::io::Result<zip::ZipArchive<BufReader<File>>>
+{
+	zip::ZipArchive::new(BufReader::new(File::open(archive)?)).map_err(invalid)
+}
+
+fn open_tar(archive: &Path, kind: Kind) -> std::io::Result<tar::Archive<Box<dyn Read>>>
+{
+	let file = BufReader::new(File::open(archive)?);
+	let reader: Box<dyn Read> = if kind == Kind::TarGz { Box::new(flate2::read::GzDecoder::new(file)) } else { Box::new(file) };
+
+	Ok(tar::Archive::new(reader))
+}
+
+/// Paths of the regular files inside the archive, sorted. Members that would leave the archive (absolute paths, ..) are left out.
+pub fn members(archive: &Path) -> std::io::Result<Vec<PathBuf>>
+{
+	let mut out: Vec<PathBuf> = vec![];
+
+	match kind(archive)
+	{
+		None => return Err(invalid("not an archive")),
+		Some(Kind::Zip) =>
+		{
+			let mut zip = open_zip(archive)?;
+
+			for i in 0..zip.len()
+			{
+				let file = zip.by_index_raw(i).map_err(invalid)?;
+
+				if let Some(path) = file.enclosed_name().filter(|_| file.is_file()).and_then(|p| normalized(&p))
+				{
+					out.push(path);
+				}
+			}
+		},
+		Some(kind) =>
+		{
+			let mut tar = open_tar(archive, kind)?;
+
+			for entry in tar.entries()?
+			{
+				let entry = entry?;
+
+				if let Some(path) = Some(entry.path()?).filter(|_| entry.header().entry_type().is_file()).and_then(|p| normalized(&p))
+				{
+					out.push(path);
+				}
+			}
+		}
+	}
+
+	out.sort();
+	out.dedup();
+
+	Ok(out)
+}
+
+/// True if the path exists - on disk or as member of an archive. Members of every archive are listed once into the cache.
+pub fn exists(path: &Path, cache: &mut HashMap<PathBuf, HashSet<PathBuf>>) -> bool
+{
+	if path.exists()
+	{
+		return true;
+	}
+
+	let (archive, member) = match split(path)
+	{
+		None => return false,
+		Some(s) => s
+	};
+
+	cache.entry(archive).or_insert_with_key(|a| members(a).unwrap_or_default().into_iter().collect()).contains(&member)
+}
+
+// hands every wanted member to f (once) while reading through the archive - stops after the last one
+fn each_member<F>(archive: &Path, wanted: &HashSet<PathBuf>, mut f: F) -> std::io::Result<()>
+where
+	F: FnMut(&Path, &mut dyn Read)
+{
+	let mut left: HashSet<&PathBuf> = wanted.iter().collect();
+
+	match kind(archive)
+	{
+		None => Err(invalid("not an archive")),
+		Some(Kind::Zip) =>
+		{
+			let mut zip = open_zip(archive)?;
+
+			for i in 0..zip.len()
+			{
+				if left.is_empty()
+				{
+					break;
+				}
+
+				let mut file = zip.by_index(i).map_err(invalid)?;
+
+				if let Some(path) = file.enclosed_name().filter(|_| file.is_file()).and_then(|p| normalized(&p)).filter(|p| left.remove(p))
+				{
+					f(&path, &mut file);
+				}
+			}
+
+			Ok(())
+		},
+		Some(kind) =>
+		{
+			let mut tar = open_tar(archive, kind)?;
+
+			for entry in tar.entries()?
+			{
+				if left.is_empty()
+				{
+					break;
+				}
+
+				let mut entry = entry?;
+
+				if let Some(path) = Some(entry.path()?.to_path_buf()).filter(|_| entry.header().entry_type().is_file()).and_then(|p| normalized(&p)).filter(|p| left.remove(p))
+				{
+					f(&path, &mut entry);
+				}
+			}
+
+			Ok(())
+		}
+	}
+}
+
+/// Content of the file - read out of it's archive if the path lies inside of one.
+pub fn read(path: &Path) -> std::io::Result<Vec<u8>>
+{
+	let (archive, member) = match split(path)
+	{
+		None => return std::fs::read(path),
+		Some(s) => s
+	};
+
+	let mut out: Option<std::io::Result<Vec<u8>>> = None;
+
+	each_member(&archive, &HashSet::from([member]), |_, reader|
+	{
+		let mut content: Vec<u8> = vec![];
+
+		out = Some(reader.read_to_end(&mut content).map(|_| content));
+	})?;
+
+	out.unwrap_or_else(|| Err(Error::new(ErrorKind::NotFound, format!("not found in {:?}", archive))))
+}
+
+/// Hands a reader of the file's content to f - streamed out of it's archive if the path lies inside of one.
+pub fn with_reader<T, F>(path: &Path, f: F) -> std::io::Result<T>
+where
+	F: FnOnce(&mut dyn Read) -> std::io::Result<T>
+{
+	let (archive, member) = match split(path)
+	{
+		None => return f(&mut BufReader::new(File::open(path)?)),
+		Some(s) => s
+	};
+
+	let mut f = Some(f);
+	let mut out: Option<std::io::Result<T>> = None;
+
+	each_member(&archive, &HashSet::from([member]), |_, reader|
+	{
+		out = f.take().map(|f| f(reader));
+	})?;
+
+	out.unwrap_or_else(|| Err(Error::new(ErrorKind::NotFound, format!("not found in {:?}", archive))))
+}
+
+/// Extracts the given members of the archive to their target-paths (which must be free), reading the archive only once.
+/// The results are returned in order of the members.
+pub fn extract_all(archive: &Path, members: &[(&Path, &Path)]) -> Vec<std::io::Result<()>>
+{
+	let mut targets: HashMap<&Path, Vec<usize>> = HashMap::new();
+	let mut out: Vec<Option<std::io::Result<()>>> = members.iter().map(|_| None).collect();
+
+	for (i, (member, _)) in members.iter().enumerate()
+	{
+		targets.entry(member).or_default().push(i);
+	}
+
+	let wanted: HashSet<PathBuf> = targets.keys().map(|m| m.to_path_buf()).collect();
+
+	// the member is written to it's first target, all others are copies of that one
+	let read = each_member(archive, &wanted, |member, reader|
+	{
+		let indices = &targets[member];
+		let first = members[indices[0]].1;
+		let result = File::create(first).and_then(|mut f| std::io::copy(reader, &mut f)).map(|_| ());
+
+		for i in indices.iter().skip(1)
+		{
+			out[*i] = Some(match &result
+			{
+				Ok(_) => std::fs::copy(first, members[*i].1).map(|_| ()),
+				Err(e) => Err(Error::new(e.kind(), e.to_string()))
+			});
+		}
+
+		out[indices[0]] = Some(result);
+	});
+
+	let failed = |read: &std::io::Result<()>| match read
+	{
+		Err(e) => Err(Error::new(e.kind(), e.to_string())),
+		Ok(_) => Err(Error::new(ErrorKind::NotFound, format!("not found in {:?}", archive)))
+	};
+
+	out.into_iter().map(|r| r.unwrap_or_else(|| failed(&read))).collect()
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use std::io::Write;
+	use crate::catalog;
+	use crate::runtimeconfig;
+
+	// input-directory of a test below the temporary directory holding Pack.zip and Drums.tar.gz
+	fn input(name: &str) -> PathBuf
+	{
+		let dir = std::env::temp_dir().join(format!("kitcat-archive-{}-{}", name, std::process::id()));
+
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+
+		let mut zip = zip::ZipWriter::new(File::create(dir.join("Pack.zip")).unwrap());
+
+		for (name, content) in [("Kick/K1.wav", "kick one"), ("Snare/S1.wav", "snare one"), ("../Outside.wav", "outside")]
+		{
+			zip.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+			zip.write_all(content.as_bytes()).unwrap();
+		}
+
+		zip.add_directory("Empty/", zip::write::SimpleFileOptions::default()).unwrap();
+		zip.finish().unwrap();
+
+		let gz = flate2::write::GzEncoder::new(File::create(dir.join("Drums.tar.gz")).unwrap(), flate2::Compression::default());
+		let mut tar = tar::Builder::new(gz);
+
+		for (name, content) in [("./Kick/K2.wav", "kick two"), ("Hat/H1.wav", "hat one")]
+		{
+			let mut header = tar::Header::new_gnu();
+
+			header.set_size(content.len() as u64);
+			header.set_mode(0o644);
+			header.set_cksum();
+			tar.append_data(&mut header, name, content.as_bytes()).unwrap();
+		}
+
+		tar.into_inner().unwrap().finish().unwrap();
+		dir
+	}
+
+	#[test]
+	fn members_are_walked_and_matched_below_their_archive()
+	{
+		let dir = input("walk");
+		let args = ["kitcat", "-i", &dir.to_string_lossy(), "-o", "/out", "--archives", "--copy"];
+		let rcon = runtimeconfig::process_args(args.iter().map(|a| a.to_string()).collect()).unwrap();
+		let mut matched: Vec<String> = catalog::collect(&rcon.input, &rcon).unwrap().iter().map(|p| catalog::match_string(p, &rcon)).collect();
+
+		matched.sort();
+
+		assert_eq!(matched, ["Drums.tar.gz/Hat/H1.wav", "Drums.tar.gz/Kick/K2.wav", "Pack.zip/Kick/K1.wav", "Pack.zip/Snare/S1.wav"]);
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn paths_inside_an_archive_are_split_and_read_out_of_it()
+	{
+		let dir = input("read");
+
+		assert_eq!(split(&dir.join("Pack.zip/Kick/K1.wav")), Some((dir.join("Pack.zip"), PathBuf::from("Kick/K1.wav"))));
+		assert_eq!(split(&dir.join("Loose/Kick.wav")), None);
+		assert_eq!(file_of(&dir.join("Drums.tar.gz/Hat/H1.wav")), dir.join("Drums.tar.gz"));
+
+		assert_eq!(read(&dir.join("Pack.zip/Snare/S1.wav")).unwrap(), b"snare one");
+		assert_eq!(read(&dir.join("Drums.tar.gz/Kick/K2.wav")).unwrap(), b"kick two");
+		assert_eq!(read(&dir.join("Pack.zip/Kick/K9.wav")).unwrap_err().kind(), ErrorKind::NotFound);
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn members_are_extracted_to_all_their_targets_at_once()
+	{
+		let dir = input("extract");
+		let out = dir.join("out");
+
+		std::fs::create_dir_all(&out).unwrap();
+
+		let (k1, k1_copy, s1, missing) = (out.join("K1.wav"), out.join("K1 copy.wav"), out.join("S1.wav"), out.join("K9.wav"));
+		let members: [(&Path, &Path); 4] = [(Path::new("Kick/K1.wav"), &k1), (Path::new("Snare/S1.wav"), &s1), (Path::new("Kick/K1.wav"), &k1_copy), (Path::new("Kick/K9.wav"), &missing)];
+		let results = extract_all(&dir.join("Pack.zip"), &members);
+
+		assert!(results[..3].iter().all(|r| r.is_ok()));
+		assert_eq!(results[3].as_ref().unwrap_err().kind(), ErrorKind::NotFound);
+		assert_eq!(std::fs::read(&k1).unwrap(), b"kick one");
+		assert_eq!(std::fs::read(&k1_copy).unwrap(), b"kick one");
+		assert_eq!(std::fs::read(&s1).unwrap(), b"snare one");
+		assert!(!missing.exists());
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+}
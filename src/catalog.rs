@@ -2,20 +2,31 @@ extern crate stderrlog;
 extern crate log;
 	use log::*;
 
-extern crate walkdir;
-	use walkdir::WalkDir;
-
 extern crate regex;
 	use regex::*;
 
-use std::result::Result::Err;
+extern crate unicode_normalization;
+	use unicode_normalization::UnicodeNormalization;
+
+use std::io::Read;
 use std::path::Path;
 use std::path::PathBuf;
-use std::convert::TryInto;
 use std::collections::*;
 use crate::runtimeconfig::RuntimeConfig;
+use crate::error;
+	use crate::error::{Error, Result};
 use crate::ruleset;
 	use crate::ruleset::*;
+use crate::roots;
+use crate::parallel;
+	use crate::parallel::Walked;
+use crate::ignore::WalkFilter;
+use crate::writer;
+	use crate::writer::{CopyOptions, SoftLinks, WriteMode, WriteOutcome, WriteSummary};
+use crate::pack;
+	use crate::pack::PackFormat;
+use crate::manifest::Manifest;
+use crate::journal::Journal;
 
 pub struct Kit 
 {
@@ -36,8 +47,8 @@ impl std::fmt::Debug for Kit
 
 pub struct Sample 
 {
-	pub source_path: String,
-	pub target_path: String,
+	pub source_path: PathBuf,
+	pub target_path: PathBuf,
 	pub fields: HashMap<String, String>
 }
 
@@ -53,133 +64,244 @@ impl std::fmt::Debug for Sample
 	}
 }
 
-fn clone_sample(s: &Sample) -> Sample
+// result of matching a single path - computed by the workers, so nothing is logged yet
+enum Matched
 {
-	let mut out = Sample {
-		source_path: String::from(&s.source_path),
-		target_path: String::from(&s.target_path),
-		fields: HashMap::<String,String>::new()
-	};
-
-	for (key, value) in s.fields.iter()
-	{
-		out.fields.insert(key.to_string(), value.to_string());
-	}
-
-	out
+	Cached(HashMap<String, String>), // final group-values recorded in the manifest
+	Captured(HashMap<String, String>), // group-values before rearranging
+	RepeatedGroupsDiffer,
+	NoMatch
 }
 
-pub fn process_dataset(dataset: Vec<String>, rcon: &RuntimeConfig) -> HashMap<String, Kit>
+/// Matches every path of the dataset and groups the resulting samples by their index-value.
+/// Paths recorded in the manifest of a previous run with the same ruleset reuse their group-values (unless --full).
+pub fn process_dataset(dataset: Vec<PathBuf>, ruleset: &Ruleset, rcon: &RuntimeConfig, manifest: &Manifest) -> Result<HashMap<String, Kit>>
 {
 	let mut out: HashMap<String, Kit> = HashMap::new();
-	let ruleset: ruleset::Ruleset; 
-
-	if rcon.rules != ""
-	{
-		info!("Using custom configuration-file {:?}", rcon.rules);
+	let cached = if rcon.full { HashMap::new() } else { manifest.fields_by_source(&roots::root_fingerprint(ruleset, rcon)) };
 
-		ruleset = setup_custom_ruleset(&rcon.rules);
+	process_into(&mut out, dataset, ruleset, rcon, &cached)?;
 
-	}
-	else 
-	{
-		info!("Setting up default-configuration");
-
-		ruleset = setup_default_ruleset();
-	}
+	Ok(out)
+}
 
+/// Matches every path of the dataset (relative to rcon.input) and adds the resulting samples to the kits of their index-value.
+/// Paths with cached group-values are not matched again.
+pub fn process_into(kits: &mut HashMap<String, Kit>, dataset: Vec<PathBuf>, ruleset: &Ruleset, rcon: &RuntimeConfig, cached: &HashMap<&Path, &BTreeMap<String, String>>) -> Result<()>
+{
 	info!("Using Ruleset {:?}", ruleset);
 
-	let input_rule = Regex::new(&ruleset.input).unwrap();
-	let _output_rule = &ruleset.output;// just a string
-	let recheck_rule = Regex::new(&ruleset.recheck).unwrap();// compile here, not in loop
+	let rules = ruleset::compile(ruleset)?;// compile here, not in loop
 
-	info!("Applying input-rule {:?} on {:?}", input_rule, rcon.input);
+	info!("Applying input-rule {:?} on {:?}", rules.input, rcon.input);
 	info!("Found {:?} paths - processing...", dataset.len());
 
-	for path in dataset
+	let (samples, reused) = create_samples(&dataset, &rules, ruleset, rcon, cached)?;
+
+	for sample in samples
 	{
-		debug!("Processing {:?}...", path);
+		let index_value = kit_name(&sample, ruleset)?;
 
-		if input_rule.is_match(&path)
+		match kits.get_mut(&index_value)
 		{
-			let cap = input_rule.captures(&path).unwrap();
-				debug!("Captured {:?}", cap);
-			
-			let sample = &process_capture(cap, &rcon, &ruleset, &recheck_rule);
-				debug!("Created sample {:?}", sample);
-
-			let index_value = sample.fields.get(&ruleset.index).unwrap();
-
-			if out.contains_key(index_value)
-			{
-				out.get_mut(index_value).unwrap().samples.push(clone_sample(&sample));
-			}
-			else 
+			Some(kit) => kit.samples.push(sample),
+			None =>
 			{
 				let new_kit = Kit {
-					name: String::from(index_value),
-					samples: vec![clone_sample(&sample)]
+					name: index_value.to_string(),
+					samples: vec![sample]
 				};
 
 				debug!("Created Kit {:?}", new_kit);
 
-				out.insert(String::from(index_value), new_kit);
+				kits.insert(index_value, new_kit);
 			}
 		}
-		else 
+	}
+
+	info!("Reused the group-values of {:?} paths from the manifest", reused);
+
+	Ok(())
+}
+
+/// Matches the paths (relative to the input-directory) on up to rcon.workers() workers and creates their samples - in order of the paths.
+/// Paths with cached group-values are not matched again. Returns the samples and the count of paths that used cached values.
+pub fn create_samples(paths: &[PathBuf], rules: &CompiledRuleset, ruleset: &Ruleset, rcon: &RuntimeConfig, cached: &HashMap<&Path, &BTreeMap<String, String>>) -> Result<(Vec<Sample>, usize)>
+{
+	let mut out: Vec<Sample> = Vec::with_capacity(paths.len());
+	let mut reused: usize = 0;
+
+	let matches = parallel::map(paths, rcon.workers(), |path|
+	{
+		if let Some(fields) = cached.get(Path::new(&rcon.input).join(path).as_path())
+		{
+			return Matched::Cached(fields.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect());
+		}
+
+		match rules.input.captures(&match_string(path, rcon))
 		{
-			warn!("{:?} does not match on {:?}", path, input_rule);
+			None => Matched::NoMatch,
+			Some(cap) => map_capture(&cap, &ruleset.input_groups).map_or(Matched::RepeatedGroupsDiffer, Matched::Captured)
 		}
+	});
+
+	for (path, matched) in paths.iter().zip(matches)
+	{
+		debug!("Processing {:?}...", path);
+
+		let sample = match matched
+		{
+			Matched::Cached(fields) =>
+			{
+				reused+=1;
+				create_sample(path, fields, rcon, ruleset)
+			},
+			Matched::Captured(mut fields) =>
+			{
+				debug!("Captured {:?}", fields);
+
+				rearrange(&mut fields, ruleset, &rules.recheck)?;
+				apply_namespace(&mut fields, ruleset, rcon);
+				create_sample(path, fields, rcon, ruleset)
+			},
+			Matched::RepeatedGroupsDiffer => { warn!("{:?} does not match on {:?} - repeated groups differ", path, rules.input); continue },
+			Matched::NoMatch => { warn!("{:?} does not match on {:?}", path, rules.input); continue }
+		};
+
+		debug!("Created sample {:?}", sample);
+		out.push(sample);
 	}
-	
-	out
+
+	Ok((out, reused))
 }
 
-pub fn process_capture(cap: Captures, rcon: &RuntimeConfig, ruleset: &Ruleset, recheck_rule: &Regex) -> Sample
+/// Name of the kit the sample belongs to (the value of the index-group).
+pub fn kit_name(sample: &Sample, ruleset: &Ruleset) -> Result<String>
 {
-	let mut _path = rcon.input.to_string();
-	let mut _target_pre = rcon.output.to_string();
+	match sample.fields.get(&ruleset.index)
+	{
+		None => Err(Error::UnknownGroup { group: ruleset.index.to_string() }),
+		Some(v) => Ok(v.to_string())
+	}
+}
 
-	_path.push_str(&cap[0]);
-	_target_pre.push_str(&ruleset.output);
+pub struct Rearrangement
+{
+	pub group: String,
+	pub value: String,
+	pub rule: String,
+	pub result: String,
+	pub fired: bool
+}
 
-	debug!("Mapping {:?} by order {:?}", cap, ruleset.input_order);
+impl std::fmt::Debug for Rearrangement
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result 
+	{
+	    writeln!(f, "{{ [REARRANGEMENT] group: {:?} value: {:?} rule: {:?} result: {:?} fired: {:?} }}",
+    		self.group,
+    		self.value,
+    		self.rule,
+    		self.result,
+    		self.fired
+		)
+	}
+}
+
+/// Applies all rearranges whose group-value matches the recheck-rule to the given fields.
+/// Returns every checked rearrange (fired or not) in the order they were applied.
+pub fn rearrange(fields: &mut HashMap<String, String>, ruleset: &Ruleset, recheck_rule: &Regex) -> Result<Vec<Rearrangement>>
+{
+	let mut out: Vec<Rearrangement> = vec![];
 
-	let mut matched_groups = map_capture(&cap, &ruleset.input_order);
-	
 	for (group, replace_str) in ruleset.rearranges.iter()
 	{
-		let recheck_val = match matched_groups.get(group) 
+		let recheck_val = match fields.get(group) 
 		{
-			None => panic!("Failed on reading recheck value with given group name {:?} out of matching group {:?} - this group name is not defined in groups section of {:?}", group, matched_groups, rcon.rules),
-			Some(d) => d
+			None => return Err(Error::UnknownGroup { group: group.to_string() }),
+			Some(d) => d.to_string()
 		};
 
-		if recheck_rule.is_match(recheck_val)
+		let mut new_value = recheck_val.to_string();
+		let fired = recheck_rule.is_match(&recheck_val);
+
+		if fired
 		{
-			let mut new_value = String::from(replace_str);
+			new_value = String::from(replace_str);
 
-			apply_output_rule(&mut new_value, &matched_groups);
-			
-			let (r_group, _r_value) = matched_groups.remove_entry(group).unwrap();
+			apply_output_rule(&mut new_value, fields);
 
 			info!("Recheck-rule matched on field {:?} - created new value by {:?} -> {:?}", group, replace_str, new_value);
 
-			matched_groups.insert(r_group.to_string(), new_value.to_string());
+			fields.insert(group.to_string(), new_value.to_string());
 		}
+
+		out.push(Rearrangement {
+			group: group.to_string(),
+			value: recheck_val,
+			rule: replace_str.to_string(),
+			result: new_value,
+			fired
+		});
 	}
 
-	ruleset::apply_output_rule(&mut _target_pre, &matched_groups);
+	Ok(out)
+}
+
+pub fn process_capture(path: &Path, cap: Captures, rcon: &RuntimeConfig, ruleset: &Ruleset, recheck_rule: &Regex) -> Result<Option<Sample>>
+{
+	debug!("Mapping {:?} by groups {:?}", cap, ruleset.input_groups);
+
+	let mut matched_groups = match map_capture(&cap, &ruleset.input_groups)
+	{
+		Some(m) => m,
+		None => return Ok(None)
+	};
+	
+	rearrange(&mut matched_groups, ruleset, recheck_rule)?;
+	apply_namespace(&mut matched_groups, ruleset, rcon);
+
+	Ok(Some(create_sample(path, matched_groups, rcon, ruleset)))
+}
+
+/// Prefixes the index-value with the name of the input-directory (VendorA/KitA) if kits are namespaced.
+pub fn apply_namespace(fields: &mut HashMap<String, String>, ruleset: &Ruleset, rcon: &RuntimeConfig)
+{
+	if !rcon.namespace
+	{
+		return;
+	}
+
+	if let Some(value) = fields.get_mut(&ruleset.index)
+	{
+		*value = format!("{}/{}", rcon.root_name(), value);
+	}
+}
+
+/// Creates the sample of a path relative to the input-directory out of it's final group-values.
+pub fn create_sample(path: &Path, fields: HashMap<String, String>, rcon: &RuntimeConfig, ruleset: &Ruleset) -> Sample
+{
+	let mut _target_pre = ruleset.output.replace(ruleset::SEPARATOR, "/");
+
+	ruleset::apply_output_rule(&mut _target_pre, &fields);
 
 	Sample {
-		source_path: _path,
-		target_path: _target_pre.to_string(),
-		fields: matched_groups
+		source_path: Path::new(&rcon.input).join(path),
+		target_path: ruleset::output_path(&rcon.output, &_target_pre),
+		fields
 	}
 }
 
-pub fn apply_filters<'a,'b>(processed_dataset: &'a mut HashMap<String, Kit>, rcon: &'b RuntimeConfig) -> &'a mut HashMap<String, Kit>
+/// The string the input-rule is applied on: the path relative to the input-directory with / between it's components
+/// (on every OS), normalized as configured. Bytes that are not valid UTF-8 are replaced by U+FFFD - the sample still reads the file by it's real path.
+pub fn match_string(path: &Path, rcon: &RuntimeConfig) -> String
+{
+	let components: Vec<String> = path.iter().map(|c| c.to_string_lossy().to_string()).collect();
+
+	rcon.normalize.apply(&components.join("/"))
+}
+
+pub fn apply_filters<'a>(processed_dataset: &'a mut HashMap<String, Kit>, rcon: &RuntimeConfig) -> &'a mut HashMap<String, Kit>
 {
 	if rcon.trunc > 0
 	{
@@ -187,7 +309,7 @@ pub fn apply_filters<'a,'b>(processed_dataset: &'a mut HashMap<String, Kit>, rco
 
 		processed_dataset.retain(|_name, kit|
 		{
-			let retain = kit.samples.len() >= rcon.trunc.try_into().unwrap();
+			let retain = kit.samples.len() >= usize::from(rcon.trunc);
 
 			if !retain 
 			{
@@ -198,7 +320,7 @@ pub fn apply_filters<'a,'b>(processed_dataset: &'a mut HashMap<String, Kit>, rco
 		});
 	}
 
-	if rcon.kits.len() > 0
+	if !rcon.kits.is_empty()
 	{
 		info!("Applying kits-filter - every kit not in {:?} will be truncated", rcon.kits);
 
@@ -218,130 +340,558 @@ pub fn apply_filters<'a,'b>(processed_dataset: &'a mut HashMap<String, Kit>, rco
 	processed_dataset
 }
 
-pub fn write_dataset(processed_dataset: &HashMap<String, Kit>, rcon: &RuntimeConfig)
+/// Unicode normalization applied to paths before they are matched, so decomposed (macOS) and composed names match the same rules.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Normalization
 {
-	if rcon.dry 
+	Keep, // match paths as they are
+	Nfc, // canonical composition (Ä as a single code point)
+	Nfd // canonical decomposition (A followed by a combining diaeresis)
+}
+
+const NF_KEEP: &str = "none";
+const NF_NFC: &str = "nfc";
+const NF_NFD: &str = "nfd";
+
+impl Normalization
+{
+	pub const NAMES: [&'static str; 3] = [NF_KEEP, NF_NFC, NF_NFD];
+
+	pub fn name(&self) -> &'static str
 	{
-		info!("Not writing {:?} kits", processed_dataset.len());	
+		match self
+		{
+			Normalization::Keep => NF_KEEP,
+			Normalization::Nfc => NF_NFC,
+			Normalization::Nfd => NF_NFD
+		}
 	}
-	else 
+
+	pub fn from_name(name: &str) -> Option<Normalization>
 	{
-		info!("Writing {:?} kits", processed_dataset.len());
+		match name
+		{
+			NF_KEEP => Some(Normalization::Keep),
+			NF_NFC => Some(Normalization::Nfc),
+			NF_NFD => Some(Normalization::Nfd),
+			_ => None
+		}
 	}
 
-	let mut total_samples_failed = 0;
-	let mut total_samples_written = 0;
+	pub fn apply(&self, value: &str) -> String
+	{
+		match self
+		{
+			Normalization::Keep => value.to_string(),
+			Normalization::Nfc => value.nfc().collect(),
+			Normalization::Nfd => value.nfd().collect()
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CollisionPolicy
+{
+	Fail, // abort before anything is written
+	KeepFirst, // drop every sample whose target-path is already taken
+	Suffix, // append a counter to the file-name (Kick 2.wav)
+	Conflicts // route duplicates into the _conflicts directory of the output-directory
+}
+
+const CP_FAIL: &str = "fail";
+const CP_KEEP_FIRST: &str = "keep-first";
+const CP_SUFFIX: &str = "suffix";
+const CP_CONFLICTS: &str = "conflicts";
+
+pub const CONFLICTS_DIR: &str = "_conflicts";
 
-	for (_name, kit) in processed_dataset.iter()
+impl CollisionPolicy
+{
+	pub const NAMES: [&'static str; 4] = [CP_FAIL, CP_KEEP_FIRST, CP_SUFFIX, CP_CONFLICTS];
+
+	pub fn name(&self) -> &'static str
 	{
-		let current_samples = &kit.samples;
+		match self
+		{
+			CollisionPolicy::Fail => CP_FAIL,
+			CollisionPolicy::KeepFirst => CP_KEEP_FIRST,
+			CollisionPolicy::Suffix => CP_SUFFIX,
+			CollisionPolicy::Conflicts => CP_CONFLICTS
+		}
+	}
 
-		for sample in current_samples.into_iter()
+	pub fn from_name(name: &str) -> Option<CollisionPolicy>
+	{
+		match name
 		{
-			if rcon.dry
-			{
-				continue;
-			}
+			CP_FAIL => Some(CollisionPolicy::Fail),
+			CP_KEEP_FIRST => Some(CollisionPolicy::KeepFirst),
+			CP_SUFFIX => Some(CollisionPolicy::Suffix),
+			CP_CONFLICTS => Some(CollisionPolicy::Conflicts),
+			_ => None
+		}
+	}
+}
 
-			let filepath = PathBuf::from(&sample.target_path);
-			let path = filepath.with_file_name("");
+/// A sample whose target-path was already taken by another sample of the same run.
+pub struct Collision
+{
+	pub kit: String,
+	pub target: PathBuf,
+	pub source: PathBuf,
+	pub taken_by: PathBuf, // source-path of the sample that keeps the target-path (empty if unknown)
+	pub resolved: Option<PathBuf> // new target-path of the sample, None if it was dropped
+}
 
-			if !path.exists()
-			{
-				info!("Path {:?} does not exist - trying to create", path);
+impl std::fmt::Debug for Collision
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result 
+	{
+	    writeln!(f, "{{ [COLLISION] kit: {:?} target: {:?} source: {:?} taken_by: {:?} resolved: {:?} }}",
+    		self.kit,
+    		self.target,
+    		self.source,
+    		self.taken_by,
+    		self.resolved
+		)
+	}
+}
 
-				match std::fs::create_dir_all(&path)
-				{
-					Err(e) => { error!("Creating path {:?} failed: {:?} - skipping sample {:?}", path, e, sample); total_samples_failed+=1; continue },
-					Ok(_) => {}
-				}
-			}
+// targets differing in case only are the same file on case-insensitive filesystems
+pub fn collision_key(target: &Path) -> std::ffi::OsString
+{
+	if cfg!(any(target_family = "windows", target_os = "macos"))
+	{
+		return target.to_string_lossy().to_lowercase().into();
+	}
 
-			if rcon.copy 
+	target.as_os_str().to_os_string()
+}
+
+fn free_target(target: &Path, is_taken: &dyn Fn(&Path) -> bool) -> PathBuf
+{
+	let stem = target.file_stem().unwrap_or_default();
+
+	(2..).map(|i|
+	{
+		let mut name = stem.to_os_string();
+
+		name.push(format!(" {}", i));
+
+		if let Some(extension) = target.extension()
+		{
+			name.push(".");
+			name.push(extension);
+		}
+
+		target.with_file_name(name)
+	})
+	.find(|t| !is_taken(t))
+	.unwrap_or_else(|| target.to_path_buf())
+}
+
+/// New target-path of a sample whose target-path is already taken - None if the policy drops it.
+pub fn route_collision(target: &Path, rcon: &RuntimeConfig, is_taken: &dyn Fn(&Path) -> bool) -> Option<PathBuf>
+{
+	let routed = match rcon.on_collision
+	{
+		CollisionPolicy::Fail | CollisionPolicy::KeepFirst => return None,
+		CollisionPolicy::Suffix => target.to_path_buf(),
+		CollisionPolicy::Conflicts =>
+		{
+			let relative = target.strip_prefix(&rcon.output).unwrap_or(target);
+			Path::new(&rcon.output).join(CONFLICTS_DIR).join(relative)
+		}
+	};
+
+	Some(if is_taken(&routed) { free_target(&routed, is_taken) } else { routed })
+}
+
+/// Finds samples sharing their target-path with another sample and resolves them by the configured policy.
+/// Samples are ordered by source-path (and kits by name), so the first sample of a target-path is always the same one.
+pub fn resolve_collisions(processed_dataset: &mut HashMap<String, Kit>, rcon: &RuntimeConfig) -> Result<Vec<Collision>>
+{
+	let mut out: Vec<Collision> = vec![];
+	let mut taken: HashMap<std::ffi::OsString, PathBuf> = HashMap::new(); // collision_key(target) -> source
+	let mut names: Vec<String> = processed_dataset.keys().cloned().collect();
+
+	names.sort();
+
+	for name in names.iter()
+	{
+		let kit = match processed_dataset.get_mut(name)
+		{
+			None => continue,
+			Some(k) => k
+		};
+
+		kit.samples.sort_by(|a, b| a.source_path.cmp(&b.source_path));
+
+		let mut kept: Vec<Sample> = vec![];
+
+		for mut sample in kit.samples.drain(..)
+		{
+			let key = collision_key(&sample.target_path);
+
+			let taken_by = match taken.get(&key)
 			{
-				match copy_sample(sample)
-				{
-					true => total_samples_written+=1,
-					false => total_samples_failed+=1
-				}
-			}
-			else
+				None => { taken.insert(key, sample.source_path.to_path_buf()); kept.push(sample); continue },
+				Some(s) => s.to_path_buf()
+			};
+
+			let mut collision = Collision {
+				kit: name.to_string(),
+				target: sample.target_path.to_path_buf(),
+				source: sample.source_path.to_path_buf(),
+				taken_by,
+				resolved: None
+			};
+
+			if let Some(target) = route_collision(&sample.target_path, rcon, &|t| taken.contains_key(&collision_key(t)))
 			{
-				match link_sample(sample, rcon.soft)
-				{
-					true => total_samples_written+=1,
-					false => total_samples_failed+=1
-				}
+				taken.insert(collision_key(&target), sample.source_path.to_path_buf());
+				sample.target_path = target.to_path_buf();
+				collision.resolved = Some(target);
+				kept.push(sample);
 			}
+
+			out.push(collision);
 		}
+
+		kit.samples = kept;
 	}
 
-	info!("Wrote {:?} samples, {:?} failed, {:?} total", total_samples_written, total_samples_failed, total_samples_failed+total_samples_written)
+	processed_dataset.retain(|_name, kit| !kit.samples.is_empty());
+	report_collisions(&out, rcon.on_collision);
+
+	if rcon.on_collision == CollisionPolicy::Fail && !out.is_empty()
+	{
+		return Err(Error::Collisions { count: out.len() });
+	}
+
+	Ok(out)
 }
 
-fn copy_sample(sample: &Sample) -> bool
+/// Logs the collisions grouped by kit.
+pub fn report_collisions(collisions: &[Collision], policy: CollisionPolicy)
 {
-	debug!("Copying {:?} to {:?}", sample.source_path, sample.target_path);
+	let mut kit = "";
 
-	let source = Path::new(&sample.source_path);
-	let target = &Path::new(&sample.target_path);
-
-	match std::fs::copy(source, target)
+	for collision in collisions.iter()
 	{
-		Err(e) => { error!("Creating softlink for {:?} failed: {:?}", sample, e); return false },
-		Ok(_) => { return true }
+		if collision.kit != kit
+		{
+			kit = &collision.kit;
+			warn!("Kit {:?}: {:?} target-path collision(s)", kit, collisions.iter().filter(|c| c.kit == kit).count());
+		}
+
+		let taken_by = if collision.taken_by.as_os_str().is_empty() { "another sample".to_string() } else { format!("{:?}", collision.taken_by) };
+
+		match &collision.resolved
+		{
+			None => warn!("\t{:?} -> {:?} is already taken by {} - {} ({})", collision.source, collision.target, taken_by, if policy == CollisionPolicy::Fail { "failing" } else { "skipping" }, policy.name()),
+			Some(t) => warn!("\t{:?} -> {:?} is already taken by {} - using {:?} ({})", collision.source, collision.target, taken_by, t, policy.name())
+		}
 	}
 }
 
-fn link_sample(sample: &Sample, soft: bool) -> bool
+/// Writes all samples of the kits. Duplicates found by dedup (target-path -> target-path of the first sample of their cluster)
+/// are hard-linked to that one after all other samples are written - or written like any other sample if it was not.
+/// Nothing is written if the copies would not fit into the free space of their filesystem.
+pub fn write_dataset(processed_dataset: &HashMap<String, Kit>, rcon: &RuntimeConfig, manifest: &mut Manifest, journal: &mut Journal, links: &HashMap<PathBuf, PathBuf>) -> Result<WriteSummary>
 {
-	let source = Path::new(&sample.source_path);
-	let target = &Path::new(&sample.target_path);
+	let mut summary = WriteSummary::new();
+	let mode = WriteMode::from_config(rcon);
+	let soft_links = SoftLinks::from_config(rcon);
+	let copies = CopyOptions::from_config(rcon);
 
-	if soft 
+	if rcon.dry 
 	{
-		debug!("Linking (soft) {:?} to {:?}", sample.source_path, sample.target_path);
+		info!("Not writing {:?} kits", processed_dataset.len());	
 
-		#[cfg(target_family = "windows")]
-			match std::os::windows::fs::symlink_file(source, target)
-			{
-				Err(e) => { error!("Creating softlink for {:?} failed: {:?}", sample, e); return false },
-				Ok(_) => return true
-			}
-		
-		#[cfg(target_family = "unix")]
-			match std::os::unix::fs::symlink(source, target)
-			{
-				Err(e) => { error!("Creating softlink for {:?} failed: {:?}", sample, e); return false },
-				Ok(_) => return true
-			}
+		return Ok(summary);
+	}
+
+	info!("Writing {:?} kits", processed_dataset.len());
+
+	let mut kits: Vec<&Kit> = processed_dataset.values().collect();
+	kits.sort_by(|a, b| a.name.cmp(&b.name));
+
+	if rcon.pack != PackFormat::None
+	{
+		return Ok(pack::write(&kits, rcon, journal));
 	}
-	else 
+
+	let all: Vec<(&Kit, &Sample)> = kits.iter().flat_map(|kit| kit.samples.iter().map(move |sample| (*kit, sample))).collect();
+	let (linked, samples): (Vec<_>, Vec<_>) = all.into_iter().partition(|(_, sample)| links.contains_key(&sample.target_path));
+
+	let jobs: Vec<(&Sample, WriteMode)> = samples.iter().map(|(_, sample)| (*sample, mode)).collect();
+
+	writer::check_space(&jobs, rcon.on_existing)?; // duplicates are linked - unless the first sample of their cluster was not written
+
+	let outcomes = writer::write_samples(&jobs, rcon.on_existing, &soft_links, &copies, journal, rcon.workers());
+	let mut written: HashMap<&Path, PathBuf> = HashMap::new(); // target-path -> path the sample was written to
+
+	for ((kit, sample), (outcome, used)) in samples.iter().zip(outcomes)
 	{
-		debug!("Linking (hard) {:?} to {:?}", sample.source_path, sample.target_path);
-		
-		match std::fs::hard_link(source, target)
+		match &outcome
 		{
-			Err(e) => { error!("Creating hardlink for {:?} failed: {:?}", sample, e); return false },
-			Ok(_) => return true
+			WriteOutcome::Written | WriteOutcome::Overwritten => { written.insert(&sample.target_path, sample.target_path.to_path_buf()); },
+			WriteOutcome::Renamed(p) => { written.insert(&sample.target_path, p.to_path_buf()); },
+			_ => {}
 		}
+
+		manifest.record_outcome(&kit.name, sample, used, &outcome, rcon.hash);
+		summary.count_sample(sample, &outcome, used);
 	}
+
+	// the first sample of the cluster was written now or by a previous run (and is unchanged since)
+	let link_to = |target: &Path| written.get(target).cloned().or_else(|| Some(target.to_path_buf()).filter(|t| manifest.owns(t)));
+
+	let redirected: Vec<(Sample, WriteMode)> = linked.iter().map(|(_, sample)| match link_to(&links[&sample.target_path])
+	{
+		Some(to) => (Sample { source_path: to, target_path: sample.target_path.to_path_buf(), fields: sample.fields.clone() }, WriteMode::Hard),
+		None => (Sample { source_path: sample.source_path.to_path_buf(), target_path: sample.target_path.to_path_buf(), fields: sample.fields.clone() }, mode)
+	}).collect();
+
+	let jobs: Vec<(&Sample, WriteMode)> = redirected.iter().map(|(sample, mode)| (sample, *mode)).collect();
+	let outcomes = writer::write_samples(&jobs, rcon.on_existing, &soft_links, &copies, journal, rcon.workers());
+
+	for ((kit, sample), (outcome, used)) in linked.iter().zip(outcomes)
+	{
+		manifest.record_outcome(&kit.name, sample, used, &outcome, rcon.hash);
+		summary.count_sample(sample, &outcome, used);
+	}
+
+	info!("{}", summary);
+	Ok(summary)
 }
 
-pub fn collect(from: &str) -> Vec<String>
+/// Paths of all regular files below the input-directory that are not excluded, relative to it - including paths that are not valid UTF-8.
+pub fn collect(from: &str, rcon: &RuntimeConfig) -> Result<Vec<PathBuf>>
 {
 	info!("Collecting samples from {:?}", from);
-	let mut samples: Vec<String> = vec![];
+	let mut samples: Vec<PathBuf> = vec![];
+
+	if let Err(e) = std::fs::read_dir(from)
+	{
+		return Err(error::io(from, e));
+	}
+
+	let filter = WalkFilter::from_config(rcon)?;
+	let mut excluded = 0;
+
+	info!("Walking with {:?}", filter);
+
+	for walked in parallel::walk(Path::new(from), &filter, rcon.workers())
+	{
+		let path = match walked
+		{
+			Walked::File(p) => p,
+			Walked::Unreadable(p, e) => { warn!("Skipping {:?} - {}", p, e); continue },
+			Walked::Skipped(p, reason) => { warn!("Skipping {:?} - {}", p, reason); continue },
+			Walked::Excluded(p) => { debug!("Excluded {:?}", p); excluded+=1; continue }
+		};
 
-	for e in WalkDir::new(&from).into_iter().filter_map(|e| e.ok()) 
+		samples.push(path.strip_prefix(from).unwrap_or(&path).to_path_buf());
+	}
+
+	if excluded > 0
+	{
+		info!("Excluded {:?} files and directories", excluded);
+	}
+
+	info!("Found {:?} samples", samples.len());
+
+	Ok(samples)
+}
+
+/// Paths listed in the given file (- for stdin), separated by NUL-characters if there are any, by newlines otherwise.
+/// Relative paths are joined to the base-directory (the current directory if empty). Empty lines are ignored.
+pub fn read_list(from: &str, base: &str) -> Result<Vec<PathBuf>>
+{
+	let mut content: Vec<u8> = vec![];
+	let read = if from == "-"
+	{
+		info!("Reading file-list from stdin");
+		std::io::stdin().lock().read_to_end(&mut content)
+	}
+	else
+	{
+		info!("Reading file-list from {:?}", from);
+		std::fs::File::open(from).and_then(|mut f| f.read_to_end(&mut content))
+	};
+
+	read.map_err(|e| error::io(from, e))?;
+
+	let base = if base.is_empty() { std::env::current_dir().map_err(|e| error::io(".", e))? } else { PathBuf::from(base) };
+	let separator = if content.contains(&0) { b'\0' } else { b'\n' };
+	let mut out: Vec<PathBuf> = vec![];
+
+	for entry in content.split(|b| *b == separator)
+	{
+		let entry = if separator == b'\n' { entry.strip_suffix(b"\r").unwrap_or(entry) } else { entry };
+
+		if !entry.is_empty()
+		{
+			out.push(base.join(path_of_bytes(entry)));
+		}
+	}
+
+	info!("Listed {:?} paths", out.len());
+
+	Ok(out)
+}
+
+#[cfg(target_family = "unix")]
+fn path_of_bytes(bytes: &[u8]) -> PathBuf
+{
+	PathBuf::from(<std::ffi::OsStr as std::os::unix::ffi::OsStrExt>::from_bytes(bytes))
+}
+
+#[cfg(not(target_family = "unix"))]
+fn path_of_bytes(bytes: &[u8]) -> PathBuf
+{
+	String::from_utf8_lossy(bytes).to_string().into()
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use crate::runtimeconfig;
+
+	// config of a run from the temporary directory into /out with the given further arguments
+	fn config(args: &[&str]) -> RuntimeConfig
+	{
+		let input = std::env::temp_dir().to_string_lossy().to_string();
+		let mut all: Vec<String> = vec!["kitcat".to_string(), "-i".to_string(), input, "-o".to_string(), "/out".to_string()];
+
+		all.extend(args.iter().map(|a| a.to_string()));
+		runtimeconfig::process_args(all).unwrap()
+	}
+
+	// ruleset mapping {group}/{sample} {kit}.wav to {kit}/{group}.wav
+	fn ruleset() -> Ruleset
+	{
+		let mut out = Ruleset {
+			groups: [("group", "(Kick|Snare)"), ("sample", "(.+)"), ("kit", "(Kit[A-Z])")].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+			rearranges: HashMap::new(),
+			recheck: String::new(),
+			raw_input: r"^{group}/{sample} {kit}\.wav$".to_string(),
+			input: String::new(),
+			output: "{kit}/{group}.wav".to_string(),
+			index: "kit".to_string(),
+			input_groups: HashMap::new()
+		};
+
+		out.input = out.raw_input.to_string();
+		out.input_groups = ruleset::apply_input_groups(&mut out.input, &out.groups).unwrap();
+		out
+	}
+
+	// kits of the samples of the paths, mapped by ruleset()
+	fn kits(paths: &[&str], rcon: &RuntimeConfig) -> HashMap<String, Kit>
+	{
+		let ruleset = ruleset();
+		let rules = ruleset::compile(&ruleset).unwrap();
+		let mut out: HashMap<String, Kit> = HashMap::new();
+
+		for path in paths.iter().map(Path::new)
+		{
+			let fields = map_capture(&rules.input.captures(&match_string(path, rcon)).unwrap(), &ruleset.input_groups).unwrap();
+			let sample = create_sample(path, fields, rcon, &ruleset);
+			let name = kit_name(&sample, &ruleset).unwrap();
+
+			out.entry(name.to_string()).or_insert(Kit { name, samples: vec![] }).samples.push(sample);
+		}
+
+		out
+	}
+
+	// target-paths of the samples by kit and source-name, after the collisions were resolved
+	fn targets(kits: &HashMap<String, Kit>) -> BTreeMap<String, String>
 	{
-        if e.metadata().unwrap().is_file() 
-        {
-            samples.push(String::from(e.path().strip_prefix(&from).unwrap().to_str().unwrap()));
-        }
-    }
+		kits.values().flat_map(|k| k.samples.iter())
+		.map(|s| (s.source_path.file_name().unwrap().to_string_lossy().to_string(), s.target_path.to_string_lossy().to_string()))
+		.collect()
+	}
 
-    info!("Found {:?} samples", samples.len());
+	#[test]
+	fn keep_first_keeps_the_sample_with_the_first_source_path()
+	{
+		let rcon = config(&["--on-collision", "keep-first"]);
+		let mut kits = kits(&["Kick/Kick3 KitA.wav", "Kick/Kick1 KitA.wav", "Snare/Snare KitA.wav", "Kick/Kick2 KitA.wav"], &rcon);
+		let collisions = resolve_collisions(&mut kits, &rcon).unwrap();
+
+		assert_eq!(collisions.len(), 2);
+		assert!(collisions.iter().all(|c| c.resolved.is_none() && c.taken_by.ends_with("Kick/Kick1 KitA.wav")));
+		assert_eq!(targets(&kits), BTreeMap::from([
+			("Kick1 KitA.wav".to_string(), "/out/KitA/Kick.wav".to_string()),
+			("Snare KitA.wav".to_string(), "/out/KitA/Snare.wav".to_string())
+		]));
+	}
 
-    samples
-}
\ No newline at end of file
+	#[test]
+	fn suffix_numbers_the_later_samples_past_taken_names()
+	{
+		let rcon = config(&["--on-collision", "suffix"]);
+		let mut kits = kits(&["Kick/Kick1 KitA.wav", "Kick/Kick2 KitA.wav", "Kick/Kick3 KitA.wav", "Kick/Kick1 KitB.wav"], &rcon);
+
+		resolve_collisions(&mut kits, &rcon).unwrap();
+
+		assert_eq!(targets(&kits), BTreeMap::from([
+			("Kick1 KitA.wav".to_string(), "/out/KitA/Kick.wav".to_string()),
+			("Kick2 KitA.wav".to_string(), "/out/KitA/Kick 2.wav".to_string()),
+			("Kick3 KitA.wav".to_string(), "/out/KitA/Kick 3.wav".to_string()),
+			("Kick1 KitB.wav".to_string(), "/out/KitB/Kick.wav".to_string())
+		]));
+
+		let taken = |t: &Path| t == Path::new("/out/KitA/Kick 2.wav") || t == Path::new("/out/KitA/Kick.wav");
+
+		assert_eq!(route_collision(Path::new("/out/KitA/Kick.wav"), &rcon, &taken), Some(PathBuf::from("/out/KitA/Kick 3.wav")));
+	}
+
+	#[test]
+	fn conflicts_moves_the_later_samples_below_the_conflicts_directory()
+	{
+		let rcon = config(&["--on-collision", "conflicts"]);
+		let mut kits = kits(&["Kick/Kick1 KitA.wav", "Kick/Kick2 KitA.wav", "Kick/Kick3 KitA.wav"], &rcon);
+		let collisions = resolve_collisions(&mut kits, &rcon).unwrap();
+
+		assert_eq!(collisions.iter().map(|c| c.resolved.clone().unwrap()).collect::<Vec<_>>(), [
+			PathBuf::from("/out/_conflicts/KitA/Kick.wav"),
+			PathBuf::from("/out/_conflicts/KitA/Kick 2.wav")
+		]);
+		assert_eq!(targets(&kits)["Kick1 KitA.wav"], "/out/KitA/Kick.wav");
+	}
+
+	#[test]
+	fn fail_counts_every_collision()
+	{
+		let rcon = config(&["--on-collision", "fail"]);
+		let mut kits = kits(&["Kick/Kick1 KitA.wav", "Kick/Kick2 KitA.wav", "Kick/Kick3 KitA.wav", "Snare/Snare KitA.wav"], &rcon);
+
+		assert!(matches!(resolve_collisions(&mut kits, &rcon), Err(Error::Collisions { count: 2 })));
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn non_utf8_paths_are_matched_lossy_but_read_by_their_real_path()
+	{
+		use std::os::unix::ffi::OsStrExt;
+
+		let rcon = config(&[]);
+		let ruleset = ruleset();
+		let rules = ruleset::compile(&ruleset).unwrap();
+		let path = Path::new(std::ffi::OsStr::from_bytes(b"Kick/Kick\xff KitA.wav"));
+		let matched = match_string(path, &rcon);
+		let fields = map_capture(&rules.input.captures(&matched).unwrap(), &ruleset.input_groups).unwrap();
+		let sample = create_sample(path, fields, &rcon, &ruleset);
+
+		assert_eq!(matched, "Kick/Kick\u{fffd} KitA.wav");
+		assert_eq!(sample.fields["sample"], "Kick\u{fffd}");
+		assert_eq!(sample.source_path, Path::new(&rcon.input).join(path));
+		assert_eq!(sample.target_path, Path::new("/out/KitA/Kick.wav"));
+	}
+}
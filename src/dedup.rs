@@ -0,0 +1,583 @@
+extern crate log;
+	use log::*;
+
+use std::collections::*;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use crate::runtimeconfig::RuntimeConfig;
+use crate::catalog::Kit;
+use crate::manifest;
+use crate::archive;
+use crate::parallel;
+
+/// What makes two samples duplicates of each other.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DedupMode
+{
+	None, // no hashing stage
+	Bytes, // byte-identical files
+	Audio // identical fmt- and data-chunks of RIFF/WAVE files - other chunks (bext, LIST, iXML, ...) are ignored
+}
+
+const DM_NONE: &str = "none";
+const DM_BYTES: &str = "bytes";
+const DM_AUDIO: &str = "audio";
+
+impl DedupMode
+{
+	pub const NAMES: [&'static str; 3] = [DM_NONE, DM_BYTES, DM_AUDIO];
+
+	pub fn name(&self) -> &'static str
+	{
+		match self
+		{
+			DedupMode::None => DM_NONE,
+			DedupMode::Bytes => DM_BYTES,
+			DedupMode::Audio => DM_AUDIO
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<DedupMode>
+	{
+		match name
+		{
+			DM_NONE => Some(DedupMode::None),
+			DM_BYTES => Some(DedupMode::Bytes),
+			DM_AUDIO => Some(DedupMode::Audio),
+			_ => None
+		}
+	}
+}
+
+/// What to do with the duplicates found.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DuplicatePolicy
+{
+	Report, // only report the clusters
+	Link, // copy the first sample of a cluster, hard-link all others to it
+	Drop // drop all but the first sample of a cluster inside the same kit
+}
+
+const DP_REPORT: &str = "report";
+const DP_LINK: &str = "link";
+const DP_DROP: &str = "drop";
+
+impl DuplicatePolicy
+{
+	pub const NAMES: [&'static str; 3] = [DP_REPORT, DP_LINK, DP_DROP];
+
+	pub fn name(&self) -> &'static str
+	{
+		match self
+		{
+			DuplicatePolicy::Report => DP_REPORT,
+			DuplicatePolicy::Link => DP_LINK,
+			DuplicatePolicy::Drop => DP_DROP
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<DuplicatePolicy>
+	{
+		match name
+		{
+			DP_REPORT => Some(DuplicatePolicy::Report),
+			DP_LINK => Some(DuplicatePolicy::Link),
+			DP_DROP => Some(DuplicatePolicy::Drop),
+			_ => None
+		}
+	}
+}
+
+/// Samples with the same content - ordered by kit and target-path, the first one is kept (or linked to).
+pub struct Cluster
+{
+	pub size: u64, // of the compared content
+	pub samples: Vec<(String, PathBuf)> // kit-name, target-path
+}
+
+pub struct DedupReport
+{
+	pub mode: DedupMode,
+	pub clusters: Vec<Cluster>,
+	pub dropped: usize, // duplicates removed from their kit
+	pub links: HashMap<PathBuf, PathBuf> // target-path of a duplicate -> target-path of the first sample of it's cluster
+}
+
+impl std::fmt::Debug for DedupReport
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [DEDUPREPORT] mode: {:?} clusters: {:?} dropped: {:?} links: {:?} }}",
+    		self.mode,
+    		self.clusters.len(),
+    		self.dropped,
+    		self.links.len()
+		)
+	}
+}
+
+impl std::fmt::Display for DedupReport
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		let redundant: usize = self.clusters.iter().map(|c| c.samples.len() - 1).sum();
+		let bytes: u64 = self.clusters.iter().map(|c| c.size * (c.samples.len() as u64 - 1)).sum();
+
+		for cluster in self.clusters.iter()
+		{
+			writeln!(f, "{} ({})", cluster.samples[0].1.display(), cluster.samples[0].0)?;
+
+			for (kit, target) in cluster.samples.iter().skip(1)
+			{
+				writeln!(f, "\t= {} ({})", target.display(), kit)?;
+			}
+		}
+
+		write!(f, "Duplicates ({}): {} clusters, {} redundant samples ({} bytes), {} dropped, {} linked", self.mode.name(), self.clusters.len(), redundant, bytes, self.dropped, self.links.len())
+	}
+}
+
+// size of the pieces compared
+const CHUNK_SIZE: usize = 65536;
+
+/// Streams the fmt- and data-chunks of a RIFF/WAVE file (each as it's id, content and length) out of the reader - other chunks are skipped.
+/// Truncated chunks end with the file. Nothing is read if the file is no RIFF/WAVE file; is_audio tells if it was one with a data-chunk once the stream is read.
+struct AudioPayload<R: Read>
+{
+	inner: R,
+	started: bool,
+	riff: bool, // the file starts with a RIFF/WAVE header
+	has_data: bool,
+	chunk: Option<(bool, u64, u64)>, // current chunk: emitted, bytes of it's content left, bytes read of it
+	padded: bool, // current chunk is followed by a pad-byte
+	pending: Vec<u8>, // header or length of an emitted chunk not read yet
+	done: bool
+}
+
+impl<R: Read> AudioPayload<R>
+{
+	fn new(inner: R) -> AudioPayload<R>
+	{
+		AudioPayload { inner, started: false, riff: false, has_data: false, chunk: None, padded: false, pending: vec![], done: false }
+	}
+
+	fn is_audio(&self) -> bool
+	{
+		self.riff && self.has_data
+	}
+}
+
+// reads until the buffer is full or the reader ends - returns the count of bytes read
+fn fill(reader: &mut dyn Read, buf: &mut [u8]) -> std::io::Result<usize>
+{
+	let mut read = 0;
+
+	while read < buf.len()
+	{
+		match reader.read(&mut buf[read..])
+		{
+			Ok(0) => break,
+			Ok(n) => read += n,
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {},
+			Err(e) => return Err(e)
+		}
+	}
+
+	Ok(read)
+}
+
+impl<R: Read> Read for AudioPayload<R>
+{
+	fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize>
+	{
+		loop
+		{
+			if !self.pending.is_empty()
+			{
+				let n = buf.len().min(self.pending.len());
+
+				buf[..n].copy_from_slice(&self.pending[..n]);
+				self.pending.drain(..n);
+
+				return Ok(n);
+			}
+
+			if self.done || buf.is_empty()
+			{
+				return Ok(0);
+			}
+
+			if !self.started
+			{
+				let mut header = [0u8; 12];
+
+				self.started = true;
+				self.riff = fill(&mut self.inner, &mut header)? == 12 && &header[0..4] == b"RIFF" && &header[8..12] == b"WAVE";
+				self.done = !self.riff;
+				continue;
+			}
+
+			match self.chunk
+			{
+				Some((emitted, left, read)) if left > 0 =>
+				{
+					let wanted = left.min(buf.len() as u64) as usize;
+					let n = if emitted
+					{
+						self.inner.read(&mut buf[..wanted])?
+					}
+					else
+					{
+						let mut skipped = [0u8; 8192];
+
+						fill(&mut self.inner, &mut skipped[..left.min(8192) as usize])?
+					};
+
+					// a truncated chunk ends with the file
+					self.chunk = Some((emitted, if n == 0 { 0 } else { left - n as u64 }, read + n as u64));
+
+					if emitted && n > 0
+					{
+						return Ok(n);
+					}
+				},
+				Some((emitted, _, read)) =>
+				{
+					if emitted
+					{
+						self.pending.extend_from_slice(&read.to_le_bytes());
+					}
+
+					if self.padded
+					{
+						fill(&mut self.inner, &mut [0u8; 1])?; // chunks are padded to an even length
+					}
+
+					self.chunk = None;
+				},
+				None =>
+				{
+					let mut header = [0u8; 8];
+
+					if fill(&mut self.inner, &mut header)? < 8
+					{
+						self.done = true;
+						continue;
+					}
+
+					let id = &header[0..4];
+					let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+					let emitted = id == b"fmt " || id == b"data";
+
+					if emitted
+					{
+						self.pending.extend_from_slice(id);
+						self.has_data |= id == b"data";
+					}
+
+					self.chunk = Some((emitted, u64::from(size), 0));
+					self.padded = size & 1 == 1;
+				}
+			}
+		}
+	}
+}
+
+// length and hash of the content compared by the mode, and if it is the audio-payload - files that are no RIFF/WAVE (with a data-chunk) are compared byte by byte
+fn digest(source: &Path, mode: DedupMode) -> std::io::Result<(u64, String, bool)>
+{
+	if mode == DedupMode::Audio
+	{
+		let audio = archive::with_reader(source, |r|
+		{
+			let mut payload = AudioPayload::new(r);
+			let (size, hash) = manifest::hash_reader(&mut payload)?;
+
+			Ok(Some((size, hash)).filter(|_| payload.is_audio()))
+		})?;
+
+		if let Some((size, hash)) = audio
+		{
+			return Ok((size, hash, true));
+		}
+	}
+
+	archive::with_reader(source, manifest::hash_reader).map(|(size, hash)| (size, hash, false))
+}
+
+// true if both readers give the same bytes - compared piece by piece
+fn same_stream(a: &mut dyn Read, b: &mut dyn Read) -> std::io::Result<bool>
+{
+	let mut buf_a = vec![0u8; CHUNK_SIZE];
+	let mut buf_b = vec![0u8; CHUNK_SIZE];
+
+	loop
+	{
+		let read = fill(a, &mut buf_a)?;
+
+		if read != fill(b, &mut buf_b)? || buf_a[..read] != buf_b[..read]
+		{
+			return Ok(false);
+		}
+
+		if read == 0
+		{
+			return Ok(true);
+		}
+	}
+}
+
+// true if both sources have the same content compared by the mode (the audio-payload if both are RIFF/WAVE files with a data-chunk)
+fn same_payload(a: &Path, b: &Path, audio: bool) -> std::io::Result<bool>
+{
+	archive::with_reader(a, |ra| archive::with_reader(b, |rb| if audio
+	{
+		same_stream(&mut AudioPayload::new(ra), &mut AudioPayload::new(rb))
+	}
+	else
+	{
+		same_stream(ra, rb)
+	}))
+}
+
+/// Hashes the content of every sample (using up to --jobs workers) and clusters the samples with identical content.
+/// Samples with the same hash are compared in full, so a hash-collision never makes two samples duplicates.
+/// Contents are streamed, so only a few pieces of them are held in memory at once.
+pub fn find(kits: &HashMap<String, Kit>, rcon: &RuntimeConfig) -> Vec<Cluster>
+{
+	let mut samples: Vec<(&str, &Path, &Path)> = kits.values().flat_map(|k| k.samples.iter().map(move |s| (k.name.as_str(), s.target_path.as_path(), s.source_path.as_path()))).collect();
+
+	samples.sort();
+	info!("Hashing {:?} samples ({})", samples.len(), rcon.dedup.name());
+
+	let keys = parallel::map(&samples, rcon.workers(), |(_, _, source)| digest(source, rcon.dedup));
+	let mut by_key: BTreeMap<(u64, String, bool), Vec<usize>> = BTreeMap::new();
+
+	for (i, key) in keys.into_iter().enumerate()
+	{
+		match key
+		{
+			Ok(k) => by_key.entry(k).or_default().push(i),
+			Err(e) => warn!("Not deduplicating {:?} - {}", samples[i].2, e)
+		}
+	}
+
+	let mut out: Vec<Cluster> = vec![];
+
+	for ((size, _, audio), members) in by_key.into_iter().filter(|(_, m)| m.len() > 1)
+	{
+		// members with the same content as the first one not placed yet form a cluster
+		let mut left: Vec<usize> = members;
+
+		while left.len() > 1
+		{
+			let first = left.remove(0);
+			let (same, rest): (Vec<usize>, Vec<usize>) = left.into_iter().partition(|i| match same_payload(samples[first].2, samples[*i].2, audio)
+			{
+				Ok(same) => same,
+				Err(e) => { warn!("Not deduplicating {:?} - {}", samples[*i].2, e); false }
+			});
+
+			left = rest;
+
+			if !same.is_empty()
+			{
+				let samples = std::iter::once(first).chain(same).map(|i| (samples[i].0.to_string(), samples[i].1.to_path_buf())).collect();
+
+				out.push(Cluster { size, samples });
+			}
+		}
+	}
+
+	out.sort_by(|a, b| a.samples[0].cmp(&b.samples[0]));
+	out
+}
+
+/// Finds the duplicates of the kits and applies the policy: duplicates inside the same kit are dropped,
+/// or all duplicates are recorded to be linked to the first sample of their cluster by the write phase.
+pub fn apply(kits: &mut HashMap<String, Kit>, rcon: &RuntimeConfig) -> DedupReport
+{
+	let clusters = find(kits, rcon);
+	let mut report = DedupReport { mode: rcon.dedup, clusters: vec![], dropped: 0, links: HashMap::new() };
+
+	match rcon.on_duplicate
+	{
+		DuplicatePolicy::Report => {},
+		DuplicatePolicy::Link =>
+		{
+			for cluster in clusters.iter()
+			{
+				for (_, target) in cluster.samples.iter().skip(1)
+				{
+					report.links.insert(target.to_path_buf(), cluster.samples[0].1.to_path_buf());
+				}
+			}
+		},
+		DuplicatePolicy::Drop =>
+		{
+			let mut dropped: HashSet<&Path> = HashSet::new();
+
+			for cluster in clusters.iter()
+			{
+				let mut kept: HashSet<&str> = HashSet::new();
+
+				for (kit, target) in cluster.samples.iter()
+				{
+					if !kept.insert(kit)
+					{
+						info!("Dropping {:?} - duplicate in kit {:?}", target, kit);
+						dropped.insert(target);
+					}
+				}
+			}
+
+			for kit in kits.values_mut()
+			{
+				kit.samples.retain(|s| !dropped.contains(s.target_path.as_path()));
+			}
+
+			report.dropped = dropped.len();
+		}
+	}
+
+	report.clusters = clusters;
+	report
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// RIFF/WAVE file out of the chunks - odd chunks get their pad-byte
+	fn wav(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8>
+	{
+		let mut body: Vec<u8> = b"WAVE".to_vec();
+
+		for (id, content) in chunks
+		{
+			body.extend_from_slice(*id);
+			body.extend_from_slice(&(content.len() as u32).to_le_bytes());
+			body.extend_from_slice(content);
+
+			if content.len() % 2 == 1
+			{
+				body.push(0);
+			}
+		}
+
+		let mut out: Vec<u8> = b"RIFF".to_vec();
+
+		out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+		out.extend_from_slice(&body);
+		out
+	}
+
+	fn payload(bytes: &[u8]) -> (Vec<u8>, bool)
+	{
+		let mut reader = AudioPayload::new(bytes);
+		let mut out: Vec<u8> = vec![];
+
+		reader.read_to_end(&mut out).unwrap();
+		(out, reader.is_audio())
+	}
+
+	// reads the payload one byte at a time
+	fn payload_bytewise(bytes: &[u8]) -> Vec<u8>
+	{
+		let mut reader = AudioPayload::new(bytes);
+		let mut out: Vec<u8> = vec![];
+		let mut byte = [0u8; 1];
+
+		while reader.read(&mut byte).unwrap() == 1
+		{
+			out.push(byte[0]);
+		}
+
+		out
+	}
+
+	const FMT: &[u8] = &[1, 0, 2, 0, 0x44, 0xac, 0, 0, 0x10, 0xb1, 2, 0, 4, 0, 16, 0];
+	const DATA: &[u8] = &[1, 2, 3, 4, 5, 6, 7, 8];
+
+	#[test]
+	fn metadata_chunks_are_ignored()
+	{
+		let plain = payload(&wav(&[(b"fmt ", FMT), (b"data", DATA)]));
+		let tagged = payload(&wav(&[(b"bext", &[9; 32]), (b"fmt ", FMT), (b"LIST", b"INFOodd"), (b"data", DATA), (b"id3 ", &[7; 5])]));
+
+		assert!(plain.1);
+		assert_eq!(plain, tagged);
+	}
+
+	#[test]
+	fn payload_holds_id_content_and_length_of_fmt_and_data()
+	{
+		let (out, _) = payload(&wav(&[(b"fmt ", FMT), (b"data", DATA)]));
+		let mut expected: Vec<u8> = vec![];
+
+		expected.extend_from_slice(b"fmt ");
+		expected.extend_from_slice(FMT);
+		expected.extend_from_slice(&(FMT.len() as u64).to_le_bytes());
+		expected.extend_from_slice(b"data");
+		expected.extend_from_slice(DATA);
+		expected.extend_from_slice(&(DATA.len() as u64).to_le_bytes());
+
+		assert_eq!(out, expected);
+	}
+
+	#[test]
+	fn odd_chunks_are_padded()
+	{
+		let odd = &DATA[..7];
+
+		assert_eq!(payload(&wav(&[(b"fmt ", FMT), (b"data", odd), (b"LIST", b"INFO")])), payload(&wav(&[(b"fmt ", FMT), (b"data", odd)])));
+	}
+
+	#[test]
+	fn different_audio_differs()
+	{
+		assert_ne!(payload(&wav(&[(b"fmt ", FMT), (b"data", DATA)])), payload(&wav(&[(b"fmt ", FMT), (b"data", &DATA[..6])])));
+		assert_ne!(payload(&wav(&[(b"fmt ", FMT), (b"data", DATA)])), payload(&wav(&[(b"fmt ", &[0; 16]), (b"data", DATA)])));
+	}
+
+	#[test]
+	fn truncated_files_end_their_chunk()
+	{
+		let full = wav(&[(b"fmt ", FMT), (b"data", DATA)]);
+		let truncated = &full[..full.len() - 3];
+		let (out, audio) = payload(truncated);
+
+		assert!(audio);
+		assert_ne!(payload(&full).0, out);
+		assert!(out.ends_with(&((DATA.len() - 3) as u64).to_le_bytes()));
+	}
+
+	#[test]
+	fn small_reads_give_the_same_payload()
+	{
+		let file = wav(&[(b"bext", &[9; 3]), (b"fmt ", FMT), (b"data", DATA)]);
+
+		assert_eq!(payload_bytewise(&file), payload(&file).0);
+	}
+
+	#[test]
+	fn other_files_are_no_audio()
+	{
+		assert_eq!(payload(b"not a wav-file at all"), (vec![], false));
+		assert_eq!(payload(b"RIFF"), (vec![], false));
+		assert!(!payload(&wav(&[(b"fmt ", FMT)])).1);
+	}
+
+	#[test]
+	fn streams_are_compared_by_content()
+	{
+		let long: Vec<u8> = (0..CHUNK_SIZE * 2 + 17).map(|i| i as u8).collect();
+		let mut changed = long.clone();
+		changed[CHUNK_SIZE + 3] ^= 1;
+
+		assert!(same_stream(&mut long.as_slice(), &mut long.as_slice()).unwrap());
+		assert!(!same_stream(&mut long.as_slice(), &mut changed.as_slice()).unwrap());
+		assert!(!same_stream(&mut long.as_slice(), &mut &long[..long.len() - 1]).unwrap());
+	}
+}
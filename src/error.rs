@@ -0,0 +1,94 @@
+extern crate regex;
+
+use std::path::PathBuf;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub enum Error
+{
+	Regex { rule: String, source: regex::Error },
+	Ini { file: String, source: ini::ini::Error },
+	MissingSection { file: String, section: String },
+	MissingKey { file: String, key: String },
+	UnknownIndexGroup { file: String, group: String },
+	UnknownGroup { group: String },
+	InvalidGroupName { group: String },
+	NoGroupsInInput { input: String },
+	Io { path: PathBuf, source: std::io::Error },
+	InvalidArgument { argument: String, reason: String },
+	Plan { file: String, reason: String },
+	RulesetMismatch { plan: String, ruleset: String },
+	MissingSources { count: usize },
+	OutsideOutput { count: usize, output: String },
+	Collisions { count: usize },
+	NoSpace { path: PathBuf, needed: u64, available: u64 },
+	Manifest { file: String, reason: String },
+	Journal { file: String, reason: String },
+	Progress { file: String, reason: String }
+}
+
+impl std::fmt::Display for Error
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		match self
+		{
+			Error::Regex { rule, source } => write!(f, "Failed to compile rule {:?}: {}", rule, source),
+			Error::Ini { file, source } => write!(f, "Failed to read ruleset {:?}: {}", file, source),
+			Error::MissingSection { file, section } => write!(f, "Missing {} section in {:?}", section, file),
+			Error::MissingKey { file, key } => write!(f, "Missing {} configuration in {:?}", key, file),
+			Error::UnknownIndexGroup { file, group } => write!(f, "Index-group {:?} does not exist in groups section of {:?}", group, file),
+			Error::UnknownGroup { group } => write!(f, "Group {:?} is not defined in groups section", group),
+			Error::InvalidGroupName { group } => write!(f, "Group-name {:?} is invalid - use letters, digits and single underscores only", group),
+			Error::NoGroupsInInput { input } => write!(f, "Input string {:?} does not contain any matching groups", input),
+			Error::Io { path, source } => write!(f, "Failed to access {:?}: {}", path, source),
+			Error::InvalidArgument { argument, reason } => write!(f, "Invalid argument {:?}: {}", argument, reason),
+			Error::Plan { file, reason } => write!(f, "Invalid plan {:?}: {}", file, reason),
+			Error::RulesetMismatch { plan, ruleset } => write!(f, "Plan was created by ruleset {} but the current ruleset is {} - use --force to apply it anyway", plan, ruleset),
+			Error::MissingSources { count } => write!(f, "{} source(s) of the plan do not exist anymore", count),
+			Error::OutsideOutput { count, output } => write!(f, "{} target(s) of the plan lie outside of it's output-directory {:?} - nothing was written", count, output),
+			Error::Collisions { count } => write!(f, "{} sample(s) share their target-path with another sample - nothing was written", count),
+			Error::NoSpace { path, needed, available } => write!(f, "Not enough free space in {:?}: {} bytes needed for the copies, {} bytes available - nothing was written", path, needed, available),
+			Error::Manifest { file, reason } => write!(f, "Invalid manifest {:?}: {}", file, reason),
+			Error::Journal { file, reason } => write!(f, "Invalid journal {:?}: {}", file, reason),
+			Error::Progress { file, reason } => write!(f, "Invalid progress-record {:?}: {}", file, reason)
+		}
+	}
+}
+
+impl std::fmt::Debug for Error
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		write!(f, "{{ [ERROR] {} }}", self)
+	}
+}
+
+impl std::error::Error for Error
+{
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)>
+	{
+		match self
+		{
+			Error::Regex { source, .. } => Some(source),
+			Error::Ini { source, .. } => Some(source),
+			Error::Io { source, .. } => Some(source),
+			_ => None
+		}
+	}
+}
+
+pub fn regex(rule: &str, source: regex::Error) -> Error
+{
+	Error::Regex { rule: rule.to_string(), source }
+}
+
+pub fn io<P: Into<PathBuf>>(path: P, source: std::io::Error) -> Error
+{
+	Error::Io { path: path.into(), source }
+}
+
+pub fn invalid_argument(argument: &str, reason: &str) -> Error
+{
+	Error::InvalidArgument { argument: argument.to_string(), reason: reason.to_string() }
+}
@@ -0,0 +1,140 @@
+use std::collections::*;
+use std::path::Path;
+use crate::runtimeconfig::RuntimeConfig;
+use crate::error::Result;
+use crate::catalog;
+	use crate::catalog::{Rearrangement, Sample};
+use crate::ruleset;
+	use crate::ruleset::Ruleset;
+
+/// Everything KitCat did with a single path - the path does not need to exist.
+pub struct Explanation
+{
+	pub path: String,
+	pub input_rule: String,
+	pub captures: Vec<(String, String)>, // capture name -> raw value, in order of the input-rule
+	pub rearrangements: Vec<Rearrangement>,
+	pub sample: Option<Sample>,
+	pub index: Option<String>,
+	pub reason: String // why the path was not turned into a sample (empty if it was)
+}
+
+impl std::fmt::Debug for Explanation
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [EXPLANATION] path: {:?} input_rule: {:?} captures: {:?} rearrangements: {:?} sample: {:?} index: {:?} reason: {:?} }}",
+    		self.path,
+    		self.input_rule,
+    		self.captures,
+    		self.rearrangements,
+    		self.sample,
+    		self.index,
+    		self.reason
+		)
+	}
+}
+
+impl std::fmt::Display for Explanation
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		writeln!(f, "Path:       {}", self.path)?;
+		writeln!(f, "Input-rule: {}", self.input_rule)?;
+
+		if self.captures.is_empty()
+		{
+			return writeln!(f, "Result:     {}", self.reason);
+		}
+
+		writeln!(f, "Captures:")?;
+
+		for (name, value) in self.captures.iter()
+		{
+			writeln!(f, "\t{} = {:?}", name, value)?;
+		}
+
+		writeln!(f, "Rearranges:")?;
+
+		if self.rearrangements.is_empty()
+		{
+			writeln!(f, "\t(none)")?;
+		}
+
+		for r in self.rearrangements.iter()
+		{
+			if r.fired
+			{
+				writeln!(f, "\t{} = {:?} matched recheck - {:?} -> {:?}", r.group, r.value, r.rule, r.result)?;
+			}
+			else
+			{
+				writeln!(f, "\t{} = {:?} did not match recheck - kept", r.group, r.value)?;
+			}
+		}
+
+		let sample = match &self.sample
+		{
+			None => return writeln!(f, "Result:     {}", self.reason),
+			Some(s) => s
+		};
+
+		let mut fields: Vec<(&String, &String)> = sample.fields.iter().collect();
+		fields.sort();
+
+		writeln!(f, "Fields:")?;
+
+		for (name, value) in fields
+		{
+			writeln!(f, "\t{} = {:?}", name, value)?;
+		}
+
+		writeln!(f, "Index:      {}", self.index.as_deref().unwrap_or(""))?;
+		writeln!(f, "Source:     {}", sample.source_path.display())?;
+		writeln!(f, "Target:     {}", sample.target_path.display())
+	}
+}
+
+/// Runs a single path through the input-rule, the rearranges and the output-path of the given ruleset.
+/// Paths starting with the input-directory are treated as relative to it.
+pub fn explain(path: &str, ruleset: &Ruleset, rcon: &RuntimeConfig) -> Result<Explanation>
+{
+	let rules = ruleset::compile(ruleset)?;
+	let relative = Path::new(path).strip_prefix(&rcon.input).unwrap_or(Path::new(path));
+	let matched = catalog::match_string(relative, rcon);
+
+	let mut out = Explanation {
+		path: matched.to_string(),
+		input_rule: rules.input.to_string(),
+		captures: vec![],
+		rearrangements: vec![],
+		sample: None,
+		index: None,
+		reason: String::new()
+	};
+
+	let cap = match rules.input.captures(&matched)
+	{
+		None => { out.reason = "does not match the input-rule".to_string(); return Ok(out) },
+		Some(c) => c
+	};
+
+	for name in rules.input.capture_names().flatten()
+	{
+		out.captures.push((name.to_string(), cap.name(name).map_or("", |m| m.as_str()).to_string()));
+	}
+
+	let mut fields: HashMap<String, String> = match ruleset::map_capture(&cap, &ruleset.input_groups)
+	{
+		None => { out.reason = "repeated groups captured different values".to_string(); return Ok(out) },
+		Some(m) => m
+	};
+
+	out.rearrangements = catalog::rearrange(&mut fields, ruleset, &rules.recheck)?;
+	catalog::apply_namespace(&mut fields, ruleset, rcon);
+
+	out.index = fields.get(&ruleset.index).cloned();
+	out.sample = Some(catalog::create_sample(relative, fields, rcon, ruleset));
+
+	Ok(out)
+}
@@ -0,0 +1,547 @@
+extern crate regex;
+	use regex::Regex;
+
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::runtimeconfig::RuntimeConfig;
+use crate::error;
+	use crate::error::Result;
+use crate::archive;
+
+/// Name of the gitignore-style files read in every walked directory - their rules apply to the directory and below.
+pub const IGNORE_FILE: &str = ".kitcatignore";
+
+// debris of file-managers, DAWs and KitCat itself - excluded unless --no-default-excludes
+const DEFAULT_EXCLUDES: [&str; 10] = [
+	".DS_Store",
+	"._*", // AppleDouble resource-forks
+	"__MACOSX/",
+	".Spotlight-V100/",
+	".Trashes/",
+	"Thumbs.db",
+	"desktop.ini",
+	"*.asd", // Ableton analysis-files
+	IGNORE_FILE,
+	".kitcat/" // state-directory of an output-directory inside the input-directory
+];
+
+/// Single gitignore-style pattern, compiled to a regex over the path relative to the walked root (directories separated by /).
+#[derive(Clone)]
+struct Rule
+{
+	regex: Regex,
+	negated: bool, // !pattern - re-includes what an earlier rule excluded
+	dir_only: bool // pattern/ - only matches directories
+}
+
+impl Rule
+{
+	// base is the directory (relative to the root) of the ignore-file the pattern was read from
+	fn parse(pattern: &str, base: &str) -> Option<std::result::Result<Rule, regex::Error>>
+	{
+		let pattern = pattern.trim_end();
+
+		if pattern.is_empty() || pattern.starts_with('#')
+		{
+			return None;
+		}
+
+		let (negated, pattern) = match pattern.strip_prefix('!')
+		{
+			Some(p) => (true, p),
+			None => (false, pattern)
+		};
+
+		let (dir_only, pattern) = match pattern.strip_suffix('/')
+		{
+			Some(p) => (true, p),
+			None => (false, pattern)
+		};
+
+		// patterns without a separator match at any depth, others relative to the ignore-file
+		let anchored = pattern.contains('/');
+		let mut source = String::from("^");
+
+		if !base.is_empty()
+		{
+			source.push_str(&regex::escape(base));
+			source.push('/');
+		}
+
+		if !anchored
+		{
+			source.push_str("(?:.*/)?");
+		}
+
+		source.push_str(&glob_to_regex(pattern.trim_start_matches('/')));
+		source.push('$');
+
+		Some(Regex::new(&source).map(|regex| Rule { regex, negated, dir_only }))
+	}
+
+	fn matches(&self, relative: &str, is_dir: bool) -> bool
+	{
+		(is_dir || !self.dir_only) && self.regex.is_match(relative)
+	}
+}
+
+// * and ? stay inside a directory, ** spans directories, [...] is a character-class (negated by a leading !)
+fn glob_to_regex(glob: &str) -> String
+{
+	let chars: Vec<char> = glob.chars().collect();
+	let mut out = String::new();
+	let mut i = 0;
+
+	while i < chars.len()
+	{
+		match chars[i]
+		{
+			'*' if chars.get(i + 1) == Some(&'*') =>
+			{
+				if chars.get(i + 2) == Some(&'/')
+				{
+					out.push_str("(?:.*/)?");
+					i+=3;
+				}
+				else
+				{
+					out.push_str(".*");
+					i+=2;
+				}
+
+				continue;
+			},
+			'*' => out.push_str("[^/]*"),
+			'?' => out.push_str("[^/]"),
+			'\\' if i + 1 < chars.len() =>
+			{
+				out.push_str(&regex::escape(&chars[i + 1].to_string()));
+				i+=1;
+			},
+			'[' if chars[i + 1..].contains(&']') =>
+			{
+				let close = i + 1 + chars[i + 1..].iter().position(|c| *c == ']').unwrap_or(0);
+				let mut class: &[char] = &chars[i + 1..close];
+
+				out.push('[');
+
+				if let Some(('!', rest)) = class.split_first()
+				{
+					out.push('^');
+					class = rest;
+				}
+
+				for c in class
+				{
+					if *c == '-' { out.push('-') } else { out.push_str(&regex::escape(&c.to_string())) }
+				}
+
+				out.push(']');
+				i = close;
+			},
+			c => out.push_str(&regex::escape(&c.to_string()))
+		}
+
+		i+=1;
+	}
+
+	out
+}
+
+// the last matching rule decides - later rules (deeper ignore-files) override earlier ones
+fn is_excluded(rules: &[Rule], relative: &str, is_dir: bool) -> bool
+{
+	rules.iter().rev().find(|r| r.matches(relative, is_dir)).is_some_and(|r| !r.negated)
+}
+
+#[cfg(target_family = "unix")]
+type DirKey = (u64, u64);
+
+#[cfg(target_family = "unix")]
+fn dir_key(_path: &Path, meta: &Metadata) -> DirKey
+{
+	use std::os::unix::fs::MetadataExt;
+
+	(meta.dev(), meta.ino())
+}
+
+#[cfg(not(target_family = "unix"))]
+type DirKey = PathBuf;
+
+#[cfg(not(target_family = "unix"))]
+fn dir_key(path: &Path, _meta: &Metadata) -> DirKey
+{
+	path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// A directory to walk, together with the rules in effect inside of it.
+pub struct Dir
+{
+	pub path: PathBuf,
+	relative: String, // path relative to the root, directories separated by /
+	depth: usize, // 0 for the root
+	rules: Arc<Vec<Rule>>, // rules of the walk and the ignore-files of all parents
+	ancestors: Vec<DirKey> // the directory and all it's parents - only known if links are followed
+}
+
+/// Entry of a directory as seen by the walk.
+pub enum Entry
+{
+	Dir(Dir),
+	File(PathBuf),
+	Excluded(PathBuf), // excluded by a rule or --max-depth
+	Skipped(PathBuf, String) // link-loop or invalid ignore-file - with the reason
+}
+
+/// Decides which entries of the input-directory are walked: default excludes, .kitcatignore files, --exclude/--include globs,
+/// --max-depth and whether links are followed.
+pub struct WalkFilter
+{
+	rules: Vec<Rule>, // default excludes and --exclude
+	includes: Vec<Rule>, // --include - files must match one of them (if any)
+	max_depth: usize, // 0 = unlimited
+	follow_links: bool,
+	archives: bool // walk archives like directories
+}
+
+impl WalkFilter
+{
+	pub fn from_config(rcon: &RuntimeConfig) -> Result<WalkFilter>
+	{
+		let compile = |option: &str, patterns: &[&str]| -> Result<Vec<Rule>>
+		{
+			let mut out: Vec<Rule> = vec![];
+
+			for pattern in patterns
+			{
+				match Rule::parse(pattern, "")
+				{
+					None => {},
+					Some(Err(e)) => return Err(error::invalid_argument(option, &format!("{:?} is not a valid pattern ({})", pattern, e))),
+					Some(Ok(r)) => out.push(r)
+				}
+			}
+
+			Ok(out)
+		};
+
+		let excludes: Vec<&str> = rcon.excludes.iter().map(|e| e.as_str()).collect();
+		let includes: Vec<&str> = rcon.includes.iter().map(|i| i.as_str()).collect();
+		let mut rules = if rcon.default_excludes { compile("", &DEFAULT_EXCLUDES)? } else { vec![] };
+
+		rules.append(&mut compile("--exclude", &excludes)?);
+
+		Ok(WalkFilter {
+			rules,
+			includes: compile("--include", &includes)?,
+			max_depth: rcon.max_depth,
+			follow_links: rcon.follow_links,
+			archives: rcon.archives
+		})
+	}
+
+	/// The root-directory of a walk.
+	pub fn root(&self, path: &Path) -> Dir
+	{
+		let ancestors = match path.metadata()
+		{
+			Ok(meta) if self.follow_links => vec![dir_key(path, &meta)],
+			_ => vec![]
+		};
+
+		Dir { path: path.to_path_buf(), relative: String::new(), depth: 0, rules: Arc::new(self.rules.to_vec()), ancestors }
+	}
+
+	fn too_deep(&self, depth: usize) -> bool
+	{
+		self.max_depth > 0 && depth > self.max_depth
+	}
+
+	fn is_included(&self, relative: &str) -> bool
+	{
+		self.includes.is_empty() || self.includes.iter().any(|r| r.matches(relative, false))
+	}
+
+	/// True if a file given by a file-list (path relative to it's root) passes the excludes and includes
+	/// (ignore-files and --max-depth only apply to walks).
+	pub fn is_listed(&self, relative: &Path) -> bool
+	{
+		let components: Vec<String> = relative.iter().map(|c| c.to_string_lossy().to_string()).collect();
+
+		self.passes(&self.rules, "", &components)
+	}
+
+	// checks every parent-directory of the file below base (relative to the root) against the rules, then the file itself
+	fn passes(&self, rules: &[Rule], base: &str, components: &[String]) -> bool
+	{
+		let mut relative = base.to_string();
+
+		for (i, component) in components.iter().enumerate()
+		{
+			relative = if relative.is_empty() { component.to_string() } else { [&relative, "/", component].concat() };
+
+			if is_excluded(rules, &relative, i + 1 < components.len())
+			{
+				return false;
+			}
+		}
+
+		self.is_included(&relative)
+	}
+
+	// members of an archive below the directory - excluded members (e.g. inside __MACOSX/) and those too deep are left out
+	fn read_archive(&self, dir: &Dir, rules: &[Rule], path: PathBuf, relative: &str, out: &mut Vec<Entry>)
+	{
+		let members = match archive::members(&path)
+		{
+			Err(e) => { out.push(Entry::Skipped(path, format!("archive can't be read ({})", e))); return },
+			Ok(m) => m
+		};
+
+		for member in members
+		{
+			let components: Vec<String> = member.iter().map(|c| c.to_string_lossy().to_string()).collect();
+			let file = path.join(&member);
+
+			if self.too_deep(dir.depth + 1 + components.len()) || !self.passes(rules, relative, &components)
+			{
+				out.push(Entry::Excluded(file));
+				continue;
+			}
+
+			out.push(Entry::File(file));
+		}
+	}
+
+	/// Entries of the directory in order of their names: sub-directories to walk, regular files and what was left out.
+	/// Links are only followed if configured - links to a directory containing them are skipped as loops.
+	pub fn read(&self, dir: &Dir) -> std::io::Result<Vec<Entry>>
+	{
+		let mut entries: Vec<(String, PathBuf, std::fs::FileType)> = vec![];
+
+		for entry in std::fs::read_dir(&dir.path)?.filter_map(|e| e.ok())
+		{
+			if let Ok(file_type) = entry.file_type()
+			{
+				entries.push((entry.file_name().to_string_lossy().to_string(), entry.path(), file_type));
+			}
+		}
+
+		entries.sort_by(|a, b| a.1.cmp(&b.1));
+
+		let mut out: Vec<Entry> = vec![];
+		let mut rules = Arc::clone(&dir.rules);
+
+		if entries.iter().any(|(name, _, t)| name == IGNORE_FILE && t.is_file())
+		{
+			let file = dir.path.join(IGNORE_FILE);
+
+			match std::fs::read_to_string(&file)
+			{
+				Err(e) => out.push(Entry::Skipped(file, e.to_string())),
+				Ok(content) =>
+				{
+					let mut own: Vec<Rule> = dir.rules.to_vec();
+
+					for line in content.lines()
+					{
+						match Rule::parse(line, &dir.relative)
+						{
+							None => {},
+							Some(Err(e)) => out.push(Entry::Skipped(file.to_path_buf(), format!("pattern {:?} is invalid ({})", line, e))),
+							Some(Ok(r)) => own.push(r)
+						}
+					}
+
+					rules = Arc::new(own);
+				}
+			}
+		}
+
+		for (name, path, mut file_type) in entries
+		{
+			let relative = if dir.relative.is_empty() { name } else { [&dir.relative, "/", &name].concat() };
+			let mut meta: Option<Metadata> = None;
+
+			if file_type.is_symlink()
+			{
+				if !self.follow_links
+				{
+					continue;
+				}
+
+				match path.metadata()
+				{
+					Err(_) => continue, // broken link
+					Ok(m) => { file_type = m.file_type(); meta = Some(m) }
+				}
+			}
+
+			if file_type.is_dir()
+			{
+				// files inside the directory are one level deeper than the directory itself
+				if self.too_deep(dir.depth + 2) || is_excluded(&rules, &relative, true)
+				{
+					out.push(Entry::Excluded(path));
+					continue;
+				}
+
+				let mut ancestors: Vec<DirKey> = vec![];
+
+				if self.follow_links
+				{
+					let key = match meta.map_or_else(|| path.metadata(), Ok)
+					{
+						Err(_) => continue,
+						Ok(m) => dir_key(&path, &m)
+					};
+
+					if dir.ancestors.contains(&key)
+					{
+						out.push(Entry::Skipped(path, "link to a directory containing it".to_string()));
+						continue;
+					}
+
+					ancestors = dir.ancestors.to_vec();
+					ancestors.push(key);
+				}
+
+				out.push(Entry::Dir(Dir { path, relative, depth: dir.depth + 1, rules: Arc::clone(&rules), ancestors }));
+			}
+			else if file_type.is_file() && self.archives && archive::is_archive(&path)
+			{
+				// archives are walked like a directory
+				if self.too_deep(dir.depth + 2) || is_excluded(&rules, &relative, true)
+				{
+					out.push(Entry::Excluded(path));
+					continue;
+				}
+
+				self.read_archive(dir, &rules, path, &relative, &mut out);
+			}
+			else if file_type.is_file()
+			{
+				if self.too_deep(dir.depth + 1) || is_excluded(&rules, &relative, false) || !self.is_included(&relative)
+				{
+					out.push(Entry::Excluded(path));
+					continue;
+				}
+
+				out.push(Entry::File(path));
+			}
+		}
+
+		Ok(out)
+	}
+}
+
+impl std::fmt::Debug for WalkFilter
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [WALKFILTER] rules: {:?} includes: {:?} max_depth: {:?} follow_links: {:?} archives: {:?} }}",
+    		self.rules.iter().map(|r| r.regex.as_str()).collect::<Vec<&str>>(),
+    		self.includes.iter().map(|r| r.regex.as_str()).collect::<Vec<&str>>(),
+    		self.max_depth,
+    		self.follow_links,
+    		self.archives
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn rule(pattern: &str, base: &str) -> Rule
+	{
+		Rule::parse(pattern, base).unwrap().unwrap()
+	}
+
+	fn glob_matches(glob: &str, path: &str) -> bool
+	{
+		Regex::new(&format!("^{}$", glob_to_regex(glob))).unwrap().is_match(path)
+	}
+
+	#[test]
+	fn stars_stay_inside_a_directory()
+	{
+		assert!(glob_matches("*.wav", "Kick.wav"));
+		assert!(!glob_matches("*.wav", "Drums/Kick.wav"));
+		assert!(glob_matches("Kick?.wav", "Kick1.wav"));
+		assert!(!glob_matches("Kick?.wav", "Kick/.wav"));
+	}
+
+	#[test]
+	fn double_stars_span_directories()
+	{
+		assert!(glob_matches("**/old/*", "old/a.wav"));
+		assert!(glob_matches("**/old/*", "Drums/Kit/old/a.wav"));
+		assert!(glob_matches("Drums/**", "Drums/Kit/a.wav"));
+		assert!(!glob_matches("**/old/*", "Drums/old/sub/a.wav"));
+	}
+
+	#[test]
+	fn classes_escapes_and_regex_characters()
+	{
+		assert!(glob_matches("Kick[0-9].wav", "Kick7.wav"));
+		assert!(!glob_matches("Kick[!0-9].wav", "Kick7.wav"));
+		assert!(glob_matches("Kick[!0-9].wav", "KickA.wav"));
+		assert!(glob_matches(r"\*.wav", "*.wav"));
+		assert!(!glob_matches(r"\*.wav", "a.wav"));
+		assert!(glob_matches("(a)+.wav", "(a)+.wav"));
+		assert!(glob_matches("[a", "[a"));
+	}
+
+	#[test]
+	fn comments_and_blank_lines_are_no_rules()
+	{
+		assert!(Rule::parse("", "").is_none());
+		assert!(Rule::parse("   ", "").is_none());
+		assert!(Rule::parse("# comment", "").is_none());
+	}
+
+	#[test]
+	fn patterns_without_separator_match_at_any_depth()
+	{
+		let any = rule("*.asd", "");
+		let anchored = rule("/Loops/*.wav", "");
+
+		assert!(any.matches("a.asd", false));
+		assert!(any.matches("Drums/Kit/a.asd", false));
+		assert!(anchored.matches("Loops/a.wav", false));
+		assert!(!anchored.matches("Drums/Loops/a.wav", false));
+	}
+
+	#[test]
+	fn rules_of_ignore_files_are_relative_to_their_directory()
+	{
+		let nested = rule("old/*.wav", "Drums");
+
+		assert!(nested.matches("Drums/old/a.wav", false));
+		assert!(!nested.matches("old/a.wav", false));
+	}
+
+	#[test]
+	fn trailing_slash_only_matches_directories()
+	{
+		let dirs = rule("Loops/", "");
+
+		assert!(dirs.dir_only);
+		assert!(dirs.matches("Drums/Loops", true));
+		assert!(!dirs.matches("Drums/Loops", false));
+	}
+
+	#[test]
+	fn last_matching_rule_decides()
+	{
+		let rules = vec![rule("*.wav", ""), rule("!Keep*.wav", "")];
+
+		assert!(rules[1].negated);
+		assert!(is_excluded(&rules, "Kick.wav", false));
+		assert!(!is_excluded(&rules, "Keep.wav", false));
+		assert!(!is_excluded(&rules, "Kick.aif", false));
+	}
+}
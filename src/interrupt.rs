@@ -0,0 +1,35 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static INSTALL: Once = Once::new();
+
+/// Turns SIGINT (Ctrl-C) and SIGTERM into a request to stop: samples being written are finished, no new ones are started,
+/// and the run is saved as usual. A second signal terminates at once. Installed once, by the first write.
+pub fn install()
+{
+	INSTALL.call_once(||
+	{
+		#[cfg(target_os = "linux")]
+		for signal in [libc::SIGINT, libc::SIGTERM]
+		{
+			// SAFETY: the handler only touches an atomic and calls signal, both async-signal-safe
+			unsafe { libc::signal(signal, on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+		}
+	});
+}
+
+#[cfg(target_os = "linux")]
+extern "C" fn on_signal(signal: libc::c_int)
+{
+	REQUESTED.store(true, Ordering::SeqCst);
+
+	// SAFETY: restores the default action, so the next signal terminates
+	unsafe { libc::signal(signal, libc::SIG_DFL) };
+}
+
+/// True once a stop was requested.
+pub fn requested() -> bool
+{
+	REQUESTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,547 @@
+extern crate log;
+	use log::*;
+
+extern crate serde;
+	use serde::{Deserialize, Serialize};
+
+use std::collections::*;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::version;
+use crate::error;
+	use crate::error::{Error, Result};
+use crate::manifest;
+	use crate::manifest::Manifest;
+use crate::writer;
+
+// every run gets it's own directory (named by it's id) inside the state-directory
+const RUNS_DIR: &str = "runs";
+const JOURNAL_FILE: &str = "journal.json";
+const BACKUP_DIR: &str = "backup";
+// copy of the manifest as it was before the run
+const MANIFEST_SNAPSHOT: &str = "manifest.json";
+
+/// Everything a single run changed inside the output-directory - in the order it was done.
+/// Replaced or removed files are kept in the backup-directory of the run, so the run can be undone.
+#[derive(Serialize, Deserialize)]
+pub struct Journal
+{
+	pub kitcat: String,
+	pub run: String,
+	pub output: String,
+	pub actions: Vec<Action>
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Action
+{
+	CreatedDir { path: String },
+	Created { path: String, size: u64, modified: u64 }, // size and mtime (seconds) of the created file
+	Removed { path: String, backup: String }, // backup-path is relative to the run-directory
+	RemovedDir { path: String }
+}
+
+impl std::fmt::Debug for Journal
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [JOURNAL] kitcat: {:?} run: {:?} output: {:?} actions: {:?} }}",
+    		self.kitcat,
+    		self.run,
+    		self.output,
+    		self.actions.len()
+		)
+	}
+}
+
+pub struct UndoReport
+{
+	pub run: String,
+	pub removed: usize, // created files and directories that were removed again
+	pub restored: usize, // replaced or removed files and directories that were restored
+	pub kept: usize // files that changed since the run (or block a restore) - left untouched
+}
+
+impl std::fmt::Debug for UndoReport
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [UNDOREPORT] run: {:?} removed: {:?} restored: {:?} kept: {:?} }}",
+    		self.run,
+    		self.removed,
+    		self.restored,
+    		self.kept
+		)
+	}
+}
+
+impl std::fmt::Display for UndoReport
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		write!(f, "Undid run {}: {} removed, {} restored, {} kept", self.run, self.removed, self.restored, self.kept)
+	}
+}
+
+fn runs_dir(output: &str) -> PathBuf
+{
+	manifest::state_dir(output).join(RUNS_DIR)
+}
+
+fn journal_error(file: &Path, reason: &str) -> Error
+{
+	Error::Journal { file: file.to_string_lossy().to_string(), reason: reason.to_string() }
+}
+
+/// Ids of all runs of the output-directory that can be undone - oldest first.
+pub fn runs(output: &str) -> Vec<String>
+{
+	let mut out: Vec<String> = match std::fs::read_dir(runs_dir(output))
+	{
+		Err(_) => vec![],
+		Ok(entries) => entries.filter_map(|e| e.ok())
+			.filter(|e| e.path().join(JOURNAL_FILE).exists())
+			.map(|e| e.file_name().to_string_lossy().to_string())
+			.collect()
+	};
+
+	out.sort_by(|a, b| run_order(a).cmp(&run_order(b)));
+	out
+}
+
+// runs started within the same second get a numeric suffix (STAMP-2, STAMP-3, ...) - compared as number, so STAMP-10 follows STAMP-9
+fn run_order(run: &str) -> (&str, u64)
+{
+	match run.rsplit_once('-').map(|(stamp, n)| (stamp, n.parse::<u64>()))
+	{
+		Some((stamp, Ok(n))) if stamp.contains('-') => (stamp, n),
+		_ => (run, 1)
+	}
+}
+
+// UTC date and time as YYYYMMDD-HHMMSS
+fn timestamp() -> String
+{
+	let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+	let (days, time) = (secs / 86400, secs % 86400);
+
+	// civil date out of days since 1970-01-01 (http://howardhinnant.github.io/date_algorithms.html)
+	let z = days as i64 + 719468;
+	let era = z.div_euclid(146097);
+	let doe = z - era * 146097;
+	let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+	let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+	let mp = (5 * doy + 2) / 153;
+	let day = doy - (153 * mp + 2) / 5 + 1;
+	let month = if mp < 10 { mp + 3 } else { mp - 9 };
+	let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+	format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+}
+
+impl Journal
+{
+	/// Starts the journal of a new run of the output-directory - nothing is written until it's saved.
+	pub fn new(output: &str) -> Journal
+	{
+		let taken = runs(output);
+		let stamp = timestamp();
+		let run = (1..).map(|i| if i == 1 { stamp.to_string() } else { format!("{}-{}", stamp, i) })
+			.find(|r| !taken.contains(r) && !runs_dir(output).join(r).exists())
+			.unwrap_or(stamp);
+
+		Journal { kitcat: version::APP_VERSION.to_string(), run, output: output.to_string(), actions: vec![] }
+	}
+
+	fn run_dir(&self) -> PathBuf
+	{
+		runs_dir(&self.output).join(&self.run)
+	}
+
+	pub fn created_dir(&mut self, path: &Path)
+	{
+		self.actions.push(Action::CreatedDir { path: path.to_string_lossy().to_string() });
+	}
+
+	pub fn created(&mut self, path: &Path)
+	{
+		let (size, modified) = manifest::stat(path).unwrap_or((0, 0));
+
+		self.actions.push(Action::Created { path: path.to_string_lossy().to_string(), size, modified });
+	}
+
+	pub fn removed_dir(&mut self, path: &Path)
+	{
+		self.actions.push(Action::RemovedDir { path: path.to_string_lossy().to_string() });
+	}
+
+	// free backup-path (relative to the run-directory) - backups of files not replaced yet are not journaled, so the count of actions may be taken
+	fn backup_path(&self) -> std::io::Result<PathBuf>
+	{
+		std::fs::create_dir_all(self.run_dir().join(BACKUP_DIR))?;
+
+		Ok((self.actions.len()..).map(|i| Path::new(BACKUP_DIR).join(i.to_string())).find(|b| self.run_dir().join(b).symlink_metadata().is_err()).unwrap_or_default())
+	}
+
+	/// Moves the file (or link) out of the way into the backup-directory of the run, instead of deleting it.
+	pub fn remove(&mut self, path: &Path) -> std::io::Result<()>
+	{
+		let backup = self.backup_path()?;
+		let backup_path = self.run_dir().join(&backup);
+
+		if std::fs::rename(path, &backup_path).is_err() // e.g. another device mounted inside the output-directory
+		{
+			copy_entry(path, &backup_path)?;
+			std::fs::remove_file(path)?;
+		}
+
+		debug!("Moved {:?} to {:?}", path, backup_path);
+		self.replaced(path, &backup);
+
+		Ok(())
+	}
+
+	/// Keeps the file (or link) in the backup-directory of the run (as hard-link or copy) but leaves it in place, so it can be replaced at once.
+	/// Returns the backup, which is journaled by replaced once the file was replaced - or deleted by discard if it was not.
+	pub fn backup(&self, path: &Path) -> std::io::Result<PathBuf>
+	{
+		let backup = self.backup_path()?;
+		let backup_path = self.run_dir().join(&backup);
+
+		if std::fs::hard_link(path, &backup_path).is_err()
+		{
+			copy_entry(path, &backup_path)?;
+		}
+
+		debug!("Backed up {:?} to {:?}", path, backup_path);
+
+		Ok(backup)
+	}
+
+	/// Records that the file was replaced (or removed) - the previous one is kept as the given backup.
+	pub fn replaced(&mut self, path: &Path, backup: &Path)
+	{
+		self.actions.push(Action::Removed { path: path.to_string_lossy().to_string(), backup: backup.to_string_lossy().to_string() });
+	}
+
+	/// Deletes the backup of a file that was not replaced after all.
+	pub fn discard(&self, backup: &Path)
+	{
+		let backup_path = self.run_dir().join(backup);
+
+		if let Err(e) = std::fs::remove_file(&backup_path)
+		{
+			warn!("Removing unused backup {:?} failed: {:?}", backup_path, e);
+		}
+	}
+
+	/// Writes the journal (and a snapshot of the current manifest) into the run-directory - must be called before the manifest is saved.
+	/// Runs that did not change anything are not recorded.
+	pub fn save(&self) -> Result<()>
+	{
+		if self.actions.is_empty()
+		{
+			return Ok(());
+		}
+
+		let dir = self.run_dir();
+		let path = dir.join(JOURNAL_FILE);
+		let manifest = manifest::manifest_path(&self.output);
+
+		std::fs::create_dir_all(&dir).map_err(|e| error::io(&dir, e))?;
+
+		if manifest.exists()
+		{
+			std::fs::copy(&manifest, dir.join(MANIFEST_SNAPSHOT)).map_err(|e| error::io(&manifest, e))?;
+		}
+
+		let content = serde_json::to_string_pretty(self).map_err(|e| journal_error(&path, &e.to_string()))?;
+
+		std::fs::write(&path, content).map_err(|e| error::io(&path, e))?;
+		info!("Saved journal of run {:?} with {:?} actions to {:?}", self.run, self.actions.len(), path);
+
+		Ok(())
+	}
+
+	pub fn load(output: &str, run: &str) -> Result<Journal>
+	{
+		let path = runs_dir(output).join(run).join(JOURNAL_FILE);
+
+		if !path.exists()
+		{
+			let known = runs(output);
+			let reason = if known.is_empty() { "there are no runs to undo".to_string() } else { format!("unknown run {:?} - known runs are {}", run, known.join(", ")) };
+
+			return Err(journal_error(&runs_dir(output), &reason));
+		}
+
+		let content = std::fs::read_to_string(&path).map_err(|e| error::io(&path, e))?;
+
+		serde_json::from_str(&content).map_err(|e| journal_error(&path, &e.to_string()))
+	}
+}
+
+// copies the file - or recreates the link, a copy of it's target would be restored as a regular file
+fn copy_entry(from: &Path, to: &Path) -> std::io::Result<()>
+{
+	if from.symlink_metadata()?.file_type().is_symlink()
+	{
+		return writer::link_soft(&std::fs::read_link(from)?, to);
+	}
+
+	std::fs::copy(from, to).map(|_| ())
+}
+
+/// Reverts the given run (or the last one) of the output-directory, newest action first:
+/// created files are removed (unless they changed since), created directories are removed if empty,
+/// removed/replaced files and directories are restored (unless their path is taken by now).
+/// The manifest is updated accordingly. The journal is deleted if everything was reverted.
+pub fn undo(output: &str, run: Option<&str>, dry: bool) -> Result<UndoReport>
+{
+	let run = match run
+	{
+		Some(r) => r.to_string(),
+		None => runs(output).pop().unwrap_or_default()
+	};
+
+	let journal = Journal::load(output, &run)?;
+	let dir = journal.run_dir();
+	let snapshot = Manifest::load_from(&dir.join(MANIFEST_SNAPSHOT))?;
+	let mut manifest = Manifest::load(output)?;
+	let mut report = UndoReport { run: run.to_string(), removed: 0, restored: 0, kept: 0 };
+	let mut vacated: HashSet<&str> = HashSet::new(); // paths that would be removed by a dry run
+
+	for action in journal.actions.iter().rev()
+	{
+		match action
+		{
+			Action::Created { path, size, modified } =>
+			{
+				match manifest::stat(Path::new(path))
+				{
+					None => { manifest.entries.remove(path); },
+					Some(s) if s == (*size, *modified) =>
+					{
+						info!("Removing {:?}", path);
+						report.removed+=1;
+
+						if dry
+						{
+							vacated.insert(path);
+						}
+						else
+						{
+							match std::fs::remove_file(path)
+							{
+								Err(e) => error!("Removing {:?} failed: {:?}", path, e),
+								Ok(_) => { manifest.entries.remove(path); }
+							}
+						}
+					},
+					Some(_) => { warn!("{:?} changed since run {} - keeping it", path, run); report.kept+=1; }
+				}
+			},
+			Action::CreatedDir { path } =>
+			{
+				if Path::new(path).is_dir() && std::fs::read_dir(path).is_ok_and(|mut d| d.next().is_none())
+				{
+					info!("Removing directory {:?}", path);
+					report.removed+=1;
+
+					if !dry
+					{
+						if let Err(e) = std::fs::remove_dir(path)
+						{
+							error!("Removing directory {:?} failed: {:?}", path, e);
+						}
+					}
+				}
+			},
+			Action::Removed { path, backup } =>
+			{
+				let backup_path = dir.join(backup);
+
+				if Path::new(path).symlink_metadata().is_ok() && !vacated.contains(path.as_str())
+				{
+					warn!("{:?} was taken since run {} - keeping the previous file at {:?}", path, run, backup_path);
+					report.kept+=1;
+					continue;
+				}
+
+				info!("Restoring {:?}", path);
+				report.restored+=1;
+
+				if dry
+				{
+					continue;
+				}
+
+				let restored = Path::new(path).parent().map_or(Ok(()), std::fs::create_dir_all).and_then(|_| std::fs::rename(&backup_path, path).or_else(|_| copy_entry(&backup_path, Path::new(path))));
+
+				match restored
+				{
+					Err(e) => { error!("Restoring {:?} from {:?} failed: {:?}", path, backup_path, e); report.kept+=1; },
+					Ok(_) =>
+					{
+						match snapshot.entries.get(path)
+						{
+							None => { manifest.entries.remove(path); },
+							Some(entry) => { manifest.entries.insert(path.to_string(), entry.clone()); }
+						}
+					}
+				}
+			},
+			Action::RemovedDir { path } =>
+			{
+				if Path::new(path).is_dir()
+				{
+					continue;
+				}
+
+				info!("Restoring directory {:?}", path);
+				report.restored+=1;
+
+				if !dry
+				{
+					if let Err(e) = std::fs::create_dir_all(path)
+					{
+						error!("Restoring directory {:?} failed: {:?}", path, e);
+					}
+				}
+			}
+		}
+	}
+
+	if dry
+	{
+		return Ok(report);
+	}
+
+	manifest.save(output)?;
+
+	if report.kept == 0
+	{
+		std::fs::remove_dir_all(&dir).map_err(|e| error::io(&dir, e))?;
+	}
+	else
+	{
+		warn!("Keeping journal and backups of run {} in {:?}", run, dir);
+	}
+
+	Ok(report)
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use crate::catalog::Sample;
+	use crate::writer::{CopyOptions, ExistingPolicy, SoftLinks, WriteMode, WriteOutcome};
+
+	// empty output-directory of a test below the temporary directory
+	fn output(name: &str) -> String
+	{
+		let dir = std::env::temp_dir().join(format!("kitcat-journal-{}-{}", name, std::process::id()));
+
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+		dir.to_string_lossy().to_string()
+	}
+
+	#[test]
+	fn runs_of_the_same_second_are_ordered_by_their_number()
+	{
+		let output = output("order");
+
+		for run in ["20260101-120000-10", "20260101-120000", "20260101-120000-2", "20260101-115959", "20260101-120000-9"]
+		{
+			std::fs::create_dir_all(runs_dir(&output).join(run)).unwrap();
+			std::fs::write(runs_dir(&output).join(run).join(JOURNAL_FILE), "{}").unwrap();
+		}
+
+		assert_eq!(runs(&output), ["20260101-115959", "20260101-120000", "20260101-120000-2", "20260101-120000-9", "20260101-120000-10"]);
+
+		let _ = std::fs::remove_dir_all(&output);
+	}
+
+	fn links() -> SoftLinks
+	{
+		let input = std::env::temp_dir().to_string_lossy().to_string();
+
+		SoftLinks::from_config(&crate::runtimeconfig::process_args(vec!["kitcat".to_string(), "-i".to_string(), input, "-o".to_string(), "/out".to_string()]).unwrap())
+	}
+
+	// copies the source over the target (replacing it) within a saved run and returns the run
+	fn overwrite(output: &str, source: &Path, target: &Path) -> String
+	{
+		let mut journal = Journal::new(output);
+		let mut manifest = Manifest::load(output).unwrap();
+		let sample = Sample { source_path: source.to_path_buf(), target_path: target.to_path_buf(), fields: HashMap::new() };
+		let copies = CopyOptions { preserve_mtime: false, verify: false };
+		let (outcome, used) = writer::write_sample(&sample, WriteMode::Copy, ExistingPolicy::Overwrite, &links(), &copies, &mut journal);
+
+		assert_eq!(outcome, WriteOutcome::Overwritten);
+
+		manifest.record_outcome("KitA", &sample, used, &outcome, false);
+		journal.save().unwrap();
+		manifest.save(output).unwrap();
+		journal.run
+	}
+
+	#[test]
+	fn undo_restores_a_replaced_file_and_forgets_the_run()
+	{
+		let output = output("replaced");
+		let (source, target) = (Path::new(&output).join("Kick.wav"), Path::new(&output).join("KitA/Kick.wav"));
+
+		std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+		std::fs::write(&source, b"new kick").unwrap();
+		std::fs::write(&target, b"old kick").unwrap();
+
+		let run = overwrite(&output, &source, &target);
+
+		assert_eq!(std::fs::read(&target).unwrap(), b"new kick");
+
+		let report = undo(&output, None, false).unwrap();
+
+		assert_eq!((report.run.as_str(), report.restored, report.kept), (run.as_str(), 1, 0));
+		assert_eq!(std::fs::read(&target).unwrap(), b"old kick");
+		assert!(Manifest::load(&output).unwrap().entries.is_empty());
+		assert!(runs(&output).is_empty());
+
+		let _ = std::fs::remove_dir_all(&output);
+	}
+
+	#[test]
+	fn undo_restores_removed_files_and_keeps_created_ones_that_changed()
+	{
+		let output = output("removed");
+		let (stale, created) = (Path::new(&output).join("KitA/Snare.wav"), Path::new(&output).join("KitA/Kick.wav"));
+		let mut journal = Journal::new(&output);
+
+		std::fs::create_dir_all(stale.parent().unwrap()).unwrap();
+		std::fs::write(&stale, b"snare").unwrap();
+		journal.remove(&stale).unwrap();
+		std::fs::write(&created, b"kick").unwrap();
+		journal.created(&created);
+		journal.save().unwrap();
+
+		assert!(stale.symlink_metadata().is_err());
+
+		std::fs::write(&created, b"kick, edited by hand").unwrap();
+
+		let dry = undo(&output, Some(&journal.run), true).unwrap();
+		assert_eq!((dry.restored, dry.removed, dry.kept), (1, 0, 1));
+		assert!(stale.symlink_metadata().is_err());
+
+		let report = undo(&output, Some(&journal.run), false).unwrap();
+
+		assert_eq!((report.restored, report.removed, report.kept), (1, 0, 1));
+		assert_eq!(std::fs::read(&stale).unwrap(), b"snare");
+		assert_eq!(std::fs::read(&created).unwrap(), b"kick, edited by hand");
+		assert_eq!(runs(&output), [journal.run.to_string()]); // kept for the file it could not revert
+
+		let _ = std::fs::remove_dir_all(&output);
+	}
+}
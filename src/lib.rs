@@ -0,0 +1,65 @@
+//! KitCat sorts sample-libraries into kits by the rules of a ruleset. Every step of the pipeline returns [`Result`] instead of aborting the process:
+//!
+//! ```no_run
+//! use std::collections::HashMap;
+//! use kitcat::{catalog, journal, manifest, roots, runtimeconfig};
+//!
+//! fn main() -> kitcat::Result<()>
+//! {
+//!     let args = ["kitcat", "-r", "rules.ini", "-i", "Library", "-o", "Kits"].iter().map(|a| a.to_string()).collect();
+//!     let rc = runtimeconfig::process_args(args)?;
+//!     let roots = roots::load(&rc)?;
+//!     let mut manifest = manifest::Manifest::load(&rc.output)?;
+//!     let mut kits = roots::process(&roots, &rc, &manifest)?;
+//!
+//!     catalog::apply_filters(&mut kits, &rc);
+//!     catalog::resolve_collisions(&mut kits, &rc)?;
+//!
+//!     let mut journal = journal::Journal::new(&rc.output);
+//!
+//!     manifest.set_ruleset(&roots::fingerprint(&roots));
+//!
+//!     let summary = catalog::write_dataset(&kits, &rc, &mut manifest, &mut journal, &HashMap::new())?;
+//!
+//!     journal.save()?;
+//!     manifest.save(&rc.output)?;
+//!     println!("{}", summary);
+//!
+//!     Ok(())
+//! }
+//! ```
+
+#[macro_use]
+extern crate lazy_static;
+extern crate log;
+
+pub mod error;
+pub mod ruleset;
+pub mod lint;
+pub mod runtimeconfig;
+pub mod catalog;
+pub mod roots;
+pub mod stream;
+pub mod explain;
+pub mod plan;
+pub mod manifest;
+pub mod journal;
+pub mod sync;
+pub mod writer;
+pub mod pack;
+pub mod dedup;
+pub mod relink;
+pub mod interrupt;
+pub mod progress;
+pub mod spill;
+pub mod parallel;
+pub mod ignore;
+pub mod archive;
+pub mod ospath;
+pub mod version;
+
+pub use crate::error::{Error, Result};
+pub use crate::ruleset::Ruleset;
+pub use crate::catalog::{Kit, Sample};
+pub use crate::writer::{WriteMode, WriteSummary};
+pub use crate::runtimeconfig::RuntimeConfig;
@@ -0,0 +1,353 @@
+extern crate regex;
+	use regex::Regex;
+
+extern crate regex_syntax;
+	use regex_syntax::hir::Look;
+
+use std::collections::*;
+use crate::ruleset;
+	use crate::ruleset::Ruleset;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity
+{
+	Error,
+	Warning
+}
+
+pub struct Finding
+{
+	pub severity: Severity,
+	pub message: String
+}
+
+impl std::fmt::Debug for Finding
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [FINDING] severity: {:?} message: {:?} }}",
+    		self.severity,
+    		self.message
+		)
+	}
+}
+
+impl std::fmt::Display for Finding
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		match self.severity
+		{
+			Severity::Error => write!(f, "error: {}", self.message),
+			Severity::Warning => write!(f, "warning: {}", self.message)
+		}
+	}
+}
+
+fn error(message: String) -> Finding
+{
+	Finding { severity: Severity::Error, message }
+}
+
+fn warning(message: String) -> Finding
+{
+	Finding { severity: Severity::Warning, message }
+}
+
+pub fn has_errors(findings: &[Finding]) -> bool
+{
+	findings.iter().any(|f| f.severity == Severity::Error)
+}
+
+/// Validates a loaded ruleset without touching any file.
+/// Findings are sorted by severity (errors first).
+pub fn check(ruleset: &Ruleset) -> Vec<Finding>
+{
+	let mut out: Vec<Finding> = vec![];
+	let used: HashSet<String> = ruleset::placeholders(&ruleset.raw_input).into_iter().collect();
+
+	for name in ruleset::placeholders(&ruleset.raw_input)
+	{
+		if !ruleset.groups.contains_key(&name)
+		{
+			out.push(error(format!("input-rule uses placeholder {{{}}} which is not defined in the groups section", name)));
+		}
+	}
+
+	check_regexes(ruleset, &mut out);
+	check_placeholders("output", &ruleset.output, ruleset, &used, &mut out);
+
+	let mut rearranges: Vec<(&String, &String)> = ruleset.rearranges.iter().collect();
+	rearranges.sort();
+
+	for (group, value) in rearranges.iter()
+	{
+		if !ruleset.input_groups.contains_key(*group)
+		{
+			out.push(error(format!("rearrange {:?} refers to group {:?} which is not captured by the input-rule {:?}", group, group, ruleset.raw_input)));
+		}
+
+		check_placeholders(&format!("rearrange {:?}", group), value, ruleset, &used, &mut out);
+	}
+
+	let mut groups: Vec<&String> = ruleset.groups.keys().collect();
+	groups.sort();
+
+	for group in groups
+	{
+		if !used.contains(group)
+		{
+			out.push(warning(format!("group {:?} is defined but never used in the input-rule", group)));
+		}
+	}
+
+	if ruleset::is_helper_group(&ruleset.index)
+	{
+		out.push(error(format!("index {:?} is a helper group and does not capture any value", ruleset.index)));
+	}
+	else if !ruleset.input_groups.contains_key(&ruleset.index)
+	{
+		out.push(error(format!("index {:?} does not occur in the input-rule {:?}", ruleset.index, ruleset.raw_input)));
+	}
+
+	check_recheck(ruleset, &mut out);
+	check_separators(ruleset, &mut out);
+
+	out.sort_by_key(|f| f.severity != Severity::Error);
+	out
+}
+
+fn check_regexes(ruleset: &Ruleset, out: &mut Vec<Finding>)
+{
+	let mut groups: Vec<(&String, &String)> = ruleset.groups.iter().collect();
+	groups.sort();
+
+	let mut group_failed = false;
+
+	for (group, regex) in groups
+	{
+		if let Err(e) = Regex::new(regex)
+		{
+			out.push(error(format!("regex of group {:?} does not compile: {}", group, e)));
+			group_failed = true;
+		}
+	}
+
+	if group_failed // the compiled input-rule would just repeat the errors above
+	{
+		return;
+	}
+
+	if let Err(e) = Regex::new(&ruleset.input)
+	{
+		out.push(error(format!("input-rule {:?} does not compile: {}", ruleset.raw_input, e)));
+	}
+}
+
+fn check_placeholders(location: &str, rule: &str, ruleset: &Ruleset, used: &HashSet<String>, out: &mut Vec<Finding>)
+{
+	for name in ruleset::placeholders(rule)
+	{
+		if !ruleset.groups.contains_key(&name)
+		{
+			out.push(error(format!("{} uses placeholder {{{}}} which is not defined in the groups section", location, name)));
+		}
+		else if ruleset::is_helper_group(&name)
+		{
+			out.push(error(format!("{} uses helper group {{{}}} which does not capture any value", location, name)));
+		}
+		else if !used.contains(&name)
+		{
+			out.push(error(format!("{} uses placeholder {{{}}} which never occurs in the input-rule", location, name)));
+		}
+	}
+}
+
+fn check_recheck(ruleset: &Ruleset, out: &mut Vec<Finding>)
+{
+	if ruleset.rearranges.is_empty()
+	{
+		if !ruleset.recheck.is_empty()
+		{
+			out.push(warning(format!("recheck {:?} is never used - the rearrange section is empty", ruleset.recheck)));
+		}
+
+		return;
+	}
+
+	if ruleset.recheck.is_empty()
+	{
+		out.push(warning("recheck is empty - every rearrange will be applied to every file".to_string()));
+		return;
+	}
+
+	let recheck = match regex_syntax::Parser::new().parse(&ruleset.recheck)
+	{
+		Err(e) => { out.push(error(format!("recheck {:?} does not compile: {}", ruleset.recheck, e))); return },
+		Ok(h) => h
+	};
+
+	let props = recheck.properties();
+
+	let min_len = match props.minimum_len()
+	{
+		None => { out.push(error(format!("recheck {:?} can never match", ruleset.recheck))); return },
+		Some(l) => l
+	};
+
+	let anchored = props.look_set_prefix().contains(Look::Start) && props.look_set_suffix().contains(Look::End);
+
+	if !anchored
+	{
+		return;
+	}
+
+	let mut groups: Vec<&String> = ruleset.rearranges.keys().collect();
+	groups.sort();
+
+	for group in groups
+	{
+		let max_len = ruleset.groups.get(group)
+		.and_then(|r| regex_syntax::Parser::new().parse(r).ok())
+		.and_then(|h| h.properties().maximum_len());
+
+		if let Some(max_len) = max_len
+		{
+			if min_len > max_len
+			{
+				out.push(error(format!("recheck {:?} can never match group {:?} - it needs at least {} characters, the group captures at most {}", ruleset.recheck, group, min_len, max_len)));
+			}
+		}
+	}
+}
+
+// backslashes only separate directories on Windows - / and the separator-token work on every OS
+fn check_separators(ruleset: &Ruleset, out: &mut Vec<Finding>)
+{
+	// the input-rule is a regex - a windows separator has to be written as escaped backslash there
+	if ruleset.raw_input.contains(r"\\")
+	{
+		out.push(warning(format!("input-rule {:?} contains the Windows directory-separator {:?} - use / or {} to match paths on every OS", ruleset.raw_input, r"\\", ruleset::SEPARATOR)));
+	}
+
+	let mut rules: Vec<(String, &String)> = vec![("output".to_string(), &ruleset.output)];
+
+	for (group, value) in ruleset.rearranges.iter()
+	{
+		rules.push((format!("rearrange {:?}", group), value));
+	}
+
+	rules.sort();
+
+	for (location, rule) in rules
+	{
+		if rule.contains('\\')
+		{
+			out.push(warning(format!("{} {:?} contains the Windows directory-separator {:?} - use / or {} to write paths on every OS", location, rule, '\\', ruleset::SEPARATOR)));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// ruleset like setup_custom_ruleset creates it out of the given rules (without reading a file)
+	fn ruleset(input: &str, output: &str, groups: &[(&str, &str)], rearranges: &[(&str, &str)], recheck: &str, index: &str) -> Ruleset
+	{
+		let mut out = Ruleset {
+			groups: groups.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+			rearranges: rearranges.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+			recheck: recheck.to_string(),
+			raw_input: input.to_string(),
+			input: ruleset::portable_input(input),
+			output: output.to_string(),
+			index: index.to_string(),
+			input_groups: HashMap::new()
+		};
+
+		out.input_groups = ruleset::apply_input_groups(&mut out.input, &out.groups).unwrap_or_default();
+		out
+	}
+
+	fn messages(findings: &[Finding], severity: Severity) -> Vec<&str>
+	{
+		findings.iter().filter(|f| f.severity == severity).map(|f| f.message.as_str()).collect()
+	}
+
+	const GROUPS: &[(&str, &str)] = &[("group", "(Kick|Snare)"), ("sample", "([a-z]+)"), ("kit", "(Kit[A-Z])")];
+
+	#[test]
+	fn valid_ruleset_has_no_findings()
+	{
+		let findings = check(&ruleset("^{group}/{sample} {kit}$", "{kit}/{group}", GROUPS, &[], "", "kit"));
+
+		assert!(findings.is_empty(), "{:?}", findings);
+		assert!(!has_errors(&findings));
+	}
+
+	#[test]
+	fn default_ruleset_has_no_errors()
+	{
+		assert!(!has_errors(&check(&ruleset::setup_default_ruleset().unwrap())));
+	}
+
+	#[test]
+	fn undefined_and_uncaptured_placeholders_are_errors()
+	{
+		let findings = check(&ruleset("^{group}/{sample}$", "{kit}/{nope}", &[("group", "(Kick)"), ("sample", "(.*)"), ("kit", "(.*)")], &[], "", "group"));
+		let errors = messages(&findings, Severity::Error);
+
+		assert!(errors.contains(&"output uses placeholder {kit} which never occurs in the input-rule"), "{:?}", errors);
+		assert!(errors.contains(&"output uses placeholder {nope} which is not defined in the groups section"), "{:?}", errors);
+		assert_eq!(messages(&findings, Severity::Warning), vec!["group \"kit\" is defined but never used in the input-rule"]);
+	}
+
+	#[test]
+	fn regex_errors_are_reported_once_per_group()
+	{
+		let findings = check(&ruleset("^{group}/{kit}$", "{kit}", &[("group", "(Kick"), ("kit", "(.*)")], &[], "", "kit"));
+		let errors = messages(&findings, Severity::Error);
+
+		assert_eq!(errors.len(), 1, "{:?}", errors);
+		assert!(errors[0].starts_with("regex of group \"group\" does not compile"));
+	}
+
+	#[test]
+	fn index_must_be_captured()
+	{
+		let helper = check(&ruleset("^{_sep}{kit}$", "{kit}", &[("_sep", "[ ]*"), ("kit", "(.*)")], &[], "", "_sep"));
+		let missing = check(&ruleset("^{kit}$", "{kit}", &[("kit", "(.*)"), ("group", "(.*)")], &[], "", "group"));
+
+		assert!(messages(&helper, Severity::Error).iter().any(|m| m.starts_with("index \"_sep\" is a helper group")));
+		assert!(messages(&missing, Severity::Error).iter().any(|m| m.starts_with("index \"group\" does not occur in the input-rule")));
+	}
+
+	#[test]
+	fn recheck_that_can_never_match_is_an_error()
+	{
+		let findings = check(&ruleset("^{group}/{kit}$", "{kit}", &[("group", "([a-z]{1,2})"), ("kit", "(.*)")], &[("group", "{kit}")], "^[a-z]{3}$", "kit"));
+
+		assert!(messages(&findings, Severity::Error).iter().any(|m| m.contains("can never match group \"group\"")), "{:?}", findings);
+	}
+
+	#[test]
+	fn unused_or_empty_recheck_is_a_warning()
+	{
+		let unused = check(&ruleset("^{kit}$", "{kit}", &[("kit", "(.*)")], &[], "^$", "kit"));
+		let empty = check(&ruleset("^{kit}$", "{kit}", &[("kit", "(.*)")], &[("kit", "{kit}")], "", "kit"));
+
+		assert_eq!(messages(&unused, Severity::Warning), vec!["recheck \"^$\" is never used - the rearrange section is empty"]);
+		assert_eq!(messages(&empty, Severity::Warning), vec!["recheck is empty - every rearrange will be applied to every file"]);
+	}
+
+	#[test]
+	fn windows_separators_are_warnings_and_errors_come_first()
+	{
+		let findings = check(&ruleset(r"^{kit}\\{group}$", r"{kit}\{nope}", &[("kit", "(.*)"), ("group", "(.*)")], &[], "", "kit"));
+
+		assert_eq!(findings.first().map(|f| f.severity), Some(Severity::Error));
+		assert_eq!(messages(&findings, Severity::Warning).len(), 2, "{:?}", findings);
+	}
+}
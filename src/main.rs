@@ -1,50 +1,351 @@
-#[macro_use]
-extern crate lazy_static;
 extern crate stderrlog;
 extern crate log;
 	use log::*;
 
-mod ruleset;
-mod runtimeconfig;
-mod catalog;
-mod version;
-
 use std::collections::*;
-use crate::catalog::Kit;
-use crate::runtimeconfig::RuntimeConfig;
+use kitcat::*;
+use kitcat::runtimeconfig::Command;
+use kitcat::pack::PackFormat;
+use kitcat::dedup::DedupMode;
+use kitcat::progress::Progress;
 
 fn main()
 {
-	let rc = runtimeconfig::from_args();
+	let rc = match runtimeconfig::from_args()
+	{
+		Ok(rc) => rc,
+		Err(e) => { eprintln!("{}", e); std::process::exit(2) }
+	};
+
 	init_logger(&rc);
 	info!("Using {:?}", rc);
 
-	if rc.help || rc.input.is_empty()
+	if rc.help || rc.command == Command::Help
+	{
+		runtimeconfig::print_help();
+		return;
+	}
+
+	let result = match rc.command
+	{
+		Command::CheckRules => check_rules(&rc),
+		Command::Plan => plan(&rc),
+		Command::Apply => apply(&rc),
+		Command::Explain => explain(&rc),
+		Command::Undo => undo(&rc),
+		Command::Relink => relink(&rc),
+		_ => run(&rc)
+	};
+
+	if let Err(e) = result
+	{
+		error!("{}", e);
+		std::process::exit(1);
+	}
+}
+
+fn run(rc: &RuntimeConfig) -> Result<()>
+{
+	if rc.stream
+	{
+		return run_stream(rc);
+	}
+
+	let roots = load_roots(rc)?;
+	let mut manifest = manifest::Manifest::load(&rc.output)?;
+	let mut kits: HashMap<String, Kit> = roots::process(&roots, rc, &manifest)?;
+
+	catalog::apply_filters(&mut kits, rc);
+	catalog::resolve_collisions(&mut kits, rc)?;
+
+	let links = deduplicate(&mut kits, rc);
+	let mut journal = journal::Journal::new(&rc.output);
+	let resumed = resume(rc)?;
+
+	if rc.sync
+	{
+		println!("{}", sync::prepare(&mut kits, &mut manifest, &mut journal, rc));
+	}
+	else if !rc.full && rc.pack == PackFormat::None
 	{
-		if rc.input.is_empty()
+		let unchanged = sync::skip_unchanged(&mut kits, &manifest, &mut journal, rc);
+
+		if unchanged > 0
 		{
-			warn!("KitCat was started without any argument (at least -input is required) - help will be shown");
+			println!("Skipping {} samples unchanged since the last run", unchanged);
 		}
+	}
 
-		runtimeconfig::print_help();
-		return;
+	if let Some(progress) = &resumed
+	{
+		let completed = progress::skip_completed(&mut kits, progress, &manifest);
+
+		if completed > 0
+		{
+			println!("Skipping {} samples completed by run {}", completed, progress.run);
+		}
+	}
+
+	manifest.set_ruleset(&roots::fingerprint(&roots));
+
+	let summary = match catalog::write_dataset(&kits, rc, &mut manifest, &mut journal, &links)
+	{
+		Ok(s) => s,
+		Err(e) =>
+		{
+			// targets removed by --sync or replaced as outdated can still be restored by undo
+			journal.save()?;
+			manifest.save(&rc.output)?;
+
+			return Err(e);
+		}
+	};
+
+	finish_run(rc, &summary, &mut manifest, &journal, resumed)
+}
+
+fn run_stream(rc: &RuntimeConfig) -> Result<()>
+{
+	let roots = load_roots(rc)?;
+	let mut manifest = manifest::Manifest::load_streamed(&rc.output)?;
+	let mut journal = journal::Journal::new(&rc.output);
+	let resumed = resume(rc)?;
+
+	let (summary, unchanged) = stream::run(&roots, rc, &mut manifest, &mut journal, resumed.as_ref())?;
+
+	if unchanged > 0
+	{
+		println!("Skipping {} samples unchanged since the last run", unchanged);
+	}
+
+	finish_run(rc, &summary, &mut manifest, &journal, resumed)
+}
+
+// the record of the unfinished run to continue (--resume)
+fn resume(rc: &RuntimeConfig) -> Result<Option<Progress>>
+{
+	if !rc.resume
+	{
+		return Ok(None);
+	}
+
+	let progress = Progress::load(&rc.output)?;
+
+	match &progress
+	{
+		None => println!("Nothing to resume - the last run into {:?} wrote all of it's samples", rc.output),
+		Some(p) => println!("Resuming run {} ({} samples completed, {} unfinished{})", p.run, p.completed.len(), p.unfinished, if p.interrupted { ", interrupted" } else { "" })
+	}
+
+	Ok(progress)
+}
+
+// saves journal, manifest and progress of a (non-dry) run and prints it's summary
+fn finish_run(rc: &RuntimeConfig, summary: &WriteSummary, manifest: &mut manifest::Manifest, journal: &journal::Journal, resumed: Option<Progress>) -> Result<()>
+{
+	if rc.dry
+	{
+		return Ok(());
+	}
+
+	if rc.pack == PackFormat::TarStdout
+	{
+		report(rc, summary);
+		return Ok(());
+	}
+
+	journal.save()?;
+
+	if rc.pack == PackFormat::None
+	{
+		manifest.save(&rc.output)?; // archives are not recorded - there is nothing new to save
+		progress::save(&rc.output, &journal.run, summary, resumed)?;
+	}
+
+	println!("{}", summary);
+
+
+	if !journal.actions.is_empty()
+	{
+		println!("Run {} can be reverted by: kitcat undo {}", journal.run, journal.run);
+	}
+
+	if summary.interrupted > 0
+	{
+		println!("Interrupted - {} samples were not written. Run the same command with --resume to continue", summary.interrupted);
+		std::process::exit(130); // like a process terminated by SIGINT
+	}
+
+	Ok(())
+}
+
+fn plan(rc: &RuntimeConfig) -> Result<()>
+{
+	let roots = load_roots(rc)?;
+	let manifest = manifest::Manifest::load(&rc.output)?;
+	let mut kits: HashMap<String, Kit> = roots::process(&roots, rc, &manifest)?;
+
+	catalog::apply_filters(&mut kits, rc);
+	catalog::resolve_collisions(&mut kits, rc)?;
+	deduplicate(&mut kits, rc);
+
+	let plan = plan::create(&kits, &roots, rc);
+
+	if let Some(to_file) = rc.operands.first()
+	{
+		plan::save(&plan, to_file)?;
+		println!("Saved plan with {} entries to {}", plan.entries.len(), to_file);
+
+		return Ok(());
+	}
+
+	let mut kit = "";
+
+	for entry in plan.entries.iter()
+	{
+		if entry.kit != kit
+		{
+			kit = &entry.kit;
+			println!("{}", kit);
+		}
+
+		println!("\t{} -> {} ({})", entry.source.display(), entry.target.display(), entry.mode.name());
+	}
+
+	Ok(())
+}
+
+fn apply(rc: &RuntimeConfig) -> Result<()>
+{
+	let roots = roots::load(rc)?;
+	let plan = plan::load(&rc.operands[0])?;
+
+	let summary = plan::apply(&plan, &roots, rc)?;
+
+	if !rc.dry
+	{
+		println!("{}", summary);
+	}
+
+	Ok(())
+}
+
+fn undo(rc: &RuntimeConfig) -> Result<()>
+{
+	let report = journal::undo(&rc.output, rc.operands.first().map(|r| r.as_str()), rc.dry)?;
+
+	println!("{}", report);
+
+	Ok(())
+}
+
+fn relink(rc: &RuntimeConfig) -> Result<()>
+{
+	let mut manifest = manifest::Manifest::load(&rc.output)?;
+	let mut journal = journal::Journal::new(&rc.output);
+
+	let report = relink::relink(rc, &mut manifest, &mut journal);
+
+	println!("{}", report);
+
+	if rc.dry || journal.actions.is_empty()
+	{
+		return Ok(());
 	}
 
-    let samples = catalog::collect(&rc.input);
-    let mut kits: HashMap<String, Kit> = catalog::process_dataset(samples, &rc);
+	journal.save()?;
+	manifest.save(&rc.output)?;
+	println!("Run {} can be reverted by: kitcat undo {}", journal.run, journal.run);
 
-    catalog::apply_filters(&mut kits, &rc);
-    catalog::write_dataset(&kits, &rc);
+	Ok(())
+}
+
+fn check_rules(rc: &RuntimeConfig) -> Result<()>
+{
+	let ruleset = ruleset::load(&rc.rules)?;
+	let findings = lint::check(&ruleset);
+
+	for finding in findings.iter()
+	{
+		println!("{}", finding);
+	}
+
+	if lint::has_errors(&findings)
+	{
+		std::process::exit(1);
+	}
+
+	println!("Ruleset is valid");
+
+	Ok(())
+}
+
+fn explain(rc: &RuntimeConfig) -> Result<()>
+{
+	let roots = load_roots(rc)?;
+
+	for path in rc.operands.iter()
+	{
+		let root = roots::root_of(&roots, path);
+
+		println!("{}", explain::explain(path, &root.ruleset, &root.rcon)?);
+	}
+
+	Ok(())
+}
+
+// runs the hashing stage (if configured) and reports the duplicates - returns the duplicates to link
+fn deduplicate(kits: &mut HashMap<String, Kit>, rc: &RuntimeConfig) -> HashMap<std::path::PathBuf, std::path::PathBuf>
+{
+	if rc.dedup == DedupMode::None
+	{
+		return HashMap::new();
+	}
+
+	let dedup = dedup::apply(kits, rc);
+
+	report(rc, &dedup);
+	dedup.links
+}
+
+// prints to stdout - unless stdout carries an archive
+fn report(rc: &RuntimeConfig, what: &dyn std::fmt::Display)
+{
+	if rc.pack == PackFormat::TarStdout
+	{
+		eprintln!("{}", what);
+	}
+	else
+	{
+		println!("{}", what);
+	}
+}
+
+// loads the ruleset of every input-directory and reports it's findings (once per ruleset-file)
+fn load_roots(rc: &RuntimeConfig) -> Result<Vec<roots::Root>>
+{
+	let roots = roots::load(rc)?;
+	let mut checked: HashSet<&str> = HashSet::new();
+
+	for root in roots.iter().filter(|r| checked.insert(&r.rcon.rules))
+	{
+		for finding in lint::check(&root.ruleset)
+		{
+			warn!("Ruleset {}", finding);
+		}
+	}
+
+	Ok(roots)
 }
 
 fn init_logger(rc: &RuntimeConfig)
 {
-	let verbosity = if rc.verbose { 2 } else { 0 } ;
+	let verbosity = if rc.verbose { 2 } else { 1 } ; // warnings (e.g. collisions) are always reported
 	
 	stderrlog::new()
-    .module(module_path!())
-    .verbosity(verbosity)
+	.module(module_path!())
+	.verbosity(verbosity)
 	.timestamp(stderrlog::Timestamp::Off)
-    .init()
-    .unwrap();
-}
\ No newline at end of file
+	.init()
+	.unwrap();
+}
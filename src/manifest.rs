@@ -0,0 +1,687 @@
+extern crate log;
+	use log::*;
+
+extern crate serde;
+	use serde::{Deserialize, Serialize};
+	use serde::de::{DeserializeSeed, Deserializer, Error as _, IgnoredAny, MapAccess, Visitor};
+
+use std::borrow::Cow;
+use std::collections::*;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use crate::version;
+use crate::archive;
+use crate::error;
+	use crate::error::{Error, Result};
+use crate::catalog::Sample;
+use crate::writer::{WriteMode, WriteOutcome};
+use crate::spill::Spill;
+
+// everything KitCat keeps inside the output-directory lives in this directory
+pub const STATE_DIR: &str = ".kitcat";
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Record of every file KitCat created inside an output-directory.
+/// Files that are not listed here (or changed since they were written) are never touched.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest
+{
+	pub kitcat: String,
+	#[serde(default)]
+	pub ruleset: String, // fingerprint of the ruleset of the last run
+	#[serde(default)]
+	pub output: String, // output-directory the manifest was saved in - the target-paths are rebased if it moved
+	pub entries: BTreeMap<String, ManifestEntry>, // target-path -> entry
+	#[serde(skip)]
+	streamed: Option<Streamed>, // entries of a manifest loaded by load_streamed - the entries-map stays empty
+	#[serde(skip)]
+	renamed: HashMap<String, String> // target-path of a sample -> path it was written to instead (--on-existing rename)
+}
+
+// entries kept in a spill-file (target-path -> entry as JSON), so only the hashes of their target-paths are kept in memory
+struct Streamed
+{
+	spill: Spill,
+	loaded: u64, // records below this offset were read from the saved manifest
+	ruleset: String // fingerprint of the ruleset the loaded records were created by
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ManifestEntry
+{
+	#[serde(with = "crate::ospath")]
+	pub source: PathBuf,
+	pub kit: String,
+	#[serde(default, skip_serializing_if = "String::is_empty")]
+	pub planned: String, // target-path of the sample if it was written to a different one (renamed) - empty otherwise
+	pub mode: WriteMode,
+	#[serde(default, with = "crate::ospath", skip_serializing_if = "crate::ospath::is_empty")]
+	pub link: PathBuf, // what the soft-link points to (absolute, relative or below the library-root) - empty for other modes
+	pub size: u64, // size of the target when it was written
+	pub modified: u64, // mtime (seconds) of the target when it was written
+	#[serde(default)]
+	pub source_size: u64,
+	#[serde(default)]
+	pub source_modified: u64, // mtime (seconds)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub hash: Option<String>, // content-hash of the source (--hash)
+	#[serde(default)]
+	pub fields: BTreeMap<String, String> // final group-values of the sample
+}
+
+impl std::fmt::Debug for Manifest
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [MANIFEST] kitcat: {:?} ruleset: {:?} output: {:?} entries: {:?} }}",
+    		self.kitcat,
+    		self.ruleset,
+    		self.output,
+    		self.entries
+		)
+	}
+}
+
+impl std::fmt::Debug for ManifestEntry
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [MANIFESTENTRY] source: {:?} kit: {:?} planned: {:?} mode: {:?} link: {:?} size: {:?} modified: {:?} source_size: {:?} source_modified: {:?} hash: {:?} fields: {:?} }}",
+    		self.source,
+    		self.kit,
+    		self.planned,
+    		self.mode,
+    		self.link,
+    		self.size,
+    		self.modified,
+    		self.source_size,
+    		self.source_modified,
+    		self.hash,
+    		self.fields
+		)
+	}
+}
+
+impl ManifestEntry
+{
+	/// What the soft-link points to - entries written by older versions only know the source.
+	pub fn link_path(&self) -> &Path
+	{
+		if crate::ospath::is_empty(&self.link) { &self.source } else { &self.link }
+	}
+
+	// moves the planned target-path along with the one the entry is recorded under
+	fn rebased(mut self, from: &str, to: &str) -> ManifestEntry
+	{
+		if !self.planned.is_empty()
+		{
+			self.planned = rebased(std::mem::take(&mut self.planned), from, to);
+		}
+
+		self
+	}
+}
+
+pub fn state_dir(output: &str) -> PathBuf
+{
+	PathBuf::from(output).join(STATE_DIR)
+}
+
+pub fn manifest_path(output: &str) -> PathBuf
+{
+	state_dir(output).join(MANIFEST_FILE)
+}
+
+/// Size and mtime (seconds) of the given path itself (links are not followed).
+pub fn stat(path: &Path) -> Option<(u64, u64)>
+{
+	let meta = path.symlink_metadata().ok()?;
+	let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+
+	Some((meta.len(), modified))
+}
+
+/// Size and mtime (seconds) of a source - the ones of it's archive if it lies inside of one.
+pub fn source_stat(path: &Path) -> Option<(u64, u64)>
+{
+	stat(&archive::file_of(path))
+}
+
+/// FNV-1a (64 bit) of the content of the file (read out of it's archive if it lies inside of one), as hex-string.
+pub fn hash_file(path: &Path) -> Option<String>
+{
+	archive::with_reader(path, hash_reader).ok().map(|(_, hash)| hash)
+}
+
+/// Count of the bytes read and their FNV-1a (64 bit), as hex-string - the content is read in chunks.
+pub fn hash_reader(file: &mut dyn std::io::Read) -> std::io::Result<(u64, String)>
+{
+	let mut buf = [0u8; 65536];
+	let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+	let mut count: u64 = 0;
+
+	loop
+	{
+		let read = match file.read(&mut buf)
+		{
+			Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+			r => r?
+		};
+
+		if read == 0
+		{
+			return Ok((count, format!("{:016x}", hash)));
+		}
+
+		count += read as u64;
+
+		for byte in buf[..read].iter()
+		{
+			hash ^= u64::from(*byte);
+			hash = hash.wrapping_mul(0x0100_0000_01b3);
+		}
+	}
+}
+
+impl Manifest
+{
+	pub fn new() -> Manifest
+	{
+		Manifest { kitcat: version::APP_VERSION.to_string(), ruleset: String::new(), output: String::new(), entries: BTreeMap::new(), streamed: None, renamed: HashMap::new() }
+	}
+
+	/// Reads the manifest of the output-directory entry by entry into a spill-file instead of memory (for --stream).
+	/// Lookups read the entries back from the file and save writes them out the same way - other users of the entries-map see an empty one.
+	pub fn load_streamed(output: &str) -> Result<Manifest>
+	{
+		let path = manifest_path(output);
+		let invalid = |reason: String| Error::Manifest { file: path.to_string_lossy().to_string(), reason };
+		let mut manifest = Manifest::new();
+		let mut spill = Spill::new().map_err(|e| error::io(std::env::temp_dir(), e))?;
+
+		if path.exists()
+		{
+			let file = std::fs::File::open(&path).map_err(|e| error::io(&path, e))?;
+			let mut reader = serde_json::Deserializer::from_reader(std::io::BufReader::new(file));
+
+			ManifestSeed { manifest: &mut manifest, spill: &mut spill, output }.deserialize(&mut reader).map_err(|e| invalid(e.to_string()))?;
+			reader.end().map_err(|e| invalid(e.to_string()))?;
+		}
+
+		info!("Streamed manifest with {:?} entries into {:?}", spill.len(), spill);
+
+		manifest.output = output.to_string();
+		manifest.streamed = Some(Streamed { loaded: spill.end(), ruleset: manifest.ruleset.clone(), spill });
+
+		Ok(manifest)
+	}
+
+	/// Count of entries.
+	pub fn len(&self) -> usize
+	{
+		match &self.streamed
+		{
+			None => self.entries.len(),
+			Some(s) => s.spill.len()
+		}
+	}
+
+	pub fn is_empty(&self) -> bool
+	{
+		self.len() == 0
+	}
+
+	/// Reads the manifest of the output-directory - an empty manifest is returned if there is none yet.
+	pub fn load(output: &str) -> Result<Manifest>
+	{
+		let mut manifest = Manifest::load_from(&manifest_path(output))?;
+
+		manifest.rebase(output);
+		manifest.renamed = manifest.entries.iter().filter(|(_, e)| !e.planned.is_empty()).map(|(target, e)| (e.planned.to_string(), target.to_string())).collect();
+		Ok(manifest)
+	}
+
+	// moves the target-paths below the given output-directory, if the manifest was saved in a different one (it moved since)
+	fn rebase(&mut self, output: &str)
+	{
+		if !self.output.is_empty() && self.output != output
+		{
+			info!("Output-directory moved from {:?} - rebasing {:?} entries", self.output, self.entries.len());
+
+			let old = std::mem::take(&mut self.entries);
+
+			self.entries = old.into_iter().map(|(target, entry)| (rebased(target, &self.output, output), entry.rebased(&self.output, output))).collect();
+		}
+
+		self.output = output.to_string();
+	}
+
+	pub fn load_from(path: &Path) -> Result<Manifest>
+	{
+		if !path.exists()
+		{
+			return Ok(Manifest::new());
+		}
+
+		let content = std::fs::read_to_string(path).map_err(|e| error::io(path, e))?;
+
+		serde_json::from_str(&content).map_err(|e| Error::Manifest { file: path.to_string_lossy().to_string(), reason: e.to_string() })
+	}
+
+	pub fn save(&mut self, output: &str) -> Result<()>
+	{
+		let path = manifest_path(output);
+		let dir = state_dir(output);
+
+		self.kitcat = version::APP_VERSION.to_string();
+		self.output = output.to_string();
+
+		std::fs::create_dir_all(&dir).map_err(|e| error::io(&dir, e))?;
+
+		match &self.streamed
+		{
+			Some(streamed) => self.save_streamed(streamed, &path).map_err(|e| error::io(&path, e))?,
+			None =>
+			{
+				let content = serde_json::to_string_pretty(self).map_err(|e| Error::Manifest { file: path.to_string_lossy().to_string(), reason: e.to_string() })?;
+
+				std::fs::write(&path, content).map_err(|e| error::io(&path, e))?;
+			}
+		}
+
+		info!("Saved manifest with {:?} entries to {:?}", self.len(), path);
+
+		Ok(())
+	}
+
+	// writes the entries of the spill-file out one by one (through a temporary file, so an interrupted save keeps the previous manifest)
+	// loaded records lose their group-values if they were created by a different ruleset
+	fn save_streamed(&self, streamed: &Streamed, path: &Path) -> std::io::Result<()>
+	{
+		let temp = path.with_extension("json.tmp");
+		let mut out = std::io::BufWriter::new(std::fs::File::create(&temp)?);
+		let clear = streamed.ruleset != self.ruleset;
+		let mut first = true;
+
+		write!(out, "{{\n  \"kitcat\": {},\n  \"ruleset\": {},\n  \"output\": {},\n  \"entries\": {{",
+			serde_json::to_string(&self.kitcat)?,
+			serde_json::to_string(&self.ruleset)?,
+			serde_json::to_string(&self.output)?
+		)?;
+
+		streamed.spill.each(|offset, target, value|
+		{
+			write!(out, "{}\n    {}: ", if first { "" } else { "," }, serde_json::to_string(&String::from_utf8_lossy(target))?)?;
+			first = false;
+
+			if clear && offset < streamed.loaded
+			{
+				let mut entry: ManifestEntry = serde_json::from_slice(value)?;
+
+				entry.fields.clear();
+				serde_json::to_writer(&mut out, &entry)?;
+				return Ok(());
+			}
+
+			out.write_all(value)
+		})?;
+
+		write!(out, "{}}}\n}}\n", if first { "" } else { "\n  " })?;
+		out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+		std::fs::rename(&temp, path)
+	}
+
+	/// Records a sample written to the given target-path (which differs from the sample's one if it was renamed).
+	/// The content of the source is hashed if requested.
+	pub fn record(&mut self, kit: &str, sample: &Sample, mode: WriteMode, target: &Path, hash: bool)
+	{
+		let source = &sample.source_path;
+		let (size, modified) = stat(target).unwrap_or((0, 0));
+		let (source_size, source_modified) = source_stat(source).unwrap_or((0, 0));
+
+		self.insert(target.to_string_lossy().to_string(), ManifestEntry {
+			source: source.to_path_buf(),
+			kit: kit.to_string(),
+			planned: if target == sample.target_path { String::new() } else { sample.target_path.to_string_lossy().to_string() },
+			mode,
+			link: if mode == WriteMode::Soft { std::fs::read_link(target).unwrap_or_default() } else { PathBuf::new() },
+			size,
+			modified,
+			source_size,
+			source_modified,
+			hash: if hash { hash_file(source) } else { None },
+			fields: sample.fields.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+		});
+	}
+
+	// a sample written to a different path than before replaces the entry of the previous one
+	fn insert(&mut self, target: String, entry: ManifestEntry)
+	{
+		let planned = if entry.planned.is_empty() { target.to_string() } else { entry.planned.to_string() };
+
+		if let Some(previous) = self.renamed.remove(&planned).filter(|p| *p != target)
+		{
+			self.forget(&previous);
+		}
+
+		if planned != target
+		{
+			self.renamed.insert(planned, target.to_string());
+		}
+
+		let streamed = match &mut self.streamed
+		{
+			None => { self.entries.insert(target, entry); return },
+			Some(s) => s
+		};
+
+		let stored = serde_json::to_vec(&entry).map_err(std::io::Error::from).and_then(|value| streamed.spill.insert(target.as_bytes(), &value));
+
+		if let Err(e) = stored
+		{
+			error!("Recording {:?} failed: {:?}", target, e);
+		}
+	}
+
+	// drops the entry of the target-path
+	fn forget(&mut self, target: &str)
+	{
+		match &mut self.streamed
+		{
+			None => { self.entries.remove(target); },
+			Some(s) => if let Err(e) = s.spill.remove(target.as_bytes())
+			{
+				error!("Forgetting {:?} failed: {:?}", target, e);
+			}
+		}
+	}
+
+	/// Records the sample if it was written (under the path it was written to).
+	pub fn record_outcome(&mut self, kit: &str, sample: &Sample, mode: WriteMode, outcome: &WriteOutcome, hash: bool)
+	{
+		match outcome
+		{
+			WriteOutcome::Written | WriteOutcome::Overwritten => self.record(kit, sample, mode, &sample.target_path, hash),
+			WriteOutcome::Renamed(target) => self.record(kit, sample, mode, target, hash),
+			WriteOutcome::Skipped | WriteOutcome::Failed | WriteOutcome::Interrupted => {}
+		}
+	}
+
+	/// Records that the soft-link at the target-path was rewritten to point to the (moved) source.
+	pub fn relinked(&mut self, target: &Path, source: &Path)
+	{
+		if let Some(entry) = self.entries.get_mut(target.to_string_lossy().as_ref())
+		{
+			(entry.size, entry.modified) = stat(target).unwrap_or((0, 0));
+			(entry.source_size, entry.source_modified) = source_stat(source).unwrap_or((0, 0));
+			entry.source = source.to_path_buf();
+			entry.link = std::fs::read_link(target).unwrap_or_default();
+		}
+	}
+
+	/// Sets the fingerprint of the ruleset the following records are created by.
+	/// The recorded group-values of a different ruleset are dropped - they can't be reused anymore.
+	pub fn set_ruleset(&mut self, fingerprint: &str)
+	{
+		if self.ruleset == fingerprint || self.streamed.is_some() // streamed entries are cleared on save
+		{
+			self.ruleset = fingerprint.to_string();
+			return;
+		}
+
+		for entry in self.entries.values_mut()
+		{
+			entry.fields.clear();
+		}
+
+		self.ruleset = fingerprint.to_string();
+	}
+
+	/// Final group-values of every recorded source-path - empty if the manifest was written by a different ruleset.
+	/// The values only depend on the path and the ruleset, so they can be reused instead of matching the path again.
+	pub fn fields_by_source(&self, ruleset: &str) -> HashMap<&Path, &BTreeMap<String, String>>
+	{
+		if self.ruleset != ruleset
+		{
+			return HashMap::new();
+		}
+
+		self.entries.values().filter(|e| !e.fields.is_empty()).map(|e| (e.source.as_path(), &e.fields)).collect()
+	}
+
+	/// Path a previous run wrote the sample with the given target-path to - it differs if the target-path was taken and the sample renamed.
+	pub fn written_path(&self, target: &Path) -> PathBuf
+	{
+		match self.renamed.get(target.to_string_lossy().as_ref())
+		{
+			None => target.to_path_buf(),
+			Some(written) => PathBuf::from(written)
+		}
+	}
+
+	/// True if the path the sample was written to is owned, was written from the same source and the source did not change since.
+	/// Sources with a different mtime but the same size are compared by their recorded hash (if any).
+	pub fn is_current(&self, sample: &Sample) -> bool
+	{
+		let target = self.written_path(&sample.target_path);
+
+		let entry = match self.entry(&target)
+		{
+			Some(e) if e.source == sample.source_path => e,
+			_ => return false
+		};
+
+		if !self.owns(&target)
+		{
+			return false;
+		}
+
+		let source = &sample.source_path;
+
+		match source_stat(source)
+		{
+			None => false,
+			Some(s) if s == (entry.source_size, entry.source_modified) => true,
+			Some((size, _)) => size == entry.source_size && entry.hash.is_some() && hash_file(source) == entry.hash
+		}
+	}
+
+	pub fn entry(&self, target: &Path) -> Option<Cow<'_, ManifestEntry>>
+	{
+		let target = target.to_string_lossy();
+
+		let streamed = match &self.streamed
+		{
+			None => return self.entries.get(target.as_ref()).map(Cow::Borrowed),
+			Some(s) => s
+		};
+
+		let value = streamed.spill.get(target.as_bytes()).and_then(|v| v.map(|v| serde_json::from_slice(&v).map_err(std::io::Error::from)).transpose());
+
+		match value
+		{
+			Err(e) => { error!("Reading the manifest-entry of {:?} failed: {:?}", target, e); None },
+			Ok(entry) => entry.map(Cow::Owned)
+		}
+	}
+
+	/// True if the file at the target-path is still the one KitCat created.
+	pub fn owns(&self, target: &Path) -> bool
+	{
+		let entry = match self.entry(target)
+		{
+			None => return false,
+			Some(e) => e
+		};
+
+		match entry.mode
+		{
+			WriteMode::Soft => std::fs::read_link(target).is_ok_and(|l| l == entry.link_path()),
+			WriteMode::Hard if crate::writer::is_same_file(&entry.source, target) => true,
+			_ => stat(target) == Some((entry.size, entry.modified))
+		}
+	}
+}
+
+// moves the target-path below the output-directory `to`, if it lies inside the one the manifest was saved in
+fn rebased(target: String, from: &str, to: &str) -> String
+{
+	match Path::new(&target).strip_prefix(from)
+	{
+		Ok(relative) => Path::new(to).join(relative).to_string_lossy().to_string(),
+		Err(_) => target
+	}
+}
+
+// reads the fields of a manifest into it, the entries into the spill-file
+struct ManifestSeed<'a>
+{
+	manifest: &'a mut Manifest,
+	spill: &'a mut Spill,
+	output: &'a str
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for ManifestSeed<'a>
+{
+	type Value = ();
+
+	fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> std::result::Result<(), D::Error>
+	{
+		deserializer.deserialize_map(self)
+	}
+}
+
+impl<'de, 'a> Visitor<'de> for ManifestSeed<'a>
+{
+	type Value = ();
+
+	fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		write!(f, "a manifest")
+	}
+
+	fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<(), A::Error>
+	{
+		while let Some(key) = map.next_key::<String>()?
+		{
+			match key.as_str()
+			{
+				"kitcat" => self.manifest.kitcat = map.next_value()?,
+				"ruleset" => self.manifest.ruleset = map.next_value()?,
+				"output" => self.manifest.output = map.next_value()?,
+				"entries" => map.next_value_seed(EntriesSeed { spill: &mut *self.spill, renamed: &mut self.manifest.renamed, from: &self.manifest.output, to: self.output })?,
+				_ => { map.next_value::<IgnoredAny>()?; }
+			}
+		}
+
+		Ok(())
+	}
+}
+
+// reads the entries into the spill-file one by one - rebased if the output-directory moved (the output-field comes first in saved manifests)
+struct EntriesSeed<'a>
+{
+	spill: &'a mut Spill,
+	renamed: &'a mut HashMap<String, String>,
+	from: &'a str,
+	to: &'a str
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for EntriesSeed<'a>
+{
+	type Value = ();
+
+	fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> std::result::Result<(), D::Error>
+	{
+		deserializer.deserialize_map(self)
+	}
+}
+
+impl<'de, 'a> Visitor<'de> for EntriesSeed<'a>
+{
+	type Value = ();
+
+	fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		write!(f, "a map of manifest-entries")
+	}
+
+	fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> std::result::Result<(), A::Error>
+	{
+		let rebase = !self.from.is_empty() && self.from != self.to;
+
+		while let Some((target, entry)) = map.next_entry::<String, ManifestEntry>()?
+		{
+			let (target, entry) = if rebase { (rebased(target, self.from, self.to), entry.rebased(self.from, self.to)) } else { (target, entry) };
+
+			if !entry.planned.is_empty()
+			{
+				self.renamed.insert(entry.planned.to_string(), target.to_string());
+			}
+
+			let value = serde_json::to_vec(&entry).map_err(A::Error::custom)?;
+
+			self.spill.insert(target.as_bytes(), &value).map_err(A::Error::custom)?;
+		}
+
+		Ok(())
+	}
+}
+
+impl Default for Manifest
+{
+	fn default() -> Manifest
+	{
+		Manifest::new()
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn sample(source: &Path, target: &Path) -> Sample
+	{
+		Sample { source_path: source.to_path_buf(), target_path: target.to_path_buf(), fields: HashMap::from([("kit".to_string(), "KitA".to_string())]) }
+	}
+
+	#[test]
+	fn streamed_manifests_are_rebased_looked_up_and_saved_like_loaded_ones()
+	{
+		let root = std::env::temp_dir().join(format!("kitcat-manifest-stream-{}", std::process::id()));
+		let (source, before, after) = (root.join("Kick.wav"), root.join("before"), root.join("after"));
+		let mut manifest = Manifest::new();
+
+		let _ = std::fs::remove_dir_all(&root);
+		std::fs::create_dir_all(before.join("KitA")).unwrap();
+		std::fs::write(&source, b"kick").unwrap();
+		std::fs::write(before.join("KitA/Kick 2.wav"), b"kick").unwrap();
+		manifest.record("KitA", &sample(&source, &before.join("KitA/Kick.wav")), WriteMode::Copy, &before.join("KitA/Kick 2.wav"), false);
+		manifest.save(&before.to_string_lossy()).unwrap();
+
+		std::fs::rename(&before, &after).unwrap();
+
+		let mut streamed = Manifest::load_streamed(&after.to_string_lossy()).unwrap();
+		let kick = sample(&source, &after.join("KitA/Kick.wav"));
+
+		assert!(streamed.entries.is_empty());
+		assert_eq!(streamed.len(), 1);
+		assert_eq!(streamed.written_path(&kick.target_path), after.join("KitA/Kick 2.wav"));
+		assert!(streamed.is_current(&kick));
+
+		std::fs::write(after.join("KitA/Snare.wav"), b"snare").unwrap();
+		streamed.record("KitA", &sample(&source, &after.join("KitA/Snare.wav")), WriteMode::Copy, &after.join("KitA/Snare.wav"), false);
+		streamed.save(&after.to_string_lossy()).unwrap();
+
+		let loaded = Manifest::load(&after.to_string_lossy()).unwrap();
+
+		assert_eq!(loaded.entries.keys().cloned().collect::<Vec<_>>(), [after.join("KitA/Kick 2.wav").to_string_lossy(), after.join("KitA/Snare.wav").to_string_lossy()]);
+		assert_eq!(loaded.entries[after.join("KitA/Kick 2.wav").to_string_lossy().as_ref()].planned, after.join("KitA/Kick.wav").to_string_lossy());
+		assert_eq!(loaded.output, after.to_string_lossy());
+
+		let _ = std::fs::remove_dir_all(&root);
+	}
+}
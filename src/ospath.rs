@@ -0,0 +1,39 @@
+extern crate serde;
+	use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+// paths are written as plain strings if possible, raw (as serde writes an OsString) otherwise
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Repr
+{
+	Text(String),
+	Raw(OsString)
+}
+
+/// Serializes paths that are not valid UTF-8 (e.g. Latin-1 names of old sampler CDs) without loss - use by #[serde(with = "crate::ospath")].
+pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error>
+{
+	match path.to_str()
+	{
+		Some(text) => serializer.serialize_str(text),
+		None => path.as_os_str().serialize(serializer)
+	}
+}
+
+/// For #[serde(skip_serializing_if = "crate::ospath::is_empty")] - optional paths are empty if not set.
+pub fn is_empty(path: &Path) -> bool
+{
+	path.as_os_str().is_empty()
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error>
+{
+	match Repr::deserialize(deserializer)?
+	{
+		Repr::Text(text) => Ok(PathBuf::from(text)),
+		Repr::Raw(raw) => Ok(PathBuf::from(raw))
+	}
+}
@@ -0,0 +1,361 @@
+extern crate log;
+	use log::*;
+
+extern crate zip;
+extern crate tar;
+
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use crate::runtimeconfig::RuntimeConfig;
+use crate::catalog::{Kit, Sample};
+use crate::ruleset;
+use crate::writer;
+	use crate::writer::{ExistingPolicy, WriteOutcome, WriteSummary};
+use crate::journal::Journal;
+use crate::archive;
+
+/// Where the samples are written to - the output-directory itself or archives of it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PackFormat
+{
+	None, // copy/link every sample into the output-directory
+	KitZip, // one zip per kit inside the output-directory
+	Zip, // a single zip of the whole remapped tree
+	Tar, // a single tar of the whole remapped tree
+	TarStdout // a single tar written to stdout
+}
+
+const PF_NONE: &str = "none";
+const PF_KIT_ZIP: &str = "kit-zip";
+const PF_ZIP: &str = "zip";
+const PF_TAR: &str = "tar";
+const PF_TAR_STDOUT: &str = "tar-stdout";
+
+impl PackFormat
+{
+	pub const NAMES: [&'static str; 5] = [PF_NONE, PF_KIT_ZIP, PF_ZIP, PF_TAR, PF_TAR_STDOUT];
+
+	pub fn name(&self) -> &'static str
+	{
+		match self
+		{
+			PackFormat::None => PF_NONE,
+			PackFormat::KitZip => PF_KIT_ZIP,
+			PackFormat::Zip => PF_ZIP,
+			PackFormat::Tar => PF_TAR,
+			PackFormat::TarStdout => PF_TAR_STDOUT
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<PackFormat>
+	{
+		match name
+		{
+			PF_NONE => Some(PackFormat::None),
+			PF_KIT_ZIP => Some(PackFormat::KitZip),
+			PF_ZIP => Some(PackFormat::Zip),
+			PF_TAR => Some(PackFormat::Tar),
+			PF_TAR_STDOUT => Some(PackFormat::TarStdout),
+			_ => None
+		}
+	}
+}
+
+// every entry gets the same mtime (1980-01-01, the earliest a zip can hold) and mode, so packing the same samples twice gives identical archives
+const ENTRY_MTIME: u64 = 315_532_800;
+const ENTRY_MODE: u32 = 0o644;
+
+enum Sink
+{
+	Zip(Box<zip::ZipWriter<File>>),
+	Tar(tar::Builder<Box<dyn Write>>)
+}
+
+impl Sink
+{
+	fn add(&mut self, name: &Path, mut source: Box<dyn Read>, size: u64) -> std::io::Result<()>
+	{
+		match self
+		{
+			Sink::Zip(zip) =>
+			{
+				let options = zip::write::SimpleFileOptions::default()
+				.compression_method(zip::CompressionMethod::Deflated)
+				.last_modified_time(zip::DateTime::default())
+				.unix_permissions(ENTRY_MODE)
+				.large_file(size > u64::from(u32::MAX));
+
+				zip.start_file(name.to_string_lossy(), options).map_err(std::io::Error::other)?;
+				std::io::copy(&mut source, zip).map(|_| ())
+			},
+			Sink::Tar(tar) =>
+			{
+				let mut header = tar::Header::new_gnu();
+
+				header.set_entry_type(tar::EntryType::Regular);
+				header.set_size(size);
+				header.set_mode(ENTRY_MODE);
+				header.set_mtime(ENTRY_MTIME);
+				header.set_uid(0);
+				header.set_gid(0);
+
+				tar.append_data(&mut header, name, source)
+			}
+		}
+	}
+
+	fn finish(self) -> std::io::Result<()>
+	{
+		match self
+		{
+			Sink::Zip(zip) => zip.finish().map(|_| ()).map_err(std::io::Error::other),
+			Sink::Tar(tar) => tar.into_inner()?.flush()
+		}
+	}
+}
+
+// content and size of a source - read out of it's archive if it lies inside of one
+fn open(source: &Path) -> std::io::Result<(Box<dyn Read>, u64)>
+{
+	if archive::split(source).is_some()
+	{
+		let content = archive::read(source)?;
+		let size = content.len() as u64;
+
+		return Ok((Box::new(std::io::Cursor::new(content)), size));
+	}
+
+	let file = File::open(source)?;
+	let size = file.metadata()?.len();
+
+	Ok((Box::new(std::io::BufReader::new(file)), size))
+}
+
+// path of the sample inside the archive - it's target-path relative to the output-directory, directories separated by /
+fn entry_name(sample: &Sample, rcon: &RuntimeConfig) -> PathBuf
+{
+	let relative = sample.target_path.strip_prefix(&rcon.output).unwrap_or(&sample.target_path);
+
+	PathBuf::from(relative.iter().map(|c| c.to_string_lossy()).collect::<Vec<_>>().join("/"))
+}
+
+// archive-file (None for stdout) and the samples packed into it
+fn archives<'a>(kits: &[&'a Kit], rcon: &RuntimeConfig) -> Vec<(Option<PathBuf>, Vec<&'a Sample>)>
+{
+	let all = || kits.iter().flat_map(|k| k.samples.iter()).collect::<Vec<&Sample>>();
+	let base = Path::new(&rcon.output).file_name().map_or(String::from("kits"), |n| n.to_string_lossy().to_string());
+
+	match rcon.pack
+	{
+		PackFormat::None => vec![],
+		PackFormat::KitZip => kits.iter().map(|k| (Some(ruleset::output_path(&rcon.output, &[&k.name, ".zip"].concat())), k.samples.iter().collect())).collect(),
+		PackFormat::Zip => vec![(Some(Path::new(&rcon.output).join([&base, ".zip"].concat())), all())],
+		PackFormat::Tar => vec![(Some(Path::new(&rcon.output).join([&base, ".tar"].concat())), all())],
+		PackFormat::TarStdout => vec![(None, all())]
+	}
+}
+
+// applies the existing-policy to an archive-file - true if it can be written
+fn is_free(path: &Path, rcon: &RuntimeConfig, journal: &mut Journal) -> bool
+{
+	if path.symlink_metadata().is_err()
+	{
+		return true;
+	}
+
+	match rcon.on_existing
+	{
+		ExistingPolicy::Skip => { info!("Archive {:?} already exists - skipping", path); false },
+		ExistingPolicy::Error => { error!("Archive {:?} already exists", path); false },
+		_ => match journal.remove(path)
+		{
+			Ok(_) => true,
+			Err(e) => { error!("Removing existing archive {:?} failed: {:?}", path, e); false }
+		}
+	}
+}
+
+// creates the archive-file (and it's directory) - journaled once it is finished
+fn create(path: &Path, format: PackFormat, journal: &mut Journal) -> std::io::Result<Sink>
+{
+	writer::create_dirs(&path.with_file_name(""), journal)?;
+
+	let file = File::create(path)?;
+
+	match format
+	{
+		PackFormat::KitZip | PackFormat::Zip => Ok(Sink::Zip(Box::new(zip::ZipWriter::new(file)))),
+		_ => Ok(Sink::Tar(tar::Builder::new(Box::new(BufWriter::new(file)))))
+	}
+}
+
+/// Packs the samples of the (sorted) kits into archives instead of copying/linking them: one zip per kit, a single zip or tar
+/// of the whole remapped tree inside the output-directory, or a tar to stdout. Entries are ordered by their path.
+pub fn write(kits: &[&Kit], rcon: &RuntimeConfig, journal: &mut Journal) -> WriteSummary
+{
+	let mut summary = WriteSummary::new();
+
+	for (path, samples) in archives(kits, rcon)
+	{
+		let mut entries: Vec<(PathBuf, &Sample)> = samples.into_iter().map(|s| (entry_name(s, rcon), s)).collect();
+
+		entries.sort_by(|a, b| a.0.cmp(&b.0));
+		entries.dedup_by(|a, b| a.0 == b.0);
+
+		let name = path.as_ref().map_or(String::from("stdout"), |p| format!("{:?}", p));
+
+		let mut sink = match &path
+		{
+			None => Sink::Tar(tar::Builder::new(Box::new(BufWriter::new(std::io::stdout())))),
+			Some(p) if !is_free(p, rcon, journal) =>
+			{
+				let outcome = if rcon.on_existing == ExistingPolicy::Skip { WriteOutcome::Skipped } else { WriteOutcome::Failed };
+
+				entries.iter().for_each(|_| summary.count(&outcome));
+				continue;
+			},
+			Some(p) => match create(p, rcon.pack, journal)
+			{
+				Ok(s) => s,
+				Err(e) =>
+				{
+					error!("Creating archive {} failed: {:?}", name, e);
+					entries.iter().for_each(|_| summary.count(&WriteOutcome::Failed));
+					continue;
+				}
+			}
+		};
+
+		info!("Packing {:?} samples into {}", entries.len(), name);
+
+		let mut entries = entries.iter();
+
+		for (entry, sample) in entries.by_ref()
+		{
+			// sources that can't be opened are left out - a failure while writing breaks the rest of the archive
+			let (source, size) = match open(&sample.source_path)
+			{
+				Ok(s) => s,
+				Err(e) => { error!("Reading {:?} failed: {:?}", sample.source_path, e); summary.count(&WriteOutcome::Failed); continue }
+			};
+
+			debug!("Packing {:?} as {:?}", sample.source_path, entry);
+
+			if let Err(e) = sink.add(entry, source, size)
+			{
+				error!("Writing {:?} into archive {} failed: {:?}", entry, name, e);
+				summary.count(&WriteOutcome::Failed);
+				break;
+			}
+
+			summary.count(&WriteOutcome::Written);
+		}
+
+		entries.for_each(|_| summary.count(&WriteOutcome::Failed));
+
+		if let Err(e) = sink.finish()
+		{
+			error!("Finishing archive {} failed: {:?}", name, e);
+		}
+
+		if let Some(p) = &path
+		{
+			journal.created(p);
+		}
+	}
+
+	info!("{}", summary);
+	summary
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use std::collections::HashMap;
+	use crate::runtimeconfig;
+
+	// input- and output-directory of a test below the temporary directory, with two kits of samples
+	fn setup(name: &str) -> (PathBuf, PathBuf, Vec<Kit>)
+	{
+		let root = std::env::temp_dir().join(format!("kitcat-pack-{}-{}", name, std::process::id()));
+		let (input, output) = (root.join("in"), root.join("kits"));
+		let sample = |name: &str, kit: &str| Sample { source_path: input.join(name), target_path: output.join(kit).join(name), fields: HashMap::new() };
+
+		let _ = std::fs::remove_dir_all(&root);
+		std::fs::create_dir_all(&input).unwrap();
+		std::fs::create_dir_all(&output).unwrap();
+
+		for name in ["Snare.wav", "Kick.wav", "Hat.wav"]
+		{
+			std::fs::write(input.join(name), name.repeat(100)).unwrap();
+		}
+
+		let kits = vec![
+			Kit { name: "KitA".to_string(), samples: vec![sample("Snare.wav", "KitA"), sample("Kick.wav", "KitA")] },
+			Kit { name: "KitB".to_string(), samples: vec![sample("Hat.wav", "KitB")] }
+		];
+
+		(input, output, kits)
+	}
+
+	fn pack(input: &Path, output: &Path, kits: &[Kit], format: &str) -> WriteSummary
+	{
+		let args = ["kitcat", "-i", &input.to_string_lossy(), "-o", &output.to_string_lossy(), "--pack", format, "--on-existing", "overwrite"];
+		let rcon = runtimeconfig::process_args(args.iter().map(|a| a.to_string()).collect()).unwrap();
+		let kits: Vec<&Kit> = kits.iter().collect();
+
+		write(&kits, &rcon, &mut Journal::new(&rcon.output))
+	}
+
+	#[test]
+	fn packing_the_same_kits_twice_gives_identical_archives()
+	{
+		let (input, output, kits) = setup("twice");
+
+		for (format, file) in [("zip", "kits.zip"), ("tar", "kits.tar")]
+		{
+			pack(&input, &output, &kits, format);
+
+			let first = std::fs::read(output.join(file)).unwrap();
+
+			std::thread::sleep(std::time::Duration::from_millis(1100)); // a later mtime of sources and archive must not show
+			std::fs::write(input.join("Kick.wav"), "Kick.wav".repeat(100)).unwrap();
+
+			assert_eq!(pack(&input, &output, &kits, format).written, 3);
+			assert!(std::fs::read(output.join(file)).unwrap() == first, "{} differs", file);
+		}
+
+		let _ = std::fs::remove_dir_all(input.parent().unwrap());
+	}
+
+	#[test]
+	fn entries_are_the_target_paths_relative_to_the_output()
+	{
+		let (input, output, kits) = setup("entries");
+
+		pack(&input, &output, &kits, "tar");
+
+		assert_eq!(archive::members(&output.join("kits.tar")).unwrap(), [Path::new("KitA/Kick.wav"), Path::new("KitA/Snare.wav"), Path::new("KitB/Hat.wav")]);
+		assert_eq!(archive::read(&output.join("kits.tar/KitB/Hat.wav")).unwrap(), "Hat.wav".repeat(100).as_bytes());
+
+		let _ = std::fs::remove_dir_all(input.parent().unwrap());
+	}
+
+	#[test]
+	fn kit_zips_hold_the_samples_of_their_kit_and_missing_sources_are_left_out()
+	{
+		let (input, output, kits) = setup("kit-zip");
+
+		std::fs::remove_file(input.join("Snare.wav")).unwrap();
+
+		let summary = pack(&input, &output, &kits, "kit-zip");
+
+		assert_eq!((summary.written, summary.failed), (2, 1));
+		assert_eq!(archive::members(&output.join("KitA.zip")).unwrap(), [Path::new("KitA/Kick.wav")]);
+		assert_eq!(archive::members(&output.join("KitB.zip")).unwrap(), [Path::new("KitB/Hat.wav")]);
+
+		let _ = std::fs::remove_dir_all(input.parent().unwrap());
+	}
+}
@@ -0,0 +1,163 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use crate::ignore::{Dir, Entry, WalkFilter};
+
+/// Count of workers to use if none is configured.
+pub fn default_workers() -> usize
+{
+	std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Applies f to every item using up to the given count of workers.
+/// The results are returned in the order of the items, no matter which worker finished first.
+pub fn map<T, R, F>(items: &[T], workers: usize, f: F) -> Vec<R>
+where
+	T: Sync,
+	R: Send,
+	F: Fn(&T) -> R + Sync
+{
+	let workers = workers.clamp(1, items.len().max(1));
+
+	if workers == 1
+	{
+		return items.iter().map(f).collect();
+	}
+
+	let next = AtomicUsize::new(0);
+	let mut results: Vec<(usize, R)> = Vec::with_capacity(items.len());
+
+	std::thread::scope(|scope|
+	{
+		let handles: Vec<_> = (0..workers).map(|_| scope.spawn(||
+		{
+			let mut done: Vec<(usize, R)> = vec![];
+
+			loop
+			{
+				let i = next.fetch_add(1, Ordering::Relaxed);
+
+				match items.get(i)
+				{
+					None => return done,
+					Some(item) => done.push((i, f(item)))
+				}
+			}
+		})).collect();
+
+		for handle in handles
+		{
+			results.append(&mut handle.join().expect("worker panicked"));
+		}
+	});
+
+	results.sort_by_key(|(i, _)| *i);
+	results.into_iter().map(|(_, r)| r).collect()
+}
+
+/// Entry found by walk - a regular file, a directory that could not be read or an entry that was left out.
+pub enum Walked
+{
+	File(PathBuf),
+	Unreadable(PathBuf, std::io::Error),
+	Excluded(PathBuf),
+	Skipped(PathBuf, String)
+}
+
+struct WalkState
+{
+	pending: Vec<Dir>, // directories not read yet
+	busy: usize, // workers currently reading a directory
+	found: Vec<Walked>
+}
+
+/// Recursively collects all regular files below root that pass the filter, using up to the given count of workers.
+/// The result is sorted by path, so it does not depend on the order the directories were read in.
+pub fn walk(root: &Path, filter: &WalkFilter, workers: usize) -> Vec<Walked>
+{
+	let state = Mutex::new(WalkState { pending: vec![filter.root(root)], busy: 0, found: vec![] });
+	let wakeup = Condvar::new();
+
+	let work = ||
+	{
+		let mut guard = state.lock().unwrap();
+
+		loop
+		{
+			let dir = match guard.pending.pop()
+			{
+				Some(d) => d,
+				None if guard.busy == 0 => { wakeup.notify_all(); return },
+				None => { guard = wakeup.wait(guard).unwrap(); continue }
+			};
+
+			guard.busy+=1;
+			drop(guard);
+
+			let (dirs, mut found) = read_dir(&dir, filter);
+
+			guard = state.lock().unwrap();
+			guard.busy-=1;
+			guard.pending.extend(dirs);
+			guard.found.append(&mut found);
+			wakeup.notify_all();
+		}
+	};
+
+	if workers <= 1
+	{
+		work();
+	}
+	else
+	{
+		std::thread::scope(|scope|
+		{
+			for _ in 0..workers
+			{
+				scope.spawn(work);
+			}
+		});
+	}
+
+	let mut out = state.into_inner().unwrap().found;
+
+	out.sort_by(|a, b| walked_path(a).cmp(walked_path(b)));
+	out
+}
+
+fn walked_path(walked: &Walked) -> &Path
+{
+	match walked
+	{
+		Walked::File(p) => p,
+		Walked::Unreadable(p, _) => p,
+		Walked::Excluded(p) => p,
+		Walked::Skipped(p, _) => p
+	}
+}
+
+// sub-directories and files of a single directory
+fn read_dir(dir: &Dir, filter: &WalkFilter) -> (Vec<Dir>, Vec<Walked>)
+{
+	let mut dirs: Vec<Dir> = vec![];
+	let mut found: Vec<Walked> = vec![];
+
+	let entries = match filter.read(dir)
+	{
+		Err(e) => { found.push(Walked::Unreadable(dir.path.to_path_buf(), e)); return (dirs, found) },
+		Ok(e) => e
+	};
+
+	for entry in entries
+	{
+		match entry
+		{
+			Entry::Dir(d) => dirs.push(d),
+			Entry::File(p) => found.push(Walked::File(p)),
+			Entry::Excluded(p) => found.push(Walked::Excluded(p)),
+			Entry::Skipped(p, reason) => found.push(Walked::Skipped(p, reason))
+		}
+	}
+
+	(dirs, found)
+}
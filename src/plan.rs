@@ -0,0 +1,503 @@
+extern crate log;
+	use log::*;
+
+extern crate serde;
+	use serde::{Deserialize, Serialize};
+
+use std::collections::*;
+use std::path::{Component, Path, PathBuf};
+use crate::runtimeconfig::RuntimeConfig;
+use crate::version;
+use crate::error;
+	use crate::error::{Error, Result};
+use crate::catalog::{Kit, Sample};
+use crate::manifest;
+	use crate::manifest::Manifest;
+use crate::journal::Journal;
+use crate::writer;
+	use crate::writer::{CopyOptions, SoftLinks, WriteMode, WriteSummary};
+use crate::ruleset;
+use crate::archive;
+use crate::roots;
+	use crate::roots::Root;
+
+/// Reviewable mapping of a run - created by `kitcat plan`, executed by `kitcat apply`.
+#[derive(Serialize, Deserialize)]
+pub struct Plan
+{
+	pub kitcat: String, // version that created the plan
+	pub ruleset: String, // fingerprint of the ruleset that created the plan
+	pub input: String, // input-directories, separated by ", " if there are several
+	pub output: String,
+	pub entries: Vec<PlanEntry>
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PlanEntry
+{
+	pub kit: String,
+	#[serde(with = "crate::ospath")]
+	pub source: PathBuf,
+	#[serde(with = "crate::ospath")]
+	pub target: PathBuf,
+	pub mode: WriteMode,
+	pub fields: BTreeMap<String, String>
+}
+
+impl std::fmt::Debug for Plan
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [PLAN] kitcat: {:?} ruleset: {:?} input: {:?} output: {:?} entries: {:?} }}",
+    		self.kitcat,
+    		self.ruleset,
+    		self.input,
+    		self.output,
+    		self.entries
+		)
+	}
+}
+
+impl std::fmt::Debug for PlanEntry
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [PLANENTRY] kit: {:?} source: {:?} target: {:?} mode: {:?} fields: {:?} }}",
+    		self.kit,
+    		self.source,
+    		self.target,
+    		self.mode,
+    		self.fields
+		)
+	}
+}
+
+impl PlanEntry
+{
+	pub fn to_sample(&self) -> Sample
+	{
+		Sample {
+			source_path: self.source.to_path_buf(),
+			target_path: self.target.to_path_buf(),
+			fields: self.fields.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+		}
+	}
+}
+
+// metadata-lines of csv-plans start with this prefix, followed by key=value
+const CSV_META: &str = "# ";
+const CSV_COLUMNS: [&str; 4] = ["kit", "source", "target", "mode"];
+// the columns following CSV_COLUMNS hold the fields of each entry, named like placeholders ({group})
+
+/// Creates the plan of the (filtered) kits, sorted by kit and target-path.
+pub fn create(kits: &HashMap<String, Kit>, roots: &[Root], rcon: &RuntimeConfig) -> Plan
+{
+	let mode = WriteMode::from_config(rcon);
+	let mut entries: Vec<PlanEntry> = vec![];
+
+	for kit in kits.values()
+	{
+		for sample in kit.samples.iter()
+		{
+			entries.push(PlanEntry {
+				kit: kit.name.to_string(),
+				source: sample.source_path.to_path_buf(),
+				target: sample.target_path.to_path_buf(),
+				mode,
+				fields: sample.fields.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+			});
+		}
+	}
+
+	entries.sort_by(|a, b| (&a.kit, &a.target).cmp(&(&b.kit, &b.target)));
+
+	Plan {
+		kitcat: version::APP_VERSION.to_string(),
+		ruleset: roots::fingerprint(roots),
+		input: roots.iter().map(|r| r.rcon.input.as_str()).collect::<Vec<&str>>().join(", "),
+		output: rcon.output.to_string(),
+		entries
+	}
+}
+
+fn is_csv(path: &str) -> bool
+{
+	Path::new(path).extension().is_some_and(|e| e.eq_ignore_ascii_case("csv"))
+}
+
+fn plan_error(file: &str, reason: &str) -> Error
+{
+	Error::Plan { file: file.to_string(), reason: reason.to_string() }
+}
+
+/// Writes the plan as csv (if the path ends with .csv) or json.
+pub fn save(plan: &Plan, to_file: &str) -> Result<()>
+{
+	let content = if is_csv(to_file)
+	{
+		to_csv(plan).map_err(|reason| plan_error(to_file, &reason))?
+	}
+	else
+	{
+		serde_json::to_string_pretty(plan).map_err(|e| plan_error(to_file, &e.to_string()))?
+	};
+
+	std::fs::write(to_file, content).map_err(|e| error::io(to_file, e))?;
+	info!("Saved plan with {:?} entries to {:?}", plan.entries.len(), to_file);
+
+	Ok(())
+}
+
+/// Reads a plan written by save (or edited by hand).
+pub fn load(from_file: &str) -> Result<Plan>
+{
+	let content = std::fs::read_to_string(from_file).map_err(|e| error::io(from_file, e))?;
+
+	if is_csv(from_file)
+	{
+		from_csv(&content).map_err(|reason| plan_error(from_file, &reason))
+	}
+	else
+	{
+		serde_json::from_str(&content).map_err(|e| plan_error(from_file, &e.to_string()))
+	}
+}
+
+// true if the target lies below the output-directory (but not inside it's state-directory) - edited plans may point anywhere
+fn is_inside(target: &Path, output: &str) -> bool
+{
+	!output.is_empty()
+	&& target.starts_with(output)
+	&& target != Path::new(output)
+	&& !target.starts_with(manifest::state_dir(output))
+	&& !target.components().any(|c| c == Component::ParentDir)
+}
+
+/// Writes all entries of the plan. Nothing is written if the plan was created by a different ruleset
+/// (unless forced), if any target lies outside of the output-directory of the plan or if any source of the plan does not exist anymore.
+pub fn apply(plan: &Plan, roots: &[Root], rcon: &RuntimeConfig) -> Result<WriteSummary>
+{
+	let fingerprint = roots::fingerprint(roots);
+
+	if plan.ruleset != fingerprint
+	{
+		if !rcon.force
+		{
+			return Err(Error::RulesetMismatch { plan: plan.ruleset.to_string(), ruleset: fingerprint });
+		}
+
+		warn!("Plan was created by ruleset {:?}, current ruleset is {:?} - applying anyway", plan.ruleset, fingerprint);
+	}
+
+	let outside: Vec<&PlanEntry> = plan.entries.iter().filter(|e| !is_inside(&e.target, &plan.output)).collect();
+
+	for entry in outside.iter()
+	{
+		error!("Target {:?} of plan-entry {:?} lies outside of {:?}", entry.target, entry.source, plan.output);
+	}
+
+	if !outside.is_empty()
+	{
+		return Err(Error::OutsideOutput { count: outside.len(), output: plan.output.to_string() });
+	}
+
+	let mut listed: HashMap<PathBuf, HashSet<PathBuf>> = HashMap::new(); // members of the archives sources lie in
+	let missing: Vec<&PlanEntry> = plan.entries.iter().filter(|e| !archive::exists(&e.source, &mut listed)).collect();
+
+	for entry in missing.iter()
+	{
+		error!("Source {:?} of plan-entry {:?} does not exist", entry.source, entry.target);
+	}
+
+	if !missing.is_empty()
+	{
+		return Err(Error::MissingSources { count: missing.len() });
+	}
+
+	let mut summary = WriteSummary::new();
+
+	if rcon.dry
+	{
+		info!("Not writing {:?} plan-entries", plan.entries.len());
+
+		return Ok(summary);
+	}
+
+	info!("Writing {:?} plan-entries", plan.entries.len());
+
+	let mut manifest = Manifest::load(&plan.output)?;
+	let mut journal = Journal::new(&plan.output);
+
+	manifest.set_ruleset(&plan.ruleset);
+
+	let samples: Vec<Sample> = plan.entries.iter().map(|e| e.to_sample()).collect();
+	let soft_links = SoftLinks::from_config(rcon);
+	let copies = CopyOptions::from_config(rcon);
+	let jobs: Vec<(&Sample, WriteMode)> = samples.iter().zip(plan.entries.iter()).map(|(s, e)| (s, e.mode)).collect();
+
+	writer::check_space(&jobs, rcon.on_existing)?;
+
+	let outcomes = writer::write_samples(&jobs, rcon.on_existing, &soft_links, &copies, &mut journal, rcon.workers());
+
+	for ((sample, entry), (outcome, used)) in samples.iter().zip(plan.entries.iter()).zip(outcomes)
+	{
+		manifest.record_outcome(&entry.kit, sample, used, &outcome, rcon.hash);
+		summary.count_sample(sample, &outcome, used);
+	}
+
+	info!("{}", summary);
+	journal.save()?;
+	manifest.save(&plan.output)?;
+
+	Ok(summary)
+}
+
+fn csv_escape(value: &str) -> String
+{
+	if value.contains([',', '"', '\n', '\r']) || value.trim() != value
+	{
+		return format!("\"{}\"", value.replace('"', "\"\""));
+	}
+
+	value.to_string()
+}
+
+// paths that are not valid UTF-8 can't be written to csv without changing them
+fn csv_path(path: &Path) -> std::result::Result<String, String>
+{
+	match path.to_str()
+	{
+		None => Err(format!("path {:?} is not valid UTF-8 - save the plan as json instead", path)),
+		Some(p) => Ok(csv_escape(p))
+	}
+}
+
+fn to_csv(plan: &Plan) -> std::result::Result<String, String>
+{
+	let mut out = String::new();
+	let fields: BTreeSet<&String> = plan.entries.iter().flat_map(|e| e.fields.keys()).collect();
+
+	for (key, value) in [("kitcat", &plan.kitcat), ("ruleset", &plan.ruleset), ("input", &plan.input), ("output", &plan.output)].iter()
+	{
+		out.push_str(&format!("{}{}={}\n", CSV_META, key, value));
+	}
+
+	let header: Vec<String> = CSV_COLUMNS.iter().map(|c| c.to_string()).chain(fields.iter().map(|f| csv_escape(&ruleset::with_brackets(f)))).collect();
+	out.push_str(&header.join(","));
+	out.push('\n');
+
+	for entry in plan.entries.iter()
+	{
+		let mut row: Vec<String> = vec![csv_escape(&entry.kit), csv_path(&entry.source)?, csv_path(&entry.target)?, entry.mode.name().to_string()];
+
+		for field in fields.iter()
+		{
+			row.push(csv_escape(entry.fields.get(*field).map_or("", |v| v.as_str())));
+		}
+
+		out.push_str(&row.join(","));
+		out.push('\n');
+	}
+
+	Ok(out)
+}
+
+fn parse_csv_records(content: &str) -> Vec<Vec<String>>
+{
+	let mut out: Vec<Vec<String>> = vec![];
+	let mut record: Vec<String> = vec![];
+	let mut value = String::new();
+	let mut quoted = false;
+	let mut chars = content.chars().peekable();
+
+	while let Some(c) = chars.next()
+	{
+		match c
+		{
+			'"' if quoted && chars.peek() == Some(&'"') => { value.push('"'); chars.next(); },
+			'"' => quoted = !quoted,
+			',' if !quoted => record.push(std::mem::take(&mut value)),
+			'\r' if !quoted => {},
+			'\n' if !quoted =>
+			{
+				record.push(std::mem::take(&mut value));
+				out.push(std::mem::take(&mut record));
+			},
+			_ => value.push(c)
+		}
+	}
+
+	if !value.is_empty() || !record.is_empty()
+	{
+		record.push(value);
+		out.push(record);
+	}
+
+	out.retain(|r| !(r.len() == 1 && r[0].trim().is_empty()));
+	out
+}
+
+fn from_csv(content: &str) -> std::result::Result<Plan, String>
+{
+	let mut meta: HashMap<String, String> = HashMap::new();
+	let mut body = content;
+
+	while let Some(line) = body.strip_prefix(CSV_META)
+	{
+		let (entry, rest) = line.split_once('\n').unwrap_or((line, ""));
+
+		if let Some((key, value)) = entry.trim_end_matches('\r').split_once('=')
+		{
+			meta.insert(key.trim().to_string(), value.to_string());
+		}
+
+		body = rest;
+	}
+
+	let mut records = parse_csv_records(body).into_iter();
+
+	let header = match records.next()
+	{
+		None => return Err("missing header-line".to_string()),
+		Some(h) => h
+	};
+
+	if header.len() < CSV_COLUMNS.len() || header[..CSV_COLUMNS.len()] != CSV_COLUMNS
+	{
+		return Err(format!("header-line must start with {}", CSV_COLUMNS.join(",")));
+	}
+
+	let mut entries: Vec<PlanEntry> = vec![];
+
+	for (line, record) in records.enumerate()
+	{
+		if record.len() != header.len()
+		{
+			return Err(format!("entry {} has {} columns, header has {}", line + 1, record.len(), header.len()));
+		}
+
+		let mode = match WriteMode::from_name(&record[3])
+		{
+			None => return Err(format!("entry {} has unknown mode {:?}", line + 1, record[3])),
+			Some(m) => m
+		};
+
+		entries.push(PlanEntry {
+			kit: record[0].to_string(),
+			source: PathBuf::from(&record[1]),
+			target: PathBuf::from(&record[2]),
+			mode,
+			fields: header.iter().zip(record.iter()).skip(CSV_COLUMNS.len()).map(|(k, v)| (k.trim_start_matches('{').trim_end_matches('}').to_string(), v.to_string())).collect()
+		});
+	}
+
+	let get = |key: &str| meta.get(key).cloned().unwrap_or_default();
+	let require = |key: &str| match meta.get(key)
+	{
+		Some(value) if !value.trim().is_empty() => Ok(value.to_string()),
+		_ => Err(format!("missing metadata-line {}{}=", CSV_META, key))
+	};
+
+	Ok(Plan {
+		kitcat: get("kitcat"),
+		ruleset: require("ruleset")?,
+		input: get("input"),
+		output: require("output")?,
+		entries
+	})
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	const META: &str = "# kitcat=v1.0.0\n# ruleset=abc\n# input=/in\n# output=/out\n";
+
+	fn entry(kit: &str, source: &str, target: &str) -> PlanEntry
+	{
+		PlanEntry { kit: kit.to_string(), source: PathBuf::from(source), target: PathBuf::from(target), mode: WriteMode::Hard, fields: BTreeMap::new() }
+	}
+
+	#[test]
+	fn records_keep_quoted_separators_and_escaped_quotes()
+	{
+		let records = parse_csv_records("a,\"b,c\",\"say \"\"hi\"\"\"\r\n\"multi\nline\",,x\n\n");
+
+		assert_eq!(records, vec![
+			vec!["a".to_string(), "b,c".to_string(), "say \"hi\"".to_string()],
+			vec!["multi\nline".to_string(), String::new(), "x".to_string()]
+		]);
+	}
+
+	#[test]
+	fn last_record_without_newline_is_kept()
+	{
+		assert_eq!(parse_csv_records("a,b\nc,d"), vec![vec!["a", "b"], vec!["c", "d"]]);
+	}
+
+	#[test]
+	fn plans_survive_a_csv_round_trip()
+	{
+		let mut quoted = entry("Kit, \"A\"", "/in/Kick, 1.wav", "/out/Kit, \"A\"/Kick.wav");
+		quoted.fields.insert("group".to_string(), " Kick ".to_string());
+
+		let plan = Plan { kitcat: "v1.0.0".to_string(), ruleset: "abc".to_string(), input: "/in".to_string(), output: "/out".to_string(), entries: vec![quoted, entry("KitB", "/in/b.wav", "/out/KitB/b.wav")] };
+		let loaded = from_csv(&to_csv(&plan).unwrap()).unwrap();
+
+		assert_eq!((loaded.ruleset.as_str(), loaded.output.as_str()), ("abc", "/out"));
+		assert_eq!(loaded.entries.len(), 2);
+		assert_eq!(loaded.entries[0].kit, "Kit, \"A\"");
+		assert_eq!(loaded.entries[0].target, Path::new("/out/Kit, \"A\"/Kick.wav"));
+		assert_eq!(loaded.entries[0].fields["group"], " Kick ");
+		assert_eq!(loaded.entries[1].fields["group"], "");
+	}
+
+	#[test]
+	fn missing_or_wrong_header_is_refused()
+	{
+		assert_eq!(from_csv(META).err().unwrap(), "missing header-line");
+		assert!(from_csv(&format!("{}source,kit,target,mode\n", META)).err().unwrap().starts_with("header-line must start with"));
+	}
+
+	#[test]
+	fn missing_output_or_ruleset_is_refused()
+	{
+		let body = "kit,source,target,mode\nKitA,/in/a.wav,/out/KitA/a.wav,hard\n";
+
+		assert_eq!(from_csv(&format!("# ruleset=abc\n{}", body)).err().unwrap(), "missing metadata-line # output=");
+		assert_eq!(from_csv(&format!("# ruleset=abc\n# output=\n{}", body)).err().unwrap(), "missing metadata-line # output=");
+		assert_eq!(from_csv(&format!("# output=/out\n{}", body)).err().unwrap(), "missing metadata-line # ruleset=");
+		assert!(from_csv(&format!("{}{}", META, body)).is_ok());
+	}
+
+	#[test]
+	fn entries_need_all_columns_and_a_known_mode()
+	{
+		assert_eq!(from_csv(&format!("{}kit,source,target,mode\nKitA,/in/a.wav,hard\n", META)).err().unwrap(), "entry 1 has 3 columns, header has 4");
+		assert_eq!(from_csv(&format!("{}kit,source,target,mode\nKitA,/in/a.wav,/out/a.wav,fold\n", META)).err().unwrap(), "entry 1 has unknown mode \"fold\"");
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn non_utf8_paths_are_not_written_to_csv()
+	{
+		use std::os::unix::ffi::OsStrExt;
+
+		let mut plan = Plan { kitcat: String::new(), ruleset: "abc".to_string(), input: "/in".to_string(), output: "/out".to_string(), entries: vec![entry("KitA", "/in/a.wav", "/out/KitA/a.wav")] };
+		plan.entries[0].source = PathBuf::from(std::ffi::OsStr::from_bytes(b"/in/\xff.wav"));
+
+		assert!(to_csv(&plan).err().unwrap().contains("is not valid UTF-8"));
+	}
+
+	#[test]
+	fn targets_must_lie_inside_the_output()
+	{
+		assert!(is_inside(Path::new("/out/KitA/a.wav"), "/out"));
+		assert!(!is_inside(Path::new("/out/../etc/a.wav"), "/out"));
+		assert!(!is_inside(Path::new("/etc/a.wav"), "/out"));
+		assert!(!is_inside(Path::new("/out"), "/out"));
+		assert!(!is_inside(Path::new("/out/.kitcat/manifest.json"), "/out"));
+		assert!(!is_inside(Path::new("KitA/a.wav"), ""));
+	}
+}
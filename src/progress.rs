@@ -0,0 +1,231 @@
+extern crate log;
+	use log::*;
+
+extern crate serde;
+	use serde::{Deserialize, Serialize};
+
+use std::collections::*;
+use std::path::{Path, PathBuf};
+use crate::version;
+use crate::error;
+	use crate::error::{Error, Result};
+use crate::manifest;
+	use crate::manifest::Manifest;
+use crate::catalog::{Kit, Sample};
+use crate::writer::WriteSummary;
+
+// record of the last unfinished run - removed once a run writes all of it's samples
+const PROGRESS_FILE: &str = "progress.json";
+
+/// Samples the last unfinished run (interrupted or with failed samples) of an output-directory completed - continued by --resume.
+#[derive(Serialize, Deserialize)]
+pub struct Progress
+{
+	pub kitcat: String,
+	pub run: String, // id of the run (and it's journal)
+	pub interrupted: bool,
+	pub unfinished: usize, // samples that failed or were not started
+	pub completed: BTreeSet<String> // target-paths of the samples written by the run - and by the runs it resumed
+}
+
+impl std::fmt::Debug for Progress
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [PROGRESS] kitcat: {:?} run: {:?} interrupted: {:?} unfinished: {:?} completed: {:?} }}",
+    		self.kitcat,
+    		self.run,
+    		self.interrupted,
+    		self.unfinished,
+    		self.completed.len()
+		)
+	}
+}
+
+fn progress_path(output: &str) -> PathBuf
+{
+	manifest::state_dir(output).join(PROGRESS_FILE)
+}
+
+fn progress_error(file: &Path, reason: &str) -> Error
+{
+	Error::Progress { file: file.to_string_lossy().to_string(), reason: reason.to_string() }
+}
+
+impl Progress
+{
+	/// Reads the record of the last unfinished run of the output-directory - None if the last run finished.
+	pub fn load(output: &str) -> Result<Option<Progress>>
+	{
+		let path = progress_path(output);
+
+		if !path.exists()
+		{
+			return Ok(None);
+		}
+
+		let content = std::fs::read_to_string(&path).map_err(|e| error::io(&path, e))?;
+
+		serde_json::from_str(&content).map(Some).map_err(|e| progress_error(&path, &e.to_string()))
+	}
+
+	/// True if the sample was written by the unfinished run and is still current (same source, target unchanged since).
+	pub fn is_completed(&self, sample: &Sample, manifest: &Manifest) -> bool
+	{
+		self.completed.contains(sample.target_path.to_string_lossy().as_ref()) && manifest.is_current(sample)
+	}
+}
+
+/// Records the progress of a (non-dry) run: the samples it completed - added to the ones of the resumed run - if it was interrupted
+/// or samples failed, otherwise the record of an unfinished run is removed.
+pub fn save(output: &str, run: &str, summary: &WriteSummary, resumed: Option<Progress>) -> Result<()>
+{
+	let path = progress_path(output);
+	let unfinished = summary.failed + summary.interrupted;
+
+	if unfinished == 0
+	{
+		if path.exists()
+		{
+			std::fs::remove_file(&path).map_err(|e| error::io(&path, e))?;
+			info!("Removed progress-record {:?} - all samples are written", path);
+		}
+
+		return Ok(());
+	}
+
+	let mut completed = resumed.map_or_else(BTreeSet::new, |p| p.completed);
+
+	completed.extend(summary.completed.iter().cloned());
+
+	let progress = Progress { kitcat: version::APP_VERSION.to_string(), run: run.to_string(), interrupted: summary.interrupted > 0, unfinished, completed };
+	let content = serde_json::to_string_pretty(&progress).map_err(|e| progress_error(&path, &e.to_string()))?;
+	let dir = manifest::state_dir(output);
+
+	std::fs::create_dir_all(&dir).map_err(|e| error::io(&dir, e))?;
+	std::fs::write(&path, content).map_err(|e| error::io(&path, e))?;
+	info!("Saved progress of run {:?} ({:?} samples completed, {:?} unfinished) to {:?}", run, progress.completed.len(), unfinished, path);
+
+	Ok(())
+}
+
+/// Removes the samples completed by the unfinished run from the kits - returns their count.
+pub fn skip_completed(processed_dataset: &mut HashMap<String, Kit>, progress: &Progress, manifest: &Manifest) -> usize
+{
+	let mut completed: usize = 0;
+
+	for kit in processed_dataset.values_mut()
+	{
+		kit.samples.retain(|sample|
+		{
+			let done = progress.is_completed(sample, manifest);
+
+			if done
+			{
+				debug!("{:?} was completed by run {}", sample.target_path, progress.run);
+				completed+=1;
+			}
+
+			!done
+		});
+	}
+
+	processed_dataset.retain(|_name, kit| !kit.samples.is_empty());
+
+	completed
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use crate::journal::Journal;
+	use crate::runtimeconfig;
+	use crate::writer::{self, CopyOptions, ExistingPolicy, SoftLinks, WriteMode};
+
+	// input- and output-directory of a test below the temporary directory - the source of Snare.wav is missing
+	fn setup(name: &str) -> (PathBuf, String)
+	{
+		let root = std::env::temp_dir().join(format!("kitcat-progress-{}-{}", name, std::process::id()));
+		let (input, output) = (root.join("in"), root.join("out"));
+
+		let _ = std::fs::remove_dir_all(&root);
+		std::fs::create_dir_all(&input).unwrap();
+		std::fs::create_dir_all(&output).unwrap();
+		std::fs::write(input.join("Kick.wav"), b"kick").unwrap();
+
+		(input, output.to_string_lossy().to_string())
+	}
+
+	fn kits(input: &Path, output: &str) -> HashMap<String, Kit>
+	{
+		let sample = |name: &str| Sample { source_path: input.join(name), target_path: Path::new(output).join("KitA").join(name), fields: HashMap::new() };
+
+		HashMap::from([("KitA".to_string(), Kit { name: "KitA".to_string(), samples: vec![sample("Kick.wav"), sample("Snare.wav")] })])
+	}
+
+	// writes the kits like a run and records it's progress
+	fn run(input: &Path, output: &str, kits: &HashMap<String, Kit>, resumed: Option<Progress>) -> WriteSummary
+	{
+		let args = ["kitcat", "-i", &input.to_string_lossy(), "-o", output, "--copy"];
+		let rcon = runtimeconfig::process_args(args.iter().map(|a| a.to_string()).collect()).unwrap();
+		let mut manifest = Manifest::load(output).unwrap();
+		let mut journal = Journal::new(output);
+		let mut summary = WriteSummary::new();
+
+		for sample in kits.values().flat_map(|k| k.samples.iter())
+		{
+			let (outcome, used) = writer::write_sample(sample, WriteMode::Copy, ExistingPolicy::Error, &SoftLinks::from_config(&rcon), &CopyOptions::from_config(&rcon), &mut journal);
+
+			manifest.record_outcome("KitA", sample, used, &outcome, false);
+			summary.count_sample(sample, &outcome, used);
+		}
+
+		journal.save().unwrap();
+		manifest.save(output).unwrap();
+		save(output, &journal.run, &summary, resumed).unwrap();
+		summary
+	}
+
+	#[test]
+	fn resuming_skips_the_samples_the_unfinished_run_completed()
+	{
+		let (input, output) = setup("resume");
+
+		assert_eq!(run(&input, &output, &kits(&input, &output), None).failed, 1);
+
+		let progress = Progress::load(&output).unwrap().unwrap();
+		let manifest = Manifest::load(&output).unwrap();
+		let mut resumed = kits(&input, &output);
+
+		assert_eq!((progress.interrupted, progress.unfinished, progress.completed.len()), (false, 1, 1));
+		assert_eq!(skip_completed(&mut resumed, &progress, &manifest), 1);
+		assert_eq!(resumed["KitA"].samples.iter().map(|s| s.source_path.to_path_buf()).collect::<Vec<_>>(), [input.join("Snare.wav")]);
+
+		std::fs::write(input.join("Snare.wav"), b"snare").unwrap();
+
+		let summary = run(&input, &output, &resumed, Some(progress));
+
+		assert_eq!((summary.written, summary.failed), (1, 0));
+		assert!(Progress::load(&output).unwrap().is_none());
+
+		let _ = std::fs::remove_dir_all(input.parent().unwrap());
+	}
+
+	#[test]
+	fn completed_samples_whose_source_changed_are_written_again()
+	{
+		let (input, output) = setup("changed");
+
+		run(&input, &output, &kits(&input, &output), None);
+		std::fs::write(input.join("Kick.wav"), b"a longer kick").unwrap();
+
+		let progress = Progress::load(&output).unwrap().unwrap();
+		let mut resumed = kits(&input, &output);
+
+		assert_eq!(skip_completed(&mut resumed, &progress, &Manifest::load(&output).unwrap()), 0);
+		assert_eq!(resumed["KitA"].samples.len(), 2);
+
+		let _ = std::fs::remove_dir_all(input.parent().unwrap());
+	}
+}
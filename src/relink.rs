@@ -0,0 +1,127 @@
+extern crate log;
+	use log::*;
+
+use std::path::{Component, Path, PathBuf};
+use crate::runtimeconfig::RuntimeConfig;
+use crate::manifest::Manifest;
+use crate::journal::Journal;
+use crate::writer;
+	use crate::writer::{SoftLinks, WriteMode};
+
+pub struct RelinkReport
+{
+	pub relinked: usize,
+	pub intact: usize, // links that still resolve
+	pub missing: usize, // sources not found below the new location
+	pub changed: usize, // links replaced or changed since they were written - not touched
+	pub failed: usize
+}
+
+impl std::fmt::Debug for RelinkReport
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [RELINKREPORT] relinked: {:?} intact: {:?} missing: {:?} changed: {:?} failed: {:?} }}",
+    		self.relinked,
+    		self.intact,
+    		self.missing,
+    		self.changed,
+    		self.failed
+		)
+	}
+}
+
+impl std::fmt::Display for RelinkReport
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		write!(f, "Relinked {} soft-links, {} intact, {} sources not found, {} changed since written (kept), {} failed", self.relinked, self.intact, self.missing, self.changed, self.failed)
+	}
+}
+
+// the moved source: the longest tail of the old source-path found below one of the new locations (with the recorded size, if known)
+fn locate(source: &Path, size: u64, inputs: &[String]) -> Option<PathBuf>
+{
+	let names: &[Component] = &source.components().filter(|c| matches!(c, Component::Normal(_))).collect::<Vec<_>>();
+
+	(0..names.len())
+	.flat_map(|skip| inputs.iter().map(move |input| Path::new(input).join(names[skip..].iter().collect::<PathBuf>())))
+	.find(|p| p.metadata().is_ok_and(|m| m.is_file() && (size == 0 || m.len() == size)))
+}
+
+/// Repairs the broken soft-links KitCat created in the output-directory after the library moved to the input-directories:
+/// the source of every broken link is looked up below them and the link is rewritten in the configured style (journaled, so it can be undone).
+/// Links that still resolve or were changed since they were written are not touched.
+pub fn relink(rcon: &RuntimeConfig, manifest: &mut Manifest, journal: &mut Journal) -> RelinkReport
+{
+	let links = SoftLinks::from_config(rcon);
+	let mut report = RelinkReport { relinked: 0, intact: 0, missing: 0, changed: 0, failed: 0 };
+	let entries: Vec<(PathBuf, PathBuf, u64)> = manifest.entries.iter()
+	.filter(|(_, e)| e.mode == WriteMode::Soft)
+	.map(|(target, e)| (PathBuf::from(target), e.source.to_path_buf(), e.source_size))
+	.collect();
+
+	info!("Checking {:?} soft-links in {:?}", entries.len(), rcon.output);
+
+	for (target, old, size) in entries
+	{
+		if target.symlink_metadata().is_err()
+		{
+			debug!("{:?} was removed since it was written - skipping", target);
+			continue;
+		}
+
+		if !manifest.owns(&target)
+		{
+			warn!("{:?} was changed since it was written - skipping", target);
+			report.changed += 1;
+			continue;
+		}
+
+		if target.metadata().is_ok()
+		{
+			report.intact += 1;
+			continue;
+		}
+
+		let source = match locate(&old, size, &rcon.inputs)
+		{
+			Some(s) => s,
+			None =>
+			{
+				warn!("Source {:?} of {:?} not found below {:?}", old, target, rcon.inputs);
+				report.missing += 1;
+				continue;
+			}
+		};
+
+		let link = match links.path(&source, &target)
+		{
+			Ok(l) => l,
+			Err(e) => { error!("Relinking {:?} to {:?} failed: {:?}", target, source, e); report.failed += 1; continue }
+		};
+
+		info!("Relinking {:?} to {:?} ({})", target, link, links.style.name());
+
+		if rcon.dry
+		{
+			report.relinked += 1;
+			continue;
+		}
+
+		// the broken link is moved into the backup, so undo restores it
+		if let Err(e) = journal.remove(&target).and_then(|_| writer::link_soft(&link, &target))
+		{
+			error!("Relinking {:?} to {:?} failed: {:?}", target, link, e);
+			report.failed += 1;
+			continue;
+		}
+
+		journal.created(&target);
+		manifest.relinked(&target, &source);
+		report.relinked += 1;
+	}
+
+	info!("{}", report);
+	report
+}
@@ -0,0 +1,273 @@
+extern crate log;
+	use log::*;
+
+use std::collections::*;
+use std::path::PathBuf;
+use crate::runtimeconfig::RuntimeConfig;
+use crate::error::Result;
+use crate::catalog;
+	use crate::catalog::Kit;
+use crate::ruleset;
+	use crate::ruleset::Ruleset;
+use crate::manifest::Manifest;
+use crate::ignore::WalkFilter;
+
+// part of the fingerprint of runs whose kits are namespaced by their input-directory
+const NAMESPACED: &str = "[namespace]";
+
+/// An input-directory together with the configuration and ruleset it's paths are mapped by.
+pub struct Root
+{
+	pub rcon: RuntimeConfig, // input is the directory of this root, rules it's ruleset-file
+	pub ruleset: Ruleset
+}
+
+impl std::fmt::Debug for Root
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [ROOT] input: {:?} rules: {:?} }}",
+    		self.rcon.input,
+    		self.rcon.rules
+		)
+	}
+}
+
+/// Loads the ruleset of every input-directory - a single root without input-directory if none is configured.
+pub fn load(rcon: &RuntimeConfig) -> Result<Vec<Root>>
+{
+	let mut out: Vec<Root> = vec![];
+
+	for root in rcon.roots()
+	{
+		let ruleset = ruleset::load(&root.rules)?;
+
+		out.push(Root { rcon: root, ruleset });
+	}
+
+	Ok(out)
+}
+
+/// Fingerprint of how the paths of a single input-directory are mapped: it's ruleset and normalization - and the directory itself if it's kits are namespaced.
+/// The group-values cached in the manifest (and plans) are only reused by runs with the same fingerprint.
+pub fn root_fingerprint(ruleset: &Ruleset, rcon: &RuntimeConfig) -> String
+{
+	let ruleset = ruleset::fingerprint(ruleset);
+	let mut parts: Vec<&str> = vec![&ruleset, rcon.normalize.name()];
+
+	if rcon.namespace
+	{
+		parts.push(NAMESPACED);
+		parts.push(&rcon.input);
+	}
+
+	ruleset::fingerprint_of(&parts)
+}
+
+/// Fingerprint of the mapping of all roots - the one of each root if all roots share it,
+/// so a single input-directory (or a plan of it) has the same fingerprint as it's root.
+pub fn fingerprint(roots: &[Root]) -> String
+{
+	let fingerprints: Vec<String> = roots.iter().map(|r| root_fingerprint(&r.ruleset, &r.rcon)).collect();
+
+	if fingerprints.windows(2).all(|f| f[0] == f[1])
+	{
+		return fingerprints.first().cloned().unwrap_or_default();
+	}
+
+	let mut parts: Vec<&str> = vec![];
+
+	for (root, fingerprint) in roots.iter().zip(fingerprints.iter())
+	{
+		parts.push(&root.rcon.input);
+		parts.push(fingerprint);
+	}
+
+	ruleset::fingerprint_of(&parts)
+}
+
+/// Root whose input-directory contains the given path - the first one if there is none.
+pub fn root_of<'a>(roots: &'a [Root], path: &str) -> &'a Root
+{
+	roots.iter().find(|r| !r.rcon.input.is_empty() && std::path::Path::new(path).starts_with(&r.rcon.input)).unwrap_or(&roots[0])
+}
+
+/// Splits the paths of a file-list by the root containing them - into paths relative to it's input-directory, sorted like collect does.
+/// Paths that are not regular files, lie outside of every input-directory or are excluded by the filter are skipped.
+pub fn split_list(roots: &[Root], list: Vec<PathBuf>, filter: &WalkFilter) -> Vec<Vec<PathBuf>>
+{
+	let mut out: Vec<Vec<PathBuf>> = roots.iter().map(|_| vec![]).collect();
+
+	for path in list
+	{
+		match path.symlink_metadata()
+		{
+			Ok(m) if m.is_file() => {},
+			Ok(m) if m.is_dir() => { debug!("Skipping directory {:?}", path); continue }, // e.g. listed by find without -type f
+			Ok(_) => { warn!("Skipping {:?} - not a regular file", path); continue },
+			Err(e) => { warn!("Skipping {:?} - {}", path, e); continue }
+		}
+
+		match roots.iter().position(|r| path.starts_with(&r.rcon.input))
+		{
+			None => warn!("Skipping {:?} - not inside an input-directory", path),
+			Some(i) =>
+			{
+				let relative = path.strip_prefix(&roots[i].rcon.input).unwrap_or(&path);
+
+				if filter.is_listed(relative)
+				{
+					out[i].push(relative.to_path_buf());
+				}
+				else
+				{
+					debug!("Excluded {:?}", path);
+				}
+			}
+		}
+	}
+
+	for paths in out.iter_mut()
+	{
+		paths.sort();
+		paths.dedup();
+	}
+
+	out
+}
+
+/// Paths of the files of every root (relative to it's input-directory) - out of the file-list if one is configured,
+/// by walking the input-directories otherwise.
+pub fn datasets(roots: &[Root], rcon: &RuntimeConfig) -> Result<Vec<Vec<PathBuf>>>
+{
+	if rcon.from_list.is_empty()
+	{
+		return roots.iter().map(|r| catalog::collect(&r.rcon.input, &r.rcon)).collect();
+	}
+
+	let filter = WalkFilter::from_config(rcon)?;
+
+	Ok(split_list(roots, catalog::read_list(&rcon.from_list, &rcon.list_base)?, &filter))
+}
+
+/// Collects and matches every input-directory by it's own ruleset. Kits with the same name are merged across all roots.
+/// Paths recorded in the manifest of a previous run with the same roots reuse their group-values (unless --full).
+pub fn process(roots: &[Root], rcon: &RuntimeConfig, manifest: &Manifest) -> Result<HashMap<String, Kit>>
+{
+	let mut out: HashMap<String, Kit> = HashMap::new();
+	let fingerprint = fingerprint(roots);
+
+	for (root, dataset) in roots.iter().zip(datasets(roots, rcon)?)
+	{
+		let cached = if root.rcon.full { HashMap::new() } else { manifest.fields_by_source(&fingerprint) };
+
+		catalog::process_into(&mut out, dataset, &root.ruleset, &root.rcon, &cached)?;
+	}
+
+	if roots.len() > 1
+	{
+		info!("Merged {:?} input-directories into {:?} kits", roots.len(), out.len());
+	}
+
+	Ok(out)
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use std::path::Path;
+	use crate::runtimeconfig;
+
+	const RULES: &str = "input = \"{group}/{sample} {kit}\\\\.wav\"\noutput = \"{kit}/{sample}.wav\"\nindex = kit\n\n[groups]\ngroup = \"(Kick|Snare)\"\nsample = \"([A-Za-z0-9]+)\"\nkit = \"(Kit[A-Z])\"\n";
+
+	// two input-directories (VendorA and VendorB) of a test below the temporary directory, both with samples of KitA, and it's rules-file
+	fn setup(name: &str) -> (PathBuf, String)
+	{
+		let root = std::env::temp_dir().join(format!("kitcat-roots-{}-{}", name, std::process::id()));
+		let rules = root.join("rules.ini");
+
+		let _ = std::fs::remove_dir_all(&root);
+
+		for (vendor, sample) in [("VendorA", "Kick/K1 KitA.wav"), ("VendorA", "Snare/S1 KitB.wav"), ("VendorB", "Kick/K2 KitA.wav")]
+		{
+			let path = root.join(vendor).join(sample);
+
+			std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+			std::fs::write(&path, b"sample").unwrap();
+		}
+
+		std::fs::write(&rules, RULES).unwrap();
+		(root, rules.to_string_lossy().to_string())
+	}
+
+	fn config(root: &Path, rules: &str, more: &[&str]) -> RuntimeConfig
+	{
+		let mut args: Vec<String> = ["kitcat", "-r", rules, "-i", &root.join("VendorA").to_string_lossy(), "-i", &root.join("VendorB").to_string_lossy(), "-o", "/out"].iter().map(|a| a.to_string()).collect();
+
+		args.extend(more.iter().map(|a| a.to_string()));
+		runtimeconfig::process_args(args).unwrap()
+	}
+
+	// kit-names and the file-names of their samples' sources, sorted
+	fn contents(kits: &HashMap<String, Kit>) -> BTreeMap<String, Vec<String>>
+	{
+		kits.iter().map(|(name, kit)|
+		{
+			let mut sources: Vec<String> = kit.samples.iter().map(|s| s.source_path.file_name().unwrap().to_string_lossy().to_string()).collect();
+
+			sources.sort();
+			(name.to_string(), sources)
+		}).collect()
+	}
+
+	#[test]
+	fn kits_of_the_same_name_are_merged_across_input_directories()
+	{
+		let (root, rules) = setup("merge");
+		let rcon = config(&root, &rules, &[]);
+		let roots = load(&rcon).unwrap();
+		let kits = process(&roots, &rcon, &Manifest::new()).unwrap();
+
+		assert_eq!(contents(&kits), BTreeMap::from([
+			("KitA".to_string(), vec!["K1 KitA.wav".to_string(), "K2 KitA.wav".to_string()]),
+			("KitB".to_string(), vec!["S1 KitB.wav".to_string()])
+		]));
+		assert_eq!(kits["KitA"].samples.iter().find(|s| s.source_path.starts_with(root.join("VendorB"))).unwrap().target_path, Path::new("/out/KitA/K2.wav"));
+		assert_eq!(fingerprint(&roots), root_fingerprint(&roots[0].ruleset, &roots[0].rcon));
+
+		let _ = std::fs::remove_dir_all(&root);
+	}
+
+	#[test]
+	fn namespaced_kits_stay_apart_and_roots_can_have_rules_of_their_own()
+	{
+		let (root, rules) = setup("namespace");
+		let other = root.join("other.ini");
+
+		std::fs::write(&other, RULES.replace("(Kick|Snare)", "(Snare)")).unwrap();
+
+		let rcon = config(&root, &rules, &["--namespace", "--root-rules", &format!("{}={}", root.join("VendorB").to_string_lossy(), other.to_string_lossy())]);
+		let roots = load(&rcon).unwrap();
+		let kits = process(&roots, &rcon, &Manifest::new()).unwrap();
+
+		assert_eq!(roots[1].rcon.rules, other.to_string_lossy());
+		assert_eq!(contents(&kits).keys().cloned().collect::<Vec<_>>(), ["VendorA/KitA", "VendorA/KitB"]); // Kick is no group of VendorB's rules
+		assert_ne!(fingerprint(&roots), root_fingerprint(&roots[0].ruleset, &roots[0].rcon));
+
+		let _ = std::fs::remove_dir_all(&root);
+	}
+
+	#[test]
+	fn listed_files_are_split_by_the_input_directory_containing_them()
+	{
+		let (root, rules) = setup("list");
+		let rcon = config(&root, &rules, &[]);
+		let roots = load(&rcon).unwrap();
+		let list = vec![root.join("VendorB/Kick/K2 KitA.wav"), root.join("VendorA/Kick"), root.join("rules.ini"), root.join("VendorA/Kick/K1 KitA.wav"), root.join("VendorA/Kick/K1 KitA.wav")];
+
+		assert_eq!(split_list(&roots, list, &WalkFilter::from_config(&rcon).unwrap()), [vec![PathBuf::from("Kick/K1 KitA.wav")], vec![PathBuf::from("Kick/K2 KitA.wav")]]);
+
+		let _ = std::fs::remove_dir_all(&root);
+	}
+}
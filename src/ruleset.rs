@@ -11,6 +11,10 @@ use ini::Ini;
 use ini::ini::Properties;
 
 use std::collections::*;
+use std::path::PathBuf;
+
+use crate::error;
+	use crate::error::{Error, Result};
 
 pub struct Ruleset
 {
@@ -18,10 +22,11 @@ pub struct Ruleset
 	pub rearranges: HashMap<String, String>,
 
 	pub recheck: String,
+	pub raw_input: String, // input-rule as configured, before the groups were applied
 	pub input: String,
 	pub output: String,
 	pub index: String,
-	pub input_order: HashMap<String, usize>
+	pub input_groups: HashMap<String, Vec<String>> // group name -> names of it's captures inside the compiled input-rule
 }
 
 const PH_GROUP: &str = r"group";
@@ -30,13 +35,21 @@ const PH_KIT: &str = r"kit";
 const PH_VARIATION: &str = r"variation";
 const PH_EXTENSION: &str = r"extension";
 
-const DEF_RULE_GROUP: &str = r"([a-zA-Z0-9 ]*)";
-const DEF_RULE_SAMPLE: &str = r"([a-zA-Z0-9]*)";
-const DEF_RULE_KIT: &str = r"([a-zA-Z0-9]*)";
-const DEF_RULE_VARIATION: &str = r"([a-zA-Z0-9 ]*)";
+const DEF_RULE_GROUP: &str = r"([\p{L}\p{M}\p{N} ]*)";
+const DEF_RULE_SAMPLE: &str = r"([\p{L}\p{M}\p{N}]*)";
+const DEF_RULE_KIT: &str = r"([\p{L}\p{M}\p{N}]*)";
+const DEF_RULE_VARIATION: &str = r"([\p{L}\p{M}\p{N} ]*)";
 const DEF_RULE_EXTENSION: &str = r"([wav|WAV|mp3|MP3]*)";
 const DEF_RULE_INDEX: &str = PH_KIT;
-const DEF_RULE_RECHECK: &str = r"^([0-9a-zA-Z]{1,2})$";
+const DEF_RULE_RECHECK: &str = r"^([\p{L}\p{N}]{1,2})$";
+
+// Group-names starting with this prefix are inserted as non-capturing fragments
+const HELPER_PREFIX: &str = r"_";
+// Separates group-name and occurrence of a repeated group in the compiled input-rule
+const REPEAT_SEPARATOR: &str = r"__";
+
+// Portable directory-separator - usable in input-rule, output-path and rearranges on every OS (as well as a plain /)
+pub const SEPARATOR: &str = r"{/}";
 
 // Helpers to keep the path clean: 
 const DEF_RULE_TRIMMER: &str = r"[ ]?([\|/|.])[ ]?";
@@ -50,10 +63,10 @@ impl std::fmt::Debug for Ruleset
 {
 	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result 
 	{
-	    writeln!(f, "{{ [RULES] input: {:?} output: {:?} input_order: {:?} recheck: {:?} index: {:?} groups: {:?} }}",
+	    writeln!(f, "{{ [RULES] input: {:?} output: {:?} input_groups: {:?} recheck: {:?} index: {:?} groups: {:?} }}",
     		self.input,
     		self.output,
-    		self.input_order,
+    		self.input_groups,
     		self.recheck,
     		self.index,
     		self.groups
@@ -61,63 +74,125 @@ impl std::fmt::Debug for Ruleset
 	}
 }
 
-pub fn get_group_order(raw_input_str: &str, groups: &HashMap<String, String>) -> HashMap<String, usize>
+pub fn apply_output_rule(to_str: &mut String, replacements: &HashMap<String, String>)
 {
-	let mut check: HashMap<String, usize> = HashMap::new();
-	let mut out: HashMap<String, usize> = HashMap::new();
-	let mut i = 0;
+	for (group, replacement) in replacements.iter()
+	{
+		*to_str = to_str.trim().replace(&with_brackets(group), replacement.trim());
+	}
 
-	for (group_name, _group_regex) in groups.iter()
+	*to_str = TRIMMER_REGEX.replace_all(to_str, DEF_RULE_TRIMMER_TO).to_string();
+}
+
+pub fn is_helper_group(group_name: &str) -> bool
+{
+	group_name.starts_with(HELPER_PREFIX)
+}
+
+fn capture_name(group_name: &str, occurrence: usize) -> String
+{
+	if occurrence == 1
 	{
-		match raw_input_str.find(&with_brackets(group_name)) 
-		{
-			None => continue,
-			Some(i) => { check.insert(group_name.to_string(), i); }
-		}
+		return group_name.to_string();
 	}
-	
-	if check.len() == 0
+
+	[group_name, REPEAT_SEPARATOR, &occurrence.to_string()].join("")
+}
+
+pub fn is_valid_group_name(group_name: &str) -> bool
+{
+	!group_name.is_empty()
+	&& !group_name.contains(REPEAT_SEPARATOR)
+	&& !group_name.starts_with(|c: char| c.is_ascii_digit())
+	&& group_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Replaces every {group} placeholder of the input-rule with a named capture (?P<group>...) of it's regex.
+/// Repeated placeholders get a numbered capture (group__2, ...), helper groups (prefixed with _) become non-capturing.
+/// Returns the capture names of each group inside the resulting regex.
+pub fn apply_input_groups(to_str: &mut String, replacements: &HashMap<String, String>) -> Result<HashMap<String, Vec<String>>>
+{
+	let mut out: HashMap<String, Vec<String>> = HashMap::new();
+	let mut compiled = String::new();
+	let mut rest: &str = to_str;
+
+	while let Some(open) = rest.find('{')
 	{
-		panic!("Input string does not contain any matching groups - cannot proceed");
+		let (before, from_open) = rest.split_at(open);
+		compiled.push_str(before);
+
+		let name = from_open[1..].split('}').next().unwrap_or("");
+		let replacement = match replacements.get(name)
+		{
+			Some(r) if from_open[1..].contains('}') => r,
+			_ => { compiled.push('{'); rest = &from_open[1..]; continue } // regex-quantifier or escaped bracket - keep as it is
+		};
+
+		if !is_valid_group_name(name)
+		{
+			return Err(Error::InvalidGroupName { group: name.to_string() });
+		}
+
+		if is_helper_group(name)
+		{
+			compiled.push_str(&format!("(?:{})", replacement));
+		}
+		else 
+		{
+			let captures = out.entry(name.to_string()).or_default();
+			let capture = capture_name(name, captures.len() + 1);
+
+			compiled.push_str(&format!("(?P<{}>{})", capture, replacement));
+			captures.push(capture);
+		}
+
+		rest = &from_open[name.len() + 2..];
 	}
 
-	let mut check_: Vec<(String, usize)> = check.into_iter().collect();
-	check_.sort_by(|a, b| { a.1.cmp(&b.1) });
+	compiled.push_str(rest);
 
-	for (group, _c) in check_.iter()
+	if out.is_empty()
 	{
-		i+=1;
-		out.insert(group.to_string(), i);
+		return Err(Error::NoGroupsInInput { input: to_str.to_string() });
 	}
 
-	out
+	*to_str = compiled;
+	Ok(out)
 }
 
-pub fn apply_output_rule(to_str: &mut String, replacements: &HashMap<String, String>)
+/// Input-rule with portable separators: paths are matched with / between their components on every OS.
+/// The separator-token becomes /, on Windows an escaped backslash (\\\\ in the ini-file) is read as separator too.
+pub fn portable_input(raw_input: &str) -> String
 {
-	for (group, replacement) in replacements.iter()
+	let out = raw_input.replace(SEPARATOR, "/");
+
+	if cfg!(target_family = "windows")
 	{
-		*to_str = to_str.trim().replace(&with_brackets(group), replacement.trim());
+		return out.replace(r"\\", "/");
 	}
 
-	*to_str = TRIMMER_REGEX.replace_all(to_str, DEF_RULE_TRIMMER_TO).to_string();
+	out
 }
 
-pub fn apply_input_groups(to_str: &mut String, replacements: &HashMap<String, String>)
+/// Path of an applied output-rule inside the output-directory, joined component by component.
+/// Components are separated by / (or the separator-token), on Windows by a backslash too.
+/// Empty, . and .. components (out of group-values or the rule) are dropped, so the path never leaves the output-directory.
+pub fn output_path(output_dir: &str, applied: &str) -> PathBuf
 {
-	for (group, replacement) in replacements.iter()
+	let separators: &[char] = if cfg!(target_family = "windows") { &['/', '\\'] } else { &['/'] };
+	let mut out = PathBuf::from(output_dir);
+
+	for component in applied.replace(SEPARATOR, "/").split(separators).filter(|c| !c.is_empty() && *c != "." && *c != "..")
 	{
-		*to_str = to_str.replace(&with_brackets(group), replacement);
+		out.push(component);
 	}
+
+	out
 }
 
-pub fn setup_default_ruleset() -> Ruleset
+pub fn setup_default_ruleset() -> Result<Ruleset>
 {	
-	#[cfg(target_family = "unix")]
-		let os_dir_del = r"/";
-
-	#[cfg(target_family = "windows")]
-		let os_dir_del = r"\\";// escape is for regex
+	let os_dir_del = r"/"; // matched paths use / on every OS
 	
 	let raw_input_str = &[&with_brackets(PH_GROUP), os_dir_del, &with_brackets(PH_SAMPLE), " ?", &with_brackets(PH_KIT), &with_brackets(PH_VARIATION), r"?\.", &with_brackets(PH_EXTENSION)].join("");
 	let raw_output_str = &[&with_brackets(PH_KIT), os_dir_del, &with_brackets(PH_SAMPLE), " ", &with_brackets(PH_VARIATION), ".", &with_brackets(PH_EXTENSION)].join("");
@@ -137,27 +212,37 @@ pub fn setup_default_ruleset() -> Ruleset
 	rearranges.insert(PH_SAMPLE.to_string(), PH_KIT.to_string());
 
 	let mut out = Ruleset {
-		input: raw_input_str.to_string(),
+		raw_input: raw_input_str.to_string(),
+		input: portable_input(raw_input_str),
 		output: raw_output_str.to_string(),
-		input_order: get_group_order(&raw_input_str, &groups),
+		input_groups: HashMap::new(),
 		index: index_rule.to_string(),
-		rearranges: rearranges,
+		rearranges,
 		recheck: recheck_rule.to_string(),
-		groups: groups
+		groups
 	};
 
-	apply_input_groups(&mut out.input, &out.groups);
-	out
+	out.input_groups = apply_input_groups(&mut out.input, &out.groups)?;
+	Ok(out)
 }
 
-pub fn setup_custom_ruleset(by_file: &str) -> Ruleset
+pub fn setup_custom_ruleset(by_file: &str) -> Result<Ruleset>
 {
-	let conf = Ini::load_from_file(by_file).unwrap();
-	let io = conf.general_section();
+	let conf = match Ini::load_from_file(by_file)
+	{
+		Err(e) => return Err(Error::Ini { file: by_file.to_string(), source: e }),
+		Ok(c) => c
+	};
+
+	let io: &Properties = match conf.section(None::<String>)
+	{
+		None => return Err(Error::MissingKey { file: by_file.to_string(), key: "input".to_string() }),
+		Some(s) => s
+	};
 
 	let s_groups: &Properties = match conf.section(Some("groups"))
 	{
-		None => panic!("Missing groups section in {:?}", by_file),
+		None => return Err(Error::MissingSection { file: by_file.to_string(), section: "groups".to_string() }),
 		Some(s) => s
 	};
 
@@ -170,13 +255,13 @@ pub fn setup_custom_ruleset(by_file: &str) -> Ruleset
 
 	let raw_input_str: String = match io.get("input")
 	{
-		None => panic!("Missing input configuration in {:?}", by_file),
+		None => return Err(Error::MissingKey { file: by_file.to_string(), key: "input".to_string() }),
 		Some(s) => s.to_string()
 	};
 
 	let raw_output_str: String = match io.get("output")
 	{
-		None => panic!("Missing output configuration in {:?}", by_file),
+		None => return Err(Error::MissingKey { file: by_file.to_string(), key: "output".to_string() }),
 		Some(s) => s.to_string()
 	};
 	
@@ -188,7 +273,7 @@ pub fn setup_custom_ruleset(by_file: &str) -> Ruleset
 
 	let index_rule: String = match io.get("index")
 	{
-		None => panic!("Missing index configuration in {:?}", by_file),
+		None => return Err(Error::MissingKey { file: by_file.to_string(), key: "index".to_string() }),
 		Some(s) => s.to_string()
 	};
 
@@ -201,7 +286,7 @@ pub fn setup_custom_ruleset(by_file: &str) -> Ruleset
 
 	if !groups.contains_key(&index_rule)
 	{
-		panic!("Index-group {:?} does not exist in groups section of {:?}", index_rule, by_file);
+		return Err(Error::UnknownIndexGroup { file: by_file.to_string(), group: index_rule });
 	}
 
 	let mut rearranges: HashMap<String, String> = HashMap::new();
@@ -212,36 +297,231 @@ pub fn setup_custom_ruleset(by_file: &str) -> Ruleset
 	}
 
 	let mut out = Ruleset {
-		input: raw_input_str.to_string(),
+		raw_input: raw_input_str.to_string(),
+		input: portable_input(&raw_input_str),
 		output: raw_output_str,
-		rearranges: rearranges,
+		rearranges,
 		recheck: recheck_rule,
-		input_order: get_group_order(&raw_input_str, &groups),
+		input_groups: HashMap::new(),
 		index: index_rule,
-		groups: groups,
+		groups,
 	};
 
-	apply_input_groups(&mut out.input, &out.groups);
+	out.input_groups = apply_input_groups(&mut out.input, &out.groups)?;
+	Ok(out)
+}
+
+pub fn load(by_file: &str) -> Result<Ruleset>
+{
+	if by_file.is_empty()
+	{
+		info!("Setting up default-configuration");
+
+		return setup_default_ruleset();
+	}
+
+	info!("Using custom configuration-file {:?}", by_file);
+
+	setup_custom_ruleset(by_file)
+}
+
+/// Stable fingerprint (FNV-1a, hex) of everything inside the ruleset that influences how paths are mapped.
+/// Part of roots::root_fingerprint, which also covers the options changing the group-values (like --normalize).
+pub fn fingerprint(ruleset: &Ruleset) -> String
+{
+	let mut groups: Vec<(&String, &String)> = ruleset.groups.iter().collect();
+	let mut rearranges: Vec<(&String, &String)> = ruleset.rearranges.iter().collect();
+
+	groups.sort();
+	rearranges.sort();
+
+	let mut parts: Vec<&str> = vec![&ruleset.raw_input, &ruleset.output, &ruleset.recheck, &ruleset.index];
+
+	for (name, value) in groups
+	{
+		parts.push(name);
+		parts.push(value);
+	}
+
+	parts.push("[rearrange]");
+
+	for (name, value) in rearranges
+	{
+		parts.push(name);
+		parts.push(value);
+	}
+
+	fingerprint_of(&parts)
+}
+
+/// FNV-1a (hex) of the given parts - each part is terminated by a zero byte, so ["ab", "c"] and ["a", "bc"] differ.
+pub fn fingerprint_of(parts: &[&str]) -> String
+{
+	let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+
+	for byte in parts.iter().flat_map(|p| p.bytes().chain(std::iter::once(0u8)))
+	{
+		hash ^= u64::from(byte);
+		hash = hash.wrapping_mul(0x0100_0000_01b3);
+	}
+
+	format!("{:016x}", hash)
+}
+
+pub struct CompiledRuleset
+{
+	pub input: Regex,
+	pub recheck: Regex
+}
+
+pub fn compile(ruleset: &Ruleset) -> Result<CompiledRuleset>
+{
+	let input = Regex::new(&ruleset.input).map_err(|e| error::regex(&ruleset.input, e))?;
+	let recheck = Regex::new(&ruleset.recheck).map_err(|e| error::regex(&ruleset.recheck, e))?;
+
+	Ok(CompiledRuleset { input, recheck })
+}
+
+/// Returns the names of all {group} placeholders inside the given rule, in order of appearance.
+pub fn placeholders(rule: &str) -> Vec<String>
+{
+	let mut out: Vec<String> = vec![];
+
+	for part in rule.split('{').skip(1)
+	{
+		if let Some((name, _rest)) = part.split_once('}')
+		{
+			if is_valid_group_name(name)
+			{
+				out.push(name.to_string());
+			}
+		}
+	}
+
 	out
 }
 
-pub fn map_capture(cap: &Captures, input_order: &HashMap<String,usize>) -> HashMap<String, String>
+/// Reads the value of each group out of the captures of the compiled input-rule.
+/// Returns None if a repeated group captured different values.
+pub fn map_capture(cap: &Captures, input_groups: &HashMap<String, Vec<String>>) -> Option<HashMap<String, String>>
 {
 	let mut out: HashMap<String, String> = HashMap::new();
 
-	for (group, index) in input_order.iter()
+	for (group, captures) in input_groups.iter()
 	{
-		out.insert(group.to_string(), cap[*index].trim().to_string());
+		let mut values = captures.iter().filter_map(|c| cap.name(c)).map(|m| m.as_str().trim());
+		let value = values.next().unwrap_or("");
+
+		if values.any(|v| v != value)
+		{
+			debug!("Repeated group {:?} captured different values in {:?}", group, &cap[0]);
+			return None;
+		}
+
+		out.insert(group.to_string(), value.to_string());
 	}
 
-	out
+	Some(out)
 }
 
 pub fn with_brackets(group_name: &str) -> String
 {
 	let mut out = String::new();
-	out.push_str("{"); 
+	out.push('{'); 
 	out.push_str(group_name);
-	out.push_str("}"); 
+	out.push('}'); 
 	out
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use std::path::Path;
+
+	fn groups(pairs: &[(&str, &str)]) -> HashMap<String, String>
+	{
+		pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+	}
+
+	// writes the content into a rules-file of it's own inside the temporary directory
+	fn rules_file(name: &str, content: &str) -> String
+	{
+		let path = std::env::temp_dir().join(format!("kitcat-test-{}-{}.ini", std::process::id(), name));
+
+		std::fs::write(&path, content).unwrap();
+		path.to_string_lossy().to_string()
+	}
+
+	#[test]
+	fn named_groups_become_named_captures()
+	{
+		let mut input = r"^{group}/{sample}{_sep}{kit}\.wav$".to_string();
+		let input_groups = apply_input_groups(&mut input, &groups(&[("group", "(Kick|Snare)"), ("sample", "([a-z]+)"), ("_sep", "[ _]+"), ("kit", "(Kit[A-Z])")])).unwrap();
+
+		assert_eq!(input, r"^(?P<group>(Kick|Snare))/(?P<sample>([a-z]+))(?:[ _]+)(?P<kit>(Kit[A-Z]))\.wav$");
+		assert_eq!(input_groups["kit"], vec!["kit"]);
+		assert!(!input_groups.contains_key("_sep"));
+	}
+
+	#[test]
+	fn repeated_groups_get_numbered_captures()
+	{
+		let mut input = "^{kit}/{group}_{kit}$".to_string();
+		let input_groups = apply_input_groups(&mut input, &groups(&[("kit", "([A-Z]+)"), ("group", "([a-z]+)")])).unwrap();
+
+		assert_eq!(input, "^(?P<kit>([A-Z]+))/(?P<group>([a-z]+))_(?P<kit__2>([A-Z]+))$");
+		assert_eq!(input_groups["kit"], vec!["kit", "kit__2"]);
+	}
+
+	#[test]
+	fn quantifiers_and_unknown_placeholders_are_kept()
+	{
+		let mut input = "^{kit}[0-9]{2}{nope}$".to_string();
+
+		apply_input_groups(&mut input, &groups(&[("kit", "([A-Z]+)")])).unwrap();
+		assert_eq!(input, "^(?P<kit>([A-Z]+))[0-9]{2}{nope}$");
+	}
+
+	#[test]
+	fn input_without_groups_or_with_invalid_names_fails()
+	{
+		assert!(matches!(apply_input_groups(&mut "^plain$".to_string(), &groups(&[("kit", "(.*)")])), Err(Error::NoGroupsInInput { .. })));
+		assert!(matches!(apply_input_groups(&mut "^{a__b}$".to_string(), &groups(&[("a__b", "(.*)")])), Err(Error::InvalidGroupName { .. })));
+	}
+
+	#[test]
+	fn repeated_groups_must_capture_the_same_value()
+	{
+		let mut input = "^{kit}/{group}_{kit}$".to_string();
+		let input_groups = apply_input_groups(&mut input, &groups(&[("kit", "([A-Z]+)"), ("group", "([a-z]+)")])).unwrap();
+		let regex = Regex::new(&input).unwrap();
+
+		let same = map_capture(&regex.captures("AB/kick_AB").unwrap(), &input_groups).unwrap();
+
+		assert_eq!(same["kit"], "AB");
+		assert_eq!(same["group"], "kick");
+		assert!(map_capture(&regex.captures("AB/kick_CD").unwrap(), &input_groups).is_none());
+	}
+
+	#[test]
+	fn empty_rules_file_is_missing_the_input()
+	{
+		let empty = rules_file("empty", "");
+		let groups_only = rules_file("groups-only", "[groups]\nkit = \"(.*)\"\n");
+
+		assert!(matches!(setup_custom_ruleset(&empty), Err(Error::MissingKey { key, .. }) if key == "input"));
+		assert!(matches!(setup_custom_ruleset(&groups_only), Err(Error::MissingKey { key, .. }) if key == "input"));
+
+		std::fs::remove_file(empty).unwrap();
+		std::fs::remove_file(groups_only).unwrap();
+	}
+
+	#[test]
+	fn output_paths_stay_inside_the_output_directory()
+	{
+		assert_eq!(output_path("/out", "../../etc/KitA/Kick.wav"), Path::new("/out/etc/KitA/Kick.wav"));
+		assert_eq!(output_path("/out", "KitA/./Kick.wav"), Path::new("/out/KitA/Kick.wav"));
+		assert_eq!(output_path("/out", "KitA{/}Kick.wav"), Path::new("/out/KitA/Kick.wav"));
+	}
+}
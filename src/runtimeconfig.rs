@@ -2,115 +2,387 @@ use std::path::Path;
 use std::env;
 
 use crate::version;
+use crate::parallel;
+use crate::catalog::{CollisionPolicy, Normalization};
+use crate::writer::{ExistingPolicy, LinkStyle};
+use crate::pack::PackFormat;
+use crate::dedup::{DedupMode, DuplicatePolicy};
+use crate::error;
+	use crate::error::Result;
 
 extern crate stderrlog;
 extern crate log;
-	use log::*;
 
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Command
+{
+	Run,
+	Plan,
+	Apply,
+	CheckRules,
+	Explain,
+	Undo,
+	Relink,
+	Help
+}
+
+#[derive(Clone)]
 pub struct RuntimeConfig
 {
 	pub me: String,
-	pub help: bool, // -h
-	pub dry: bool, // -d
-	pub verbose: bool, // -v
-	pub soft: bool, // -s
-	pub copy: bool, // -c
-	pub kits: Vec<String>, // -k
-	pub trunc: u8, // -t
-	pub rules: String, // -r
-	pub input: String,// -i
-	pub output: String // -o
+	pub command: Command,
+	pub help: bool, // --help / -h
+	pub dry: bool, // --dry / -d
+	pub force: bool, // --force / -f
+	pub verbose: bool, // --verbose / -v
+	pub soft: bool, // --soft / -s
+	pub copy: bool, // --copy / -c
+	pub reflink: bool, // --reflink
+	pub auto: bool, // --auto
+	pub kits: Vec<String>, // --kits / -k
+	pub trunc: u8, // --trunc / -t
+	pub rules: String, // --rules / -r
+	pub input: String,// --input / -i (the first one - or the one processed by a configuration of roots())
+	pub inputs: Vec<String>, // every --input / -i
+	pub root_rules: Vec<(String, String)>, // --root-rules DIR=FILE (input-directory, ruleset-file)
+	pub namespace: bool, // --namespace
+	pub from_list: String, // --from-list (- = stdin, empty = walk the input-directories)
+	pub list_base: String, // --list-base (empty = current directory)
+	pub excludes: Vec<String>, // --exclude
+	pub includes: Vec<String>, // --include
+	pub default_excludes: bool, // false if --no-default-excludes
+	pub max_depth: usize, // --max-depth (0 = unlimited)
+	pub follow_links: bool, // --follow-links
+	pub archives: bool, // --archives
+	pub output: String, // --output / -o
+	pub on_collision: CollisionPolicy, // --on-collision
+	pub on_existing: ExistingPolicy, // --on-existing
+	pub link_style: LinkStyle, // --link-style
+	pub preserve_mtime: bool, // --preserve mtime
+	pub verify: bool, // --verify
+	pub library_root: String, // --library-root (or $KITCAT_LIBRARY) - absolute, but links are not resolved
+	pub pack: PackFormat, // --pack
+	pub dedup: DedupMode, // --dedup
+	pub on_duplicate: DuplicatePolicy, // --on-duplicate
+	pub sync: bool, // --sync
+	pub full: bool, // --full
+	pub resume: bool, // --resume
+	pub hash: bool, // --hash
+	pub jobs: usize, // --jobs / -j (0 = one per cpu)
+	pub stream: bool, // --stream
+	pub normalize: Normalization, // --normalize
+	pub operands: Vec<String> // everything that is not an option (or follows --)
 }
 
 impl std::fmt::Debug for RuntimeConfig
 {
-	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result 
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
 	{
-	    writeln!(f, "{{ [RUNTIMECONFIG] dry: {:?} verbose: {:?} soft: {:?} copy: {:?} kits: {:?} trunc: {:?} rules: {:?} input-path: {:?} output-path: {:?} }}",
+	    writeln!(f, "{{ [RUNTIMECONFIG] command: {:?} dry: {:?} force: {:?} verbose: {:?} soft: {:?} copy: {:?} reflink: {:?} auto: {:?} kits: {:?} trunc: {:?} rules: {:?} input-path: {:?} input-paths: {:?} root-rules: {:?} namespace: {:?} from-list: {:?} list-base: {:?} excludes: {:?} includes: {:?} default-excludes: {:?} max-depth: {:?} follow-links: {:?} archives: {:?} output-path: {:?} on-collision: {:?} on-existing: {:?} link-style: {:?} library-root: {:?} preserve-mtime: {:?} verify: {:?} pack: {:?} dedup: {:?} on-duplicate: {:?} sync: {:?} full: {:?} resume: {:?} hash: {:?} jobs: {:?} stream: {:?} normalize: {:?} operands: {:?} }}",
+    		self.command,
     		self.dry,
+    		self.force,
     		self.verbose,
     		self.soft,
     		self.copy,
+    		self.reflink,
+    		self.auto,
     		self.kits,
     		self.trunc,
     		self.rules,
     		self.input,
-    		self.output
+    		self.inputs,
+    		self.root_rules,
+    		self.namespace,
+    		self.from_list,
+    		self.list_base,
+    		self.excludes,
+    		self.includes,
+    		self.default_excludes,
+    		self.max_depth,
+    		self.follow_links,
+    		self.archives,
+    		self.output,
+    		self.on_collision,
+    		self.on_existing,
+    		self.link_style,
+    		self.library_root,
+    		self.preserve_mtime,
+    		self.verify,
+    		self.pack,
+    		self.dedup,
+    		self.on_duplicate,
+    		self.sync,
+    		self.full,
+    		self.resume,
+    		self.hash,
+    		self.jobs,
+    		self.stream,
+    		self.normalize,
+    		self.operands
 		)
 	}
 }
 
-const T_INDICATOR: &str = "-";
+const T_LONG_INDICATOR: &str = "--";
+const T_SHORT_INDICATOR: &str = "-";
+const T_VALUE_INDICATOR: char = '=';
+
+const T_DRY: &str = "dry";
+const T_VERBOSE: &str = "verbose";
+const T_SOFT: &str = "soft";
+const T_COPY: &str = "copy";
+const T_REFLINK: &str = "reflink";
+const T_AUTO: &str = "auto";
+const T_KITS: &str = "kits";
+const T_TRUNC: &str = "trunc";
+const T_RULES: &str = "rules";
+const T_INPUT: &str = "input";
+const T_OUTPUT: &str = "output";
+const T_HELP: &str = "help";
+const T_FORCE: &str = "force";
+const T_ON_COLLISION: &str = "on-collision";
+const T_ON_EXISTING: &str = "on-existing";
+const T_LINK_STYLE: &str = "link-style";
+const T_LIBRARY_ROOT: &str = "library-root";
+const ENV_LIBRARY_ROOT: &str = "KITCAT_LIBRARY";
+const T_PRESERVE: &str = "preserve";
+const T_VERIFY: &str = "verify";
+const PRESERVE_MTIME: &str = "mtime";
+const T_PACK: &str = "pack";
+const T_DEDUP: &str = "dedup";
+const T_ON_DUPLICATE: &str = "on-duplicate";
+const T_SYNC: &str = "sync";
+const T_FULL: &str = "full";
+const T_RESUME: &str = "resume";
+const T_HASH: &str = "hash";
+const T_JOBS: &str = "jobs";
+const T_STREAM: &str = "stream";
+const T_NORMALIZE: &str = "normalize";
+const T_ROOT_RULES: &str = "root-rules";
+const T_NAMESPACE: &str = "namespace";
+const T_FROM_LIST: &str = "from-list";
+const T_LIST_BASE: &str = "list-base";
+const T_EXCLUDE: &str = "exclude";
+const T_INCLUDE: &str = "include";
+const T_NO_DEFAULT_EXCLUDES: &str = "no-default-excludes";
+const T_MAX_DEPTH: &str = "max-depth";
+const T_FOLLOW_LINKS: &str = "follow-links";
+const T_ARCHIVES: &str = "archives";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Arity
+{
+	Flag, // no value
+	Single, // exactly one value
+	Multiple // one or more values, read until the next option - a single one for commands with arguments (see LIST_RULE)
+}
+
+// how the values of list-options end - shown by the help
+const LIST_RULE: &str = "Options taking a list (NAME..., GLOB..., ATTR...) read values up to the next option. Commands with arguments (plan, apply, explain, undo) can't tell those values from their arguments: there every list-option takes one value - repeat the option for more (-k KitA -k KitB).";
 
-const T_DRY: &str = "d";
-const T_VERBOSE: &str = "v";
-const T_SOFT: &str = "s";
-const T_COPY: &str = "c";
-const T_KITS: &str = "k";
-const T_TRUNC: &str = "t";
-const T_RULES: &str = "r";
-const T_INPUT: &str = "i";
-const T_OUTPUT: &str = "o";
-const T_HELP: &str = "h";
+struct OptionDef
+{
+	long: &'static str,
+	short: Option<char>,
+	arity: Arity,
+	value: &'static str,
+	help: &'static str
+}
+
+struct CommandDef
+{
+	name: &'static str,
+	command: Command,
+	operands: &'static str,
+	min_operands: usize,
+	max_operands: usize,
+	help: &'static str
+}
 
-fn setup_default_config() -> RuntimeConfig
+const COMMANDS: &[CommandDef] = &[
+	CommandDef { name: "run", command: Command::Run, operands: "", min_operands: 0, max_operands: 0, help: "Collect, process and write all samples of --input into --output (default if no command is given)." },
+	CommandDef { name: "plan", command: Command::Plan, operands: "[PLAN]", min_operands: 0, max_operands: 1, help: "Collect and process all samples of --input without writing anything. The source -> target mapping is saved to PLAN (.json or .csv) or printed if no PLAN is given." },
+	CommandDef { name: "apply", command: Command::Apply, operands: "PLAN", min_operands: 1, max_operands: 1, help: "Write exactly the samples of a (possibly hand-edited) PLAN. Refuses plans created by a different ruleset than --rules unless --force is set." },
+	CommandDef { name: "check-rules", command: Command::CheckRules, operands: "", min_operands: 0, max_operands: 0, help: "Validate the ruleset given by --rules (or the internal one), print all errors and warnings and exit non-zero on errors." },
+	CommandDef { name: "explain", command: Command::Explain, operands: "PATH...", min_operands: 1, max_operands: usize::MAX, help: "Show how each PATH (relative to --input, does not need to exist) is parsed by the ruleset: compiled input-rule, captured values, fired rearranges, final fields, index and target-path." },
+	CommandDef { name: "undo", command: Command::Undo, operands: "[RUN]", min_operands: 0, max_operands: 1, help: "Revert the last run (or the given RUN) into --output: remove the files and directories it created and restore the files it replaced or removed. Files changed since are kept." },
+	CommandDef { name: "relink", command: Command::Relink, operands: "", min_operands: 0, max_operands: 0, help: "Repair the broken soft-links of --output after the library moved to --input: their sources are looked up below the new location and the links are rewritten in the style of --link-style. Can be reverted by undo." },
+	CommandDef { name: "help", command: Command::Help, operands: "", min_operands: 0, max_operands: 0, help: "Print this help." }
+];
+
+const OPTIONS: &[OptionDef] = &[
+	OptionDef { long: T_HELP, short: Some('h'), arity: Arity::Flag, value: "", help: "Print this help." },
+	OptionDef { long: T_DRY, short: Some('d'), arity: Arity::Flag, value: "", help: "Setup and process all files without copying/linking them." },
+	OptionDef { long: T_FORCE, short: Some('f'), arity: Arity::Flag, value: "", help: "Apply a plan even if it was created by a different ruleset." },
+	OptionDef { long: T_VERBOSE, short: Some('v'), arity: Arity::Flag, value: "", help: "Print more information to the stdout while processing." },
+	OptionDef { long: T_SOFT, short: Some('s'), arity: Arity::Flag, value: "", help: "Create soft-link instead of hard-link." },
+	OptionDef { long: T_COPY, short: Some('c'), arity: Arity::Flag, value: "", help: "Copy files instead of linking (if set: --soft won't be used)." },
+	OptionDef { long: T_REFLINK, short: None, arity: Arity::Flag, value: "", help: "Clone files instead of linking (btrfs/XFS) - a copy sharing the blocks of the source. Fails where the filesystem can't clone (if set: --auto and --soft won't be used)." },
+	OptionDef { long: T_AUTO, short: None, arity: Arity::Flag, value: "", help: "Clone every file if possible, else hard-link it, else copy it. The method used for every sample is recorded (if set: --soft won't be used)." },
+	OptionDef { long: T_KITS, short: Some('k'), arity: Arity::Multiple, value: "NAME...", help: "Space-separated list of names (see USAGE). Just process this kits(s). The name check is done on the configured group-index and it's value." },
+	OptionDef { long: T_TRUNC, short: Some('t'), arity: Arity::Single, value: "COUNT", help: "Truncate all kits containing less than COUNT (0-255) samples." },
+	OptionDef { long: T_ON_COLLISION, short: None, arity: Arity::Single, value: "POLICY", help: "What to do with samples mapped to an already taken target-path: fail, keep-first (default), suffix (Kick 2.wav) or conflicts (move into the _conflicts directory)." },
+	OptionDef { long: T_ON_EXISTING, short: None, arity: Arity::Single, value: "POLICY", help: "What to do if a target-path already exists in the output-directory: skip, overwrite, overwrite-if-newer, overwrite-if-different (size/content), rename (Kick 2.wav) or error (default)." },
+	OptionDef { long: T_LINK_STYLE, short: None, arity: Arity::Single, value: "STYLE", help: "What soft-links point to: absolute (default), relative (to the directory of the link - survives moving library and output together) or anchored (below --library-root as given). Requires --soft." },
+	OptionDef { long: T_LIBRARY_ROOT, short: None, arity: Arity::Single, value: "DIR", help: "Library-root anchored soft-links point below, e.g. a link to the library that is repointed after moving it (default: $KITCAT_LIBRARY). Links inside it are not resolved." },
+	OptionDef { long: T_PRESERVE, short: None, arity: Arity::Multiple, value: "ATTR...", help: "Give copies the mtime of their source: mtime. Copies always keep the permissions of their source and are written to a temporary file first and renamed once complete." },
+	OptionDef { long: T_VERIFY, short: None, arity: Arity::Flag, value: "", help: "Compare the checksums of every copy and it's source before renaming it to it's target-path - copies that differ fail." },
+	OptionDef { long: T_PACK, short: None, arity: Arity::Single, value: "FORMAT", help: "Pack the samples into archives instead of copying/linking them: none (default), kit-zip (one zip per kit), zip or tar (the whole remapped tree, inside the output-directory) or tar-stdout. Entries are sorted and share a fixed timestamp, so the archives are reproducible." },
+	OptionDef { long: T_DEDUP, short: None, arity: Arity::Single, value: "MODE", help: "Hash every sample and report clusters of duplicates: none (default), bytes (byte-identical files) or audio (identical audio of WAV-files, ignoring metadata-chunks like bext or LIST)." },
+	OptionDef { long: T_ON_DUPLICATE, short: None, arity: Arity::Single, value: "POLICY", help: "What to do with the duplicates found by --dedup: report (default), link (copy the first sample, hard-link the others to it - requires --copy, --reflink or --auto) or drop (keep only the first one inside each kit)." },
+	OptionDef { long: T_SYNC, short: None, arity: Arity::Flag, value: "", help: "Make the output-directory mirror the current mapping: remove files of previous runs that are not mapped anymore and skip unchanged ones. Files not written by KitCat are never removed." },
+	OptionDef { long: T_FULL, short: None, arity: Arity::Flag, value: "", help: "Ignore the manifest of previous runs: match every path again and write every sample (instead of only new or changed ones)." },
+	OptionDef { long: T_RESUME, short: None, arity: Arity::Flag, value: "", help: "Continue the last run that was interrupted (Ctrl-C) or had failed samples: skip the samples it completed (even with --full) and retry the others." },
+	OptionDef { long: T_HASH, short: None, arity: Arity::Flag, value: "", help: "Record a content-hash of every written source, so sources that were touched without changing are not written again." },
+	OptionDef { long: T_JOBS, short: Some('j'), arity: Arity::Single, value: "COUNT", help: "Count of workers walking the input-directory, matching paths and copying/linking samples (default: one per cpu). Logs and summary do not depend on it." },
+	OptionDef { long: T_STREAM, short: None, arity: Arity::Flag, value: "", help: "Walk, match and write the library in batches instead of loading it at once, so memory stays small for huge libraries (--trunc and --on-collision fail take an extra pass each). Can't be combined with --sync." },
+	OptionDef { long: T_NORMALIZE, short: None, arity: Arity::Single, value: "FORM", help: "Unicode normalization of paths before the input-rule is applied: none (default), nfc or nfd - use nfc to match names of macOS (decomposed) and other systems alike." },
+	OptionDef { long: T_RULES, short: Some('r'), arity: Arity::Single, value: "FILE", help: "Path to a ini-file, overriding the internal ruleset." },
+	OptionDef { long: T_INPUT, short: Some('i'), arity: Arity::Single, value: "DIR", help: "All files inside this directory will be checked against the input-rule (without the leading input-directory-path). Required by run and plan. Can be given more than once - kits with the same name are merged across all directories." },
+	OptionDef { long: T_ROOT_RULES, short: None, arity: Arity::Single, value: "DIR=FILE", help: "Map the files of input-directory DIR by the ruleset FILE instead of --rules. Can be given once per input-directory." },
+	OptionDef { long: T_NAMESPACE, short: None, arity: Arity::Flag, value: "", help: "Prefix the index-value (and so the kit-name) of every sample with the name of it's input-directory (VendorA/KitA), so kits of different input-directories are not merged." },
+	OptionDef { long: T_FROM_LIST, short: None, arity: Arity::Single, value: "FILE", help: "Process the files listed in FILE (- for stdin) instead of walking the input-directories. Paths are separated by newlines, or by NUL-characters if the list contains any (find -print0). Listed files outside of every input-directory are skipped." },
+	OptionDef { long: T_LIST_BASE, short: None, arity: Arity::Single, value: "DIR", help: "Directory the relative paths of --from-list are relative to (default: the current directory)." },
+	OptionDef { long: T_EXCLUDE, short: None, arity: Arity::Multiple, value: "GLOB...", help: "Skip files and directories matching any of the gitignore-style patterns (*.txt, Loops/, **/old/*). Directories may also contain a .kitcatignore file with one pattern per line." },
+	OptionDef { long: T_INCLUDE, short: None, arity: Arity::Multiple, value: "GLOB...", help: "Only process files matching any of the gitignore-style patterns (*.wav *.aif)." },
+	OptionDef { long: T_NO_DEFAULT_EXCLUDES, short: None, arity: Arity::Flag, value: "", help: "Don't skip the debris of file-managers and DAWs (.DS_Store, ._*, __MACOSX/, Thumbs.db, desktop.ini, *.asd, ...)." },
+	OptionDef { long: T_MAX_DEPTH, short: None, arity: Arity::Single, value: "COUNT", help: "Only walk COUNT levels below the input-directory (1 = only files directly inside of it)." },
+	OptionDef { long: T_FOLLOW_LINKS, short: None, arity: Arity::Flag, value: "", help: "Follow links to files and directories while walking the input-directory (links to a directory containing them are skipped)." },
+	OptionDef { long: T_ARCHIVES, short: None, arity: Arity::Flag, value: "", help: "Walk .zip, .tar and .tar.gz archives like directories and extract the selected samples straight into the output-directory. Requires --copy or --auto." },
+	OptionDef { long: T_OUTPUT, short: Some('o'), arity: Arity::Single, value: "DIR", help: "Output-directory - files will be written into this directory (use an asterisk (*) to use the base-name of the (first) input-directory; default is *_remapped)" }
+];
+
+pub fn setup_default_config() -> RuntimeConfig
 {
 	// DEFAULTS:
 	RuntimeConfig {
 		me: String::from(""),
+		command: Command::Run,
 		help: false,
 		dry: false,
-		verbose: false, 
-		soft: false, 
-		copy: false, 
-		kits: vec![], 
+		force: false,
+		verbose: false,
+		soft: false,
+		copy: false,
+		reflink: false,
+		auto: false,
+		kits: vec![],
 		trunc: 0, // <-- 0 = no truncating
 		rules: String::from(""),
 		input: String::from(""),
-		output: String::from("*_remapped")
+		inputs: vec![],
+		root_rules: vec![],
+		namespace: false,
+		from_list: String::from(""),
+		list_base: String::from(""),
+		excludes: vec![],
+		includes: vec![],
+		default_excludes: true,
+		max_depth: 0,
+		follow_links: false,
+		archives: false,
+		output: String::from("*_remapped"),
+		on_collision: CollisionPolicy::KeepFirst,
+		on_existing: ExistingPolicy::Error,
+		link_style: LinkStyle::Absolute,
+		preserve_mtime: false,
+		verify: false,
+		library_root: String::from(""),
+		pack: PackFormat::None,
+		dedup: DedupMode::None,
+		on_duplicate: DuplicatePolicy::Report,
+		sync: false,
+		full: false,
+		resume: false,
+		hash: false,
+		jobs: 0,
+		stream: false,
+		normalize: Normalization::Keep,
+		operands: vec![]
 	}
 }
 
-fn add_trailing_slash(to: &str) -> String 
+impl RuntimeConfig
 {
-	let mut out = to.to_string();
-	let last: char = *to.chars().rev().take(1).collect::<Vec<char>>().get(0).unwrap();
-
-	if last != '/' && last != '\\'
+	/// One configuration per input-directory, using the ruleset assigned to it (or --rules) - just a copy of this one if there is no input-directory.
+	pub fn roots(&self) -> Vec<RuntimeConfig>
 	{
-		#[cfg(target_family = "unix")]
-			out.push_str("/");
+		if self.inputs.is_empty()
+		{
+			return vec![self.clone()];
+		}
+
+		self.inputs.iter().map(|input|
+		{
+			let mut out = self.clone();
+
+			out.input = input.to_string();
+
+			if let Some((_, rules)) = self.root_rules.iter().find(|(root, _)| root == input)
+			{
+				out.rules = rules.to_string();
+			}
+
+			out
+		}).collect()
+	}
 
-		#[cfg(target_family = "windows")]
-			out.push_str("\\");
+	/// Name of the input-directory - used as namespace of it's kits.
+	pub fn root_name(&self) -> String
+	{
+		Path::new(&self.input).file_name().map_or(String::new(), |n| n.to_string_lossy().to_string())
 	}
 
-	out.to_string()
+	/// Count of workers to use - one per cpu if not configured.
+	pub fn workers(&self) -> usize
+	{
+		match self.jobs
+		{
+			0 => parallel::default_workers(),
+			j => j
+		}
+	}
 }
 
-fn to_absolute_path(path: &str) -> String
+fn to_absolute_path(path: &str) -> Result<String>
 {
-	println!("{:?}", path.is_empty());
 	if path.is_empty()
 	{
-		return path.to_string();
+		return Ok(path.to_string());
 	}
 
 	// also this will fail if the path does not exist
-	let pb = std::path::PathBuf::from(path); 
-	pb.canonicalize().unwrap().as_path().to_str().unwrap().to_string()
-	.replace("\\\\?\\", "") // windows special quirks https://docs.microsoft.com/en-us/windows/win32/fileio/naming-a-file#fully-qualified-vs-relative-paths
+	let pb = std::path::PathBuf::from(path);
+	let absolute = pb.canonicalize().map_err(|e| error::io(path, e))?;
+
+	Ok(absolute.to_string_lossy().to_string()
+	.replace("\\\\?\\", "")) // windows special quirks https://docs.microsoft.com/en-us/windows/win32/fileio/naming-a-file#fully-qualified-vs-relative-paths
+}
+
+// unlike the input-directory the output-directory does not need to exist yet
+fn to_absolute_output(path: &str) -> Result<String>
+{
+	let pb = std::path::PathBuf::from(path);
+
+	if pb.is_absolute()
+	{
+		return Ok(path.to_string());
+	}
+
+	let cwd = env::current_dir().map_err(|e| error::io(".", e))?;
+	Ok(cwd.join(pb).to_string_lossy().to_string())
 }
 
-pub fn from_args() -> RuntimeConfig
+pub fn from_args() -> Result<RuntimeConfig>
 {
 	let args: Vec<String> = env::args().collect();
-	let mut out = process_args(args);
-	out.output = add_trailing_slash(&target_name(&out.output, &out.input));
-	out
+	let mut out = process_args(args)?;
+	out.output = to_absolute_output(&target_name(&out.output, &out.input))?;
+	Ok(out)
 }
 
-fn target_name(output_path: &String, input_path: &str) -> String
+fn target_name(output_path: &str, input_path: &str) -> String
 {
 	if input_path.is_empty()// can't use
 	{
@@ -118,141 +390,427 @@ fn target_name(output_path: &String, input_path: &str) -> String
 	}
 
 	let source_path = Path::new(&input_path);
-	let name = source_path.file_name().unwrap().to_string_lossy().replace("/", ""); // Name of the working-directory and strip all occurences of / for windoze
-	let output_path_out = output_path.replace("*", &name); // * (if given) will be replaced with the name of the working-directory
+	let name = match source_path.file_name()
+	{
+		None => return output_path.to_string(),
+		Some(n) => n.to_string_lossy().replace('/', "") // Name of the working-directory and strip all occurences of / for windoze
+	};
+
+	output_path.replace('*', &name) // * (if given) will be replaced with the name of the working-directory
+}
+
+fn find_long(name: &str) -> Result<&'static OptionDef>
+{
+	match OPTIONS.iter().find(|o| o.long == name)
+	{
+		Some(o) => Ok(o),
+		None => Err(error::invalid_argument(&[T_LONG_INDICATOR, name].join(""), "unknown option - see --help"))
+	}
+}
 
-	output_path_out.to_string()
+fn find_short(name: char, token: &str) -> Result<&'static OptionDef>
+{
+	match OPTIONS.iter().find(|o| o.short == Some(name))
+	{
+		Some(o) => Ok(o),
+		None => Err(error::invalid_argument(token, &format!("unknown option {}{} - see --help", T_SHORT_INDICATOR, name)))
+	}
+}
+
+fn is_option(arg: &str) -> bool
+{
+	arg.len() > 1 && arg.starts_with(T_SHORT_INDICATOR)
 }
 
-fn process_args(args: Vec<String>) -> RuntimeConfig 
+pub fn process_args(args: Vec<String>) -> Result<RuntimeConfig>
 {
 	let mut out = setup_default_config();
-	let mut buffer: Vec<String> = vec![];
-	let mut proc_token = "";
+	let mut args = args.into_iter().peekable();
 
-	if args.len() == 1 // first argument is always set (= name of this binary)
+	out.me = args.next().unwrap_or_default(); // first argument is always set (= name of this binary)
+
+	if let Ok(root) = env::var(ENV_LIBRARY_ROOT)
 	{
-		return out;
+		out.library_root = to_absolute_output(&root)?;
 	}
 
-	for (i, elem) in args.iter().enumerate()
+	match args.peek()
 	{
-		if i == 0
+		None => { out.help = true; return Ok(out) },
+		Some(first) if !is_option(first) && first != T_LONG_INDICATOR =>
 		{
-			out.me = String::from(elem);
-			
-			continue;
+			out.command = match COMMANDS.iter().find(|c| c.name == first)
+			{
+				Some(c) => c.command,
+				None => return Err(error::invalid_argument(first, "unknown command - see --help"))
+			};
+
+			args.next();
+		},
+		_ => {}
+	}
+
+	let takes_operands = COMMANDS.iter().any(|c| c.command == out.command && c.max_operands > 0);
+
+	while let Some(arg) = args.next()
+	{
+		if arg == T_LONG_INDICATOR
+		{
+			out.operands.extend(args.by_ref());
+			break;
 		}
 
-		if elem.get(0..1).unwrap() == T_INDICATOR
-		{	
-			process_token(proc_token, &mut out, &buffer);
+		if !is_option(&arg)
+		{
+			out.operands.push(arg);
+			continue;
+		}
 
-			if elem.len() < 2
+		let mut values: Vec<String> = vec![];
+		let def = if let Some(long) = arg.strip_prefix(T_LONG_INDICATOR)
+		{
+			match long.split_once(T_VALUE_INDICATOR)
 			{
-				warn!("Missing token name after indicator {:?} - skipping", T_INDICATOR);
-				continue;
+				Some((name, value)) => { values.push(value.to_string()); find_long(name)? },
+				None => find_long(long)?
 			}
-		
-			proc_token = elem.get(1..2).unwrap();
-			buffer.clear();
 		}
-		else 
+		else
+		{
+			if arg.len() > 2 && OPTIONS.iter().any(|o| o.long == &arg[1..])
+			{
+				return Err(error::invalid_argument(&arg, &format!("unknown option - did you mean {}{}?", T_LONG_INDICATOR, &arg[1..])));
+			}
+
+			// clustered short flags (-dv); only the last one may take a value
+			let shorts: Vec<char> = arg.chars().skip(1).collect();
+			let (last, flags) = shorts.split_last().unwrap_or((&' ', &[]));
+
+			for flag in flags
+			{
+				let flag_def = find_short(*flag, &arg)?;
+
+				if flag_def.arity != Arity::Flag
+				{
+					return Err(error::invalid_argument(&arg, &format!("{}{} needs a value and must be the last option in a group", T_SHORT_INDICATOR, flag)));
+				}
+
+				apply_option(flag_def, vec![], &mut out)?;
+			}
+
+			find_short(*last, &arg)?
+		};
+
+		match def.arity
 		{
-			buffer.push(String::from(elem));
+			Arity::Flag if !values.is_empty() => return Err(error::invalid_argument(&arg, "option does not take a value")),
+			Arity::Flag => {},
+			Arity::Single if values.is_empty() =>
+			{
+				match args.next_if(|a| !is_option(a) && a != T_LONG_INDICATOR)
+				{
+					Some(value) => values.push(value),
+					None => return Err(error::invalid_argument(&arg, &format!("missing value {}", def.value)))
+				}
+			},
+			Arity::Single => {},
+			Arity::Multiple if !values.is_empty() => {}, // --kits=KitA takes just this value
+			Arity::Multiple =>
+			{
+				let limit = if takes_operands { 1 } else { usize::MAX };
+
+				while values.len() < limit
+				{
+					match args.next_if(|a| !is_option(a) && a != T_LONG_INDICATOR)
+					{
+						Some(value) => values.push(value),
+						None => break
+					}
+				}
+
+				if values.is_empty()
+				{
+					return Err(error::invalid_argument(&arg, &format!("missing value {}", def.value)));
+				}
+			}
 		}
+
+		apply_option(def, values, &mut out)?;
 	};
 
-	process_token(proc_token, &mut out, &buffer); out
+	validate(&out)?;
+	Ok(out)
 }
 
-fn read_buffer(token: &str, buffer: &Vec<String>, ensure_amount: usize) -> Vec<String>
+fn apply_option(def: &OptionDef, mut values: Vec<String>, config: &mut RuntimeConfig) -> Result<()>
 {
-	if buffer.len() < ensure_amount
+	let token = &[T_LONG_INDICATOR, def.long].join("");
+
+	match def.long
 	{
-		panic!("Too few arguments for parameter {:?} - got {:?} need at least {:?}", token, buffer.len(), ensure_amount)
+		T_VERBOSE => config.verbose = true,
+		T_DRY => config.dry = true,
+		T_FORCE => config.force = true,
+		T_SOFT => config.soft = true,
+		T_COPY => config.copy = true,
+		T_REFLINK => config.reflink = true,
+		T_AUTO => config.auto = true,
+		T_SYNC => config.sync = true,
+		T_FULL => config.full = true,
+		T_RESUME => config.resume = true,
+		T_HASH => config.hash = true,
+		T_STREAM => config.stream = true,
+		T_KITS => config.kits.append(&mut values),
+		T_TRUNC =>
+		{
+			let value = values.remove(0);
+
+			config.trunc = value.parse::<u8>().map_err(|e| error::invalid_argument(token, &format!("{:?} is not a number between 0 and 255 ({})", value, e)))?
+		},
+		T_JOBS =>
+		{
+			let value = values.remove(0);
+
+			config.jobs = value.parse::<usize>().ok().filter(|j| *j > 0).ok_or_else(|| error::invalid_argument(token, &format!("{:?} is not a number greater than 0", value)))?
+		},
+		T_RULES => config.rules = values.remove(0),
+		T_OUTPUT => config.output = values.remove(0),
+		T_ON_COLLISION =>
+		{
+			let value = values.remove(0);
+
+			config.on_collision = CollisionPolicy::from_name(&value).ok_or_else(|| error::invalid_argument(token, &format!("{:?} is not one of {}", value, CollisionPolicy::NAMES.join(", "))))?
+		},
+		T_ON_EXISTING =>
+		{
+			let value = values.remove(0);
+
+			config.on_existing = ExistingPolicy::from_name(&value).ok_or_else(|| error::invalid_argument(token, &format!("{:?} is not one of {}", value, ExistingPolicy::NAMES.join(", "))))?
+		},
+		T_LINK_STYLE =>
+		{
+			let value = values.remove(0);
+
+			config.link_style = LinkStyle::from_name(&value).ok_or_else(|| error::invalid_argument(token, &format!("{:?} is not one of {}", value, LinkStyle::NAMES.join(", "))))?
+		},
+		T_LIBRARY_ROOT => config.library_root = to_absolute_output(&values.remove(0))?,
+		T_PRESERVE =>
+		{
+			for value in values.drain(..)
+			{
+				match value.as_str()
+				{
+					PRESERVE_MTIME => config.preserve_mtime = true,
+					_ => return Err(error::invalid_argument(token, &format!("{:?} is not {}", value, PRESERVE_MTIME)))
+				}
+			}
+		},
+		T_VERIFY => config.verify = true,
+		T_PACK =>
+		{
+			let value = values.remove(0);
+
+			config.pack = PackFormat::from_name(&value).ok_or_else(|| error::invalid_argument(token, &format!("{:?} is not one of {}", value, PackFormat::NAMES.join(", "))))?
+		},
+		T_DEDUP =>
+		{
+			let value = values.remove(0);
+
+			config.dedup = DedupMode::from_name(&value).ok_or_else(|| error::invalid_argument(token, &format!("{:?} is not one of {}", value, DedupMode::NAMES.join(", "))))?
+		},
+		T_ON_DUPLICATE =>
+		{
+			let value = values.remove(0);
+
+			config.on_duplicate = DuplicatePolicy::from_name(&value).ok_or_else(|| error::invalid_argument(token, &format!("{:?} is not one of {}", value, DuplicatePolicy::NAMES.join(", "))))?
+		},
+		T_NORMALIZE =>
+		{
+			let value = values.remove(0);
+
+			config.normalize = Normalization::from_name(&value).ok_or_else(|| error::invalid_argument(token, &format!("{:?} is not one of {}", value, Normalization::NAMES.join(", "))))?
+		},
+		T_INPUT =>
+		{
+			let input = to_absolute_path(&values.remove(0))?;
+
+			if config.inputs.contains(&input)
+			{
+				return Err(error::invalid_argument(token, &format!("{:?} is given more than once", input)));
+			}
+
+			if config.input.is_empty()
+			{
+				config.input = input.to_string();
+			}
+
+			config.inputs.push(input);
+		},
+		T_ROOT_RULES =>
+		{
+			let value = values.remove(0);
+			let (input, rules) = value.split_once(T_VALUE_INDICATOR).ok_or_else(|| error::invalid_argument(token, &format!("{:?} is not of the form DIR=FILE", value)))?;
+
+			config.root_rules.push((to_absolute_path(input)?, rules.to_string()));
+		},
+		T_NAMESPACE => config.namespace = true,
+		T_FROM_LIST => config.from_list = values.remove(0),
+		T_LIST_BASE => config.list_base = to_absolute_path(&values.remove(0))?,
+		T_EXCLUDE => config.excludes.append(&mut values),
+		T_INCLUDE => config.includes.append(&mut values),
+		T_NO_DEFAULT_EXCLUDES => config.default_excludes = false,
+		T_FOLLOW_LINKS => config.follow_links = true,
+		T_ARCHIVES => config.archives = true,
+		T_MAX_DEPTH =>
+		{
+			let value = values.remove(0);
+
+			config.max_depth = value.parse::<usize>().ok().filter(|d| *d > 0).ok_or_else(|| error::invalid_argument(token, &format!("{:?} is not a number greater than 0", value)))?
+		},
+		T_HELP => config.help = true,
+		_ => return Err(error::invalid_argument(token, "option is defined but not handled"))
 	}
 
-	buffer.to_vec()
+	Ok(())
 }
 
-fn process_token(token: &str, config: &mut RuntimeConfig, buffer: &Vec<String>)
+fn validate(config: &RuntimeConfig) -> Result<()>
 {
-	if token == ""
+	if config.help || config.command == Command::Help
 	{
-		return;
+		return Ok(());
 	}
 
-	match token
+	let def = COMMANDS.iter().find(|c| c.command == config.command).unwrap_or(&COMMANDS[0]);
+
+	if config.operands.len() > def.max_operands
 	{
-		T_VERBOSE => config.verbose = true,
-		T_DRY => config.dry = true,
-		T_SOFT => config.soft = true,
-		T_COPY => config.copy = true,
-		T_KITS => config.kits = read_buffer(&token, &buffer, 1),
-		T_TRUNC => config.trunc = read_buffer(&token, &buffer, 1).get(0).unwrap().parse::<u8>().unwrap(),
-		T_RULES => config.rules = String::from(read_buffer(&token, &buffer, 1).get(0).unwrap()),
-		T_OUTPUT => config.output = String::from(read_buffer(&token, &buffer, 1).get(0).unwrap()),
-		T_INPUT => config.input = add_trailing_slash(&to_absolute_path(read_buffer(&token, &buffer, 1).get(0).unwrap())),
-		T_HELP => config.help = true,
-		_ => { 
-				warn!("Unknown token {:?}", token)
-		}
+		return Err(error::invalid_argument(&config.operands[def.max_operands], &format!("unexpected argument for command {:?}", def.name)));
 	}
-}
 
-pub fn print_help()
-{
-	println!("KITCAT {:} - Help", version::APP_VERSION);
-	println!("");
-	println!("OPTIONS");
-	println!("");
+	if config.operands.len() < def.min_operands
+	{
+		return Err(error::invalid_argument(def.name, &format!("missing {}", def.operands)));
+	}
 
-	println!("-help / -h:");
-	println!("\tPrint this help.");
+	if let Some((input, _)) = config.root_rules.iter().find(|(input, _)| !config.inputs.contains(input))
+	{
+		return Err(error::invalid_argument(&[T_LONG_INDICATOR, T_ROOT_RULES].join(""), &format!("{:?} is not one of the input-directories", input)));
+	}
 
-	println!("");
+	if !config.list_base.is_empty() && config.from_list.is_empty()
+	{
+		return Err(error::invalid_argument(&[T_LONG_INDICATOR, T_LIST_BASE].join(""), &format!("requires {}{}", T_LONG_INDICATOR, T_FROM_LIST)));
+	}
 
-	println!("-dry / -d:");
-	println!("\tSetup and process all files without copying/linking them.");
+	if config.archives && !config.copy && !config.auto && config.pack == PackFormat::None
+	{
+		return Err(error::invalid_argument(&[T_LONG_INDICATOR, T_ARCHIVES].join(""), &format!("samples can only be copied out of an archive - requires {}{} or {}{}", T_LONG_INDICATOR, T_COPY, T_LONG_INDICATOR, T_AUTO)));
+	}
 
-	println!("");
+	if config.pack != PackFormat::None
+	{
+		let conflict = [(config.soft, T_SOFT), (config.reflink, T_REFLINK), (config.auto, T_AUTO), (config.sync, T_SYNC), (config.stream, T_STREAM)].iter().find(|(set, _)| *set).map(|(_, option)| *option);
 
-	println!("-verbose / -v:");
-	println!("\tPrint more information to the stdout while processing.");
+		if let Some(option) = conflict
+		{
+			return Err(error::invalid_argument(&[T_LONG_INDICATOR, T_PACK].join(""), &format!("can't be combined with {}{}", T_LONG_INDICATOR, option)));
+		}
+	}
 
-	println!("");
+	if config.link_style != LinkStyle::Absolute && !config.soft && config.command != Command::Relink
+	{
+		return Err(error::invalid_argument(&[T_LONG_INDICATOR, T_LINK_STYLE].join(""), &format!("requires {}{}", T_LONG_INDICATOR, T_SOFT)));
+	}
 
-	println!("-soft / -s:");
-	println!("\tCreate soft-link instead of hard-link.");
+	if config.link_style == LinkStyle::Anchored && config.library_root.is_empty()
+	{
+		return Err(error::invalid_argument(&[T_LONG_INDICATOR, T_LINK_STYLE].join(""), &format!("{} requires {}{} (or ${})", LinkStyle::Anchored.name(), T_LONG_INDICATOR, T_LIBRARY_ROOT, ENV_LIBRARY_ROOT)));
+	}
 
-	println!("");
+	if config.resume && (config.command != Command::Run || config.pack != PackFormat::None)
+	{
+		return Err(error::invalid_argument(&[T_LONG_INDICATOR, T_RESUME].join(""), &format!("only runs writing into the output-directory can be resumed - requires command \"run\" without {}{}", T_LONG_INDICATOR, T_PACK)));
+	}
 
-	println!("-copy / -c:");
-	println!("\tCopy files instead of linking (if set: -s won’t be used).");
+	if config.on_duplicate != DuplicatePolicy::Report && config.dedup == DedupMode::None
+	{
+		return Err(error::invalid_argument(&[T_LONG_INDICATOR, T_ON_DUPLICATE].join(""), &format!("requires {}{}", T_LONG_INDICATOR, T_DEDUP)));
+	}
 
-	println!("");
+	if config.on_duplicate == DuplicatePolicy::Link
+	{
+		if !(config.copy || config.reflink || config.auto) || config.pack != PackFormat::None
+		{
+			return Err(error::invalid_argument(&[T_LONG_INDICATOR, T_ON_DUPLICATE].join(""), &format!("only copies can be linked to each other - requires {}{}, {}{} or {}{} without {}{}", T_LONG_INDICATOR, T_COPY, T_LONG_INDICATOR, T_REFLINK, T_LONG_INDICATOR, T_AUTO, T_LONG_INDICATOR, T_PACK)));
+		}
 
-	println!("-kits / -k:");
-	println!("\tSpace-separated list of names. Just process this kits(s). The name check is done on the configured group-index and it's value.");
+		if config.command == Command::Plan
+		{
+			return Err(error::invalid_argument(&[T_LONG_INDICATOR, T_ON_DUPLICATE].join(""), "links between duplicates can't be saved in a plan"));
+		}
+	}
 
-	println!("");
+	match config.command
+	{
+		Command::Run | Command::Plan | Command::Relink if config.input.is_empty() => Err(error::invalid_argument(&[T_LONG_INDICATOR, T_INPUT].join(""), &format!("is required by command {:?}", def.name))),
+		Command::Run if config.stream && config.dedup != DedupMode::None => Err(error::invalid_argument(&[T_LONG_INDICATOR, T_STREAM].join(""), &format!("can't be combined with {}{}", T_LONG_INDICATOR, T_DEDUP))),
+		Command::Run if config.stream && config.sync => Err(error::invalid_argument(&[T_LONG_INDICATOR, T_STREAM].join(""), &format!("can't be combined with {}{}", T_LONG_INDICATOR, T_SYNC))),
+		Command::Undo if config.input.is_empty() && config.output.contains('*') => Err(error::invalid_argument(&[T_LONG_INDICATOR, T_OUTPUT].join(""), &format!("is required by command {:?}", def.name))),
+		_ => Ok(())
+	}
+}
 
-	println!("-trunc / -t:");
-	println!("\tTruncate all kits containing less than „-t“ samples.");
+pub fn print_help()
+{
+	println!("KITCAT {:} - Help", version::APP_VERSION);
+	println!();
+	println!("USAGE");
+	println!();
+	println!("\tkitcat [COMMAND] [OPTIONS] [--] [ARGUMENTS]");
+	println!();
+	println!("\t{}", LIST_RULE);
+	println!();
+	println!("COMMANDS");
+	println!();
+
+	for def in COMMANDS.iter()
+	{
+		if def.operands.is_empty()
+		{
+			println!("{}:", def.name);
+		}
+		else
+		{
+			println!("{} {}:", def.name, def.operands);
+		}
 
-	println!("");
+		println!("\t{}", def.help);
+		println!();
+	}
 
-	println!("-rules / -r:");
-	println!("\tPath to a ini-file, overriding the internal ruleset.");
+	println!("OPTIONS");
+	println!();
 
-	println!("");
+	for def in OPTIONS.iter()
+	{
+		let mut names = [T_LONG_INDICATOR, def.long].join("");
 
-	println!("-input / -i:");
-	println!("\tAll files inside this directory will be checked against the input-rule (without the leading input-directory-path; default is the directory of the kitcat-binary (.) )");
+		if let Some(short) = def.short
+		{
+			names.push_str(&format!(" / {}{}", T_SHORT_INDICATOR, short));
+		}
 
-	println!("");
+		if def.value.is_empty()
+		{
+			println!("{}:", names);
+		}
+		else
+		{
+			println!("{} {}:", names, def.value);
+		}
 
-	println!("-output / -o:");
-	println!("\tOutput-directory - files will be written into this directory (use an asterisk (*) to use the input-directories base-name; default is *_remapped)");
-}
\ No newline at end of file
+		println!("\t{}", def.help);
+		println!();
+	}
+}
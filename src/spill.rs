@@ -0,0 +1,328 @@
+use std::cell::RefCell;
+use std::collections::*;
+use std::collections::hash_map::DefaultHasher;
+use std::convert::TryFrom;
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// count of spill-files created by this process - part of their names
+static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Append-only temporary file of key-value records, looked up by the hash of their key: only the hashes and offsets of the records are kept in memory.
+/// Hash hits are confirmed against the key stored in the file, so keys with the same hash never stand in for each other. The file is removed when dropped.
+pub struct Spill
+{
+	path: PathBuf,
+	writer: RefCell<BufWriter<File>>,
+	reader: RefCell<BufReader<File>>,
+	end: u64, // offset of the next record
+	offsets: HashMap<u64, u64>, // hash of a key -> offset of it's latest record
+	more: HashMap<u64, Vec<u64>> // offsets of the latest records of further keys with the same hash
+}
+
+impl std::fmt::Debug for Spill
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [SPILL] path: {:?} end: {:?} keys: {:?} }}",
+    		self.path,
+    		self.end,
+    		self.len()
+		)
+	}
+}
+
+fn hash_of(key: &[u8]) -> u64
+{
+	let mut hasher = DefaultHasher::new();
+
+	key.hash(&mut hasher);
+	hasher.finish()
+}
+
+/// Bytes of the OS-string as key - without the loss of a conversion to UTF-8.
+#[cfg(unix)]
+pub fn os_key(s: &OsStr) -> Vec<u8>
+{
+	use std::os::unix::ffi::OsStrExt;
+
+	s.as_bytes().to_vec()
+}
+
+/// Bytes of the OS-string as key - without the loss of a conversion to UTF-8.
+#[cfg(windows)]
+pub fn os_key(s: &OsStr) -> Vec<u8>
+{
+	use std::os::windows::ffi::OsStrExt;
+
+	s.encode_wide().flat_map(u16::to_le_bytes).collect()
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn os_key(s: &OsStr) -> Vec<u8>
+{
+	s.to_string_lossy().as_bytes().to_vec()
+}
+
+// records are stored as length (u32, little endian) and bytes of the key, followed by the ones of the value
+fn write_part(to: &mut dyn Write, part: &[u8]) -> std::io::Result<()>
+{
+	let len = u32::try_from(part.len()).map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, "record too large"))?;
+
+	to.write_all(&len.to_le_bytes())?;
+	to.write_all(part)
+}
+
+fn read_part(from: &mut dyn Read) -> std::io::Result<Vec<u8>>
+{
+	let mut len = [0u8; 4];
+
+	from.read_exact(&mut len)?;
+
+	let mut out = vec![0u8; u32::from_le_bytes(len) as usize];
+
+	from.read_exact(&mut out)?;
+	Ok(out)
+}
+
+impl Spill
+{
+	/// Creates an empty spill-file inside the temporary directory of the system.
+	pub fn new() -> std::io::Result<Spill>
+	{
+		let path = std::env::temp_dir().join(format!("kitcat-{}-{}.spill", std::process::id(), SPILLS.fetch_add(1, Ordering::Relaxed)));
+		let writer = OpenOptions::new().write(true).create_new(true).open(&path)?;
+		let reader = File::open(&path)?;
+
+		Ok(Spill { path, writer: RefCell::new(BufWriter::new(writer)), reader: RefCell::new(BufReader::new(reader)), end: 0, offsets: HashMap::new(), more: HashMap::new() })
+	}
+
+	/// Count of keys.
+	pub fn len(&self) -> usize
+	{
+		self.offsets.len() + self.more.values().map(Vec::len).sum::<usize>()
+	}
+
+	pub fn is_empty(&self) -> bool
+	{
+		self.offsets.is_empty()
+	}
+
+	/// Offset the next record is written to - records written before have a lower one.
+	pub fn end(&self) -> u64
+	{
+		self.end
+	}
+
+	fn read(&self, offset: u64) -> std::io::Result<(Vec<u8>, Vec<u8>)>
+	{
+		self.writer.borrow_mut().flush()?;
+
+		let mut reader = self.reader.borrow_mut();
+
+		reader.seek(SeekFrom::Start(offset))?;
+
+		let key = read_part(&mut *reader)?;
+		let value = read_part(&mut *reader)?;
+
+		Ok((key, value))
+	}
+
+	// offset of the latest record of the key
+	fn find(&self, key: &[u8]) -> std::io::Result<Option<u64>>
+	{
+		let hash = hash_of(key);
+
+		for offset in self.offsets.get(&hash).into_iter().chain(self.more.get(&hash).into_iter().flatten())
+		{
+			if self.read(*offset)?.0 == key
+			{
+				return Ok(Some(*offset));
+			}
+		}
+
+		Ok(None)
+	}
+
+	pub fn contains(&self, key: &[u8]) -> std::io::Result<bool>
+	{
+		Ok(self.find(key)?.is_some())
+	}
+
+	/// Value of the latest record of the key.
+	pub fn get(&self, key: &[u8]) -> std::io::Result<Option<Vec<u8>>>
+	{
+		match self.find(key)?
+		{
+			None => Ok(None),
+			Some(offset) => Ok(Some(self.read(offset)?.1))
+		}
+	}
+
+	/// Appends a record - it replaces the previous one of the key (if any).
+	pub fn insert(&mut self, key: &[u8], value: &[u8]) -> std::io::Result<()>
+	{
+		let previous = self.find(key)?;
+		let offset = self.end;
+
+		{
+			let mut writer = self.writer.borrow_mut();
+
+			write_part(&mut *writer, key)?;
+			write_part(&mut *writer, value)?;
+		}
+
+		self.end += 8 + key.len() as u64 + value.len() as u64;
+
+		let hash = hash_of(key);
+
+		match previous
+		{
+			Some(p) if self.offsets.get(&hash) == Some(&p) => { self.offsets.insert(hash, offset); },
+			Some(p) => self.more.get_mut(&hash).into_iter().flatten().filter(|o| **o == p).for_each(|o| *o = offset),
+			None => match self.offsets.entry(hash)
+			{
+				hash_map::Entry::Vacant(e) => { e.insert(offset); },
+				hash_map::Entry::Occupied(_) => self.more.entry(hash).or_default().push(offset)
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Forgets the key - it's records stay in the file, but are neither found nor handed to each anymore. Returns false if there was none.
+	pub fn remove(&mut self, key: &[u8]) -> std::io::Result<bool>
+	{
+		let offset = match self.find(key)?
+		{
+			None => return Ok(false),
+			Some(o) => o
+		};
+
+		let hash = hash_of(key);
+
+		if self.offsets.get(&hash) == Some(&offset)
+		{
+			match self.more.get_mut(&hash).and_then(Vec::pop)
+			{
+				None => { self.offsets.remove(&hash); },
+				Some(next) => { self.offsets.insert(hash, next); }
+			}
+		}
+		else if let Some(more) = self.more.get_mut(&hash)
+		{
+			more.retain(|o| *o != offset);
+		}
+
+		if self.more.get(&hash).is_some_and(Vec::is_empty)
+		{
+			self.more.remove(&hash);
+		}
+
+		Ok(true)
+	}
+
+	// true if the record at the offset is the latest one of it's key
+	fn is_latest(&self, key: &[u8], offset: u64) -> bool
+	{
+		let hash = hash_of(key);
+
+		self.offsets.get(&hash) == Some(&offset) || self.more.get(&hash).is_some_and(|m| m.contains(&offset))
+	}
+
+	/// Hands the offset, key and value of the latest record of every key to f - in order of their offsets, reading the file once.
+	pub fn each<F>(&self, mut f: F) -> std::io::Result<()>
+	where
+		F: FnMut(u64, &[u8], &[u8]) -> std::io::Result<()>
+	{
+		self.writer.borrow_mut().flush()?;
+
+		let mut reader = BufReader::new(File::open(&self.path)?);
+		let mut offset: u64 = 0;
+
+		while offset < self.end
+		{
+			let key = read_part(&mut reader)?;
+			let value = read_part(&mut reader)?;
+
+			if self.is_latest(&key, offset)
+			{
+				f(offset, &key, &value)?;
+			}
+
+			offset += 8 + key.len() as u64 + value.len() as u64;
+		}
+
+		Ok(())
+	}
+}
+
+impl Drop for Spill
+{
+	fn drop(&mut self)
+	{
+		let _ = std::fs::remove_file(&self.path);
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	// latest records of the spill in the order each hands them over
+	fn records(spill: &Spill) -> Vec<(String, String)>
+	{
+		let mut out = vec![];
+
+		spill.each(|_, key, value| { out.push((String::from_utf8_lossy(key).to_string(), String::from_utf8_lossy(value).to_string())); Ok(()) }).unwrap();
+		out
+	}
+
+	#[test]
+	fn later_records_replace_the_earlier_ones_of_their_key()
+	{
+		let mut spill = Spill::new().unwrap();
+
+		spill.insert(b"KitA/Kick.wav", b"1").unwrap();
+		spill.insert(b"KitA/Snare.wav", b"2").unwrap();
+		spill.insert(b"KitA/Kick.wav", b"3").unwrap();
+
+		assert_eq!(spill.len(), 2);
+		assert_eq!(spill.get(b"KitA/Kick.wav").unwrap(), Some(b"3".to_vec()));
+		assert_eq!(spill.get(b"KitA/Hat.wav").unwrap(), None);
+		assert_eq!(records(&spill), [("KitA/Snare.wav".to_string(), "2".to_string()), ("KitA/Kick.wav".to_string(), "3".to_string())]);
+	}
+
+	#[test]
+	fn removed_keys_are_neither_found_nor_handed_over()
+	{
+		let mut spill = Spill::new().unwrap();
+
+		spill.insert(b"KitA/Kick.wav", b"1").unwrap();
+		spill.insert(b"KitA/Snare.wav", b"2").unwrap();
+
+		assert!(spill.remove(b"KitA/Kick.wav").unwrap());
+		assert!(!spill.remove(b"KitA/Kick.wav").unwrap());
+		assert!(!spill.contains(b"KitA/Kick.wav").unwrap());
+		assert_eq!(records(&spill), [("KitA/Snare.wav".to_string(), "2".to_string())]);
+
+		spill.remove(b"KitA/Snare.wav").unwrap();
+		assert!(spill.is_empty());
+	}
+
+	#[test]
+	fn the_file_is_removed_with_the_spill()
+	{
+		let spill = Spill::new().unwrap();
+		let path = spill.path.to_path_buf();
+
+		assert!(path.exists());
+		drop(spill);
+		assert!(!path.exists());
+	}
+}
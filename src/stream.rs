@@ -0,0 +1,363 @@
+extern crate log;
+	use log::*;
+
+use std::collections::*;
+use std::path::{Path, PathBuf};
+use crate::runtimeconfig::RuntimeConfig;
+use crate::error;
+	use crate::error::{Error, Result};
+use crate::catalog;
+	use crate::catalog::{Collision, CollisionPolicy, Sample};
+use crate::ruleset;
+	use crate::ruleset::CompiledRuleset;
+use crate::ignore::{Entry, WalkFilter};
+use crate::roots;
+	use crate::roots::Root;
+use crate::writer;
+	use crate::writer::{CopyOptions, SoftLinks, SpaceCheck, WriteMode, WriteSummary};
+use crate::manifest::Manifest;
+use crate::journal::Journal;
+use crate::sync;
+use crate::progress::Progress;
+use crate::interrupt;
+use crate::spill;
+	use crate::spill::Spill;
+
+// count of paths matched (and samples written) at once
+const BATCH_SIZE: usize = 4096;
+
+/// Relative paths of all regular files below the input-directory that pass the walk-filter, read one directory at a time.
+/// Entries of each directory are visited in order of their names, so the order is the same as the one of collect.
+pub struct Paths
+{
+	root: PathBuf,
+	filter: WalkFilter,
+	pending: Vec<std::vec::IntoIter<Entry>> // not yet visited entries of every directory on the current path
+}
+
+impl Paths
+{
+	pub fn new(root: &str, rcon: &RuntimeConfig) -> Result<Paths>
+	{
+		let filter = WalkFilter::from_config(rcon)?;
+		let entries = filter.read(&filter.root(Path::new(root))).map_err(|e| error::io(root, e))?;
+
+		info!("Walking with {:?}", filter);
+
+		Ok(Paths { root: PathBuf::from(root), filter, pending: vec![entries.into_iter()] })
+	}
+}
+
+impl Iterator for Paths
+{
+	type Item = PathBuf;
+
+	fn next(&mut self) -> Option<PathBuf>
+	{
+		loop
+		{
+			let entry = match self.pending.last_mut()?.next()
+			{
+				None => { self.pending.pop(); continue },
+				Some(e) => e
+			};
+
+			let path = match entry
+			{
+				Entry::File(p) => p,
+				Entry::Excluded(p) => { debug!("Excluded {:?}", p); continue },
+				Entry::Skipped(p, reason) => { warn!("Skipping {:?} - {}", p, reason); continue },
+				Entry::Dir(dir) =>
+				{
+					match self.filter.read(&dir)
+					{
+						Err(e) => warn!("Skipping {:?} - {}", dir.path, e),
+						Ok(entries) => self.pending.push(entries.into_iter())
+					}
+
+					continue;
+				}
+			};
+
+			return Some(path.strip_prefix(&self.root).unwrap_or(&path).to_path_buf());
+		}
+	}
+}
+
+// target-paths taken by earlier samples - kept in a spill-file, so only their hashes stay in memory
+// a hash hit is confirmed by the target-path itself, so samples are never skipped or renamed because of a different path with the same hash
+struct Taken
+{
+	spill: Spill
+}
+
+impl Taken
+{
+	fn new() -> Result<Taken>
+	{
+		Ok(Taken { spill: Spill::new().map_err(|e| error::io(std::env::temp_dir(), e))? })
+	}
+
+	fn contains(&self, target: &Path) -> Result<bool>
+	{
+		self.spill.contains(&spill::os_key(&catalog::collision_key(target))).map_err(|e| error::io(target, e))
+	}
+
+	// false if the target-path was already taken
+	fn insert(&mut self, target: &Path) -> Result<bool>
+	{
+		if self.contains(target)?
+		{
+			return Ok(false);
+		}
+
+		self.spill.insert(&spill::os_key(&catalog::collision_key(target)), &[]).map_err(|e| error::io(target, e))?;
+		Ok(true)
+	}
+}
+
+// logs the collisions of one batch - they are not kept for the whole library
+fn report_batch(collisions: &mut Vec<Collision>, policy: CollisionPolicy) -> usize
+{
+	let count = collisions.len();
+
+	collisions.sort_by(|a, b| a.kit.cmp(&b.kit));
+	catalog::report_collisions(collisions, policy);
+	collisions.clear();
+
+	count
+}
+
+struct Pipeline<'a>
+{
+	roots: Vec<(&'a Root, CompiledRuleset)>,
+	lists: Option<Vec<Vec<PathBuf>>> // paths of every root out of the file-list - None to walk the input-directories
+}
+
+impl<'a> Pipeline<'a>
+{
+	// walks and matches the input-directories one after another, handing batches of (kit-name, sample) to f
+	fn each_batch<F>(&self, mut f: F) -> Result<()>
+	where
+		F: FnMut(Vec<(String, Sample)>) -> Result<()>
+	{
+		let cached = HashMap::new(); // the manifest is written to while streaming - every path is matched again
+
+		for (i, (root, rules)) in self.roots.iter().enumerate()
+		{
+			let mut paths: Box<dyn Iterator<Item = PathBuf>> = match &self.lists
+			{
+				Some(lists) => Box::new(lists[i].iter().cloned()),
+				None => Box::new(Paths::new(&root.rcon.input, &root.rcon)?)
+			};
+
+			loop
+			{
+				if interrupt::requested()
+				{
+					info!("Stop requested - not walking any further");
+					return Ok(());
+				}
+
+				let batch: Vec<PathBuf> = paths.by_ref().take(BATCH_SIZE).collect();
+
+				if batch.is_empty()
+				{
+					break;
+				}
+
+				let (samples, _) = catalog::create_samples(&batch, rules, &root.ruleset, &root.rcon, &cached)?;
+				let mut named: Vec<(String, Sample)> = Vec::with_capacity(samples.len());
+
+				for sample in samples
+				{
+					named.push((catalog::kit_name(&sample, &root.ruleset)?, sample));
+				}
+
+				f(named)?;
+			}
+		}
+
+		Ok(())
+	}
+}
+
+/// Runs walk (or file-list) -> match -> filter -> write batch by batch (input-directory by input-directory), so only the kit-sizes and the hashes of the taken target-paths
+/// and of the manifest-entries are kept in memory for the whole library (the paths and entries themselves are kept in spill-files, see Manifest::load_streamed).
+/// Collisions are reported batch by batch. Paths are always matched again (group-values of the manifest are not reused).
+/// Kit-sizes for --trunc are counted by an extra pass, --on-collision fail and the free space for copies are checked by extra passes before anything is written.
+/// Unlike a regular run the first sample of a target-path is the first one in order of the walk.
+/// Returns the summary and the count of samples skipped because they are unchanged since the last run (or completed by the resumed one).
+pub fn run(roots: &[Root], rcon: &RuntimeConfig, manifest: &mut Manifest, journal: &mut Journal, resumed: Option<&Progress>) -> Result<(WriteSummary, usize)>
+{
+	let mode = WriteMode::from_config(rcon);
+	let soft_links = SoftLinks::from_config(rcon);
+	let copies = CopyOptions::from_config(rcon);
+	let fingerprint = roots::fingerprint(roots);
+	let lists = if rcon.from_list.is_empty() { None } else { Some(roots::datasets(roots, rcon)?) };
+	let mut pipeline = Pipeline { roots: vec![], lists };
+
+	for root in roots.iter()
+	{
+		pipeline.roots.push((root, ruleset::compile(&root.ruleset)?));
+	}
+
+	info!("Streaming samples from {:?} in batches of {:?}", rcon.inputs, BATCH_SIZE);
+
+	let sizes: HashMap<String, usize> = if rcon.trunc > 0
+	{
+		info!("Counting kit-sizes");
+
+		let mut sizes: HashMap<String, usize> = HashMap::new();
+
+		pipeline.each_batch(|batch|
+		{
+			for (kit, _) in batch
+			{
+				*sizes.entry(kit).or_insert(0)+=1;
+			}
+
+			Ok(())
+		})?;
+
+		sizes
+	}
+	else
+	{
+		HashMap::new()
+	};
+
+	let keep = |kit: &str| -> bool
+	{
+		(rcon.trunc == 0 || sizes.get(kit).is_some_and(|s| *s >= usize::from(rcon.trunc)))
+		&& (rcon.kits.is_empty() || rcon.kits.iter().any(|k| k == kit))
+	};
+
+	if rcon.on_collision == CollisionPolicy::Fail
+	{
+		info!("Checking for target-path collisions");
+
+		let mut taken = Taken::new()?;
+		let mut count: usize = 0;
+
+		pipeline.each_batch(|batch|
+		{
+			let mut collisions: Vec<Collision> = vec![];
+
+			for (kit, sample) in batch.into_iter().filter(|(k, _)| keep(k))
+			{
+				if !taken.insert(&sample.target_path)?
+				{
+					collisions.push(Collision { kit, target: sample.target_path, source: sample.source_path, taken_by: PathBuf::new(), resolved: None });
+				}
+			}
+
+			count += report_batch(&mut collisions, rcon.on_collision);
+			Ok(())
+		})?;
+
+		if count > 0
+		{
+			return Err(Error::Collisions { count });
+		}
+	}
+
+	if !rcon.dry && (mode == WriteMode::Copy || mode == WriteMode::Auto)
+	{
+		info!("Checking the free space for the copies");
+
+		let mut space = SpaceCheck::new(rcon.on_existing);
+
+		pipeline.each_batch(|batch|
+		{
+			for (_, sample) in batch.into_iter().filter(|(k, _)| keep(k))
+			{
+				if rcon.full || !manifest.is_current(&sample)
+				{
+					space.add(&sample, mode);
+				}
+			}
+
+			Ok(())
+		})?;
+
+		space.check()?;
+	}
+
+	let mut summary = WriteSummary::new();
+	let mut unchanged: usize = 0;
+	let mut taken = Taken::new()?;
+
+	manifest.set_ruleset(&fingerprint);
+
+	pipeline.each_batch(|batch|
+	{
+		let mut kept: Vec<(String, Sample)> = vec![];
+		let mut collisions: Vec<Collision> = vec![];
+
+		for (kit, mut sample) in batch.into_iter().filter(|(k, _)| keep(k))
+		{
+			if !taken.insert(&sample.target_path)?
+			{
+				let mut collision = Collision { kit: kit.to_string(), target: sample.target_path.to_path_buf(), source: sample.source_path.to_path_buf(), taken_by: PathBuf::new(), resolved: None };
+				let failed: std::cell::RefCell<Option<Error>> = std::cell::RefCell::new(None); // route_collision can't hand errors of the lookup back
+				let routed = catalog::route_collision(&sample.target_path, rcon, &|t| taken.contains(t).unwrap_or_else(|e| { failed.borrow_mut().get_or_insert(e); true }));
+
+				if let Some(e) = failed.into_inner()
+				{
+					return Err(e);
+				}
+
+				match routed
+				{
+					None => { collisions.push(collision); continue },
+					Some(target) =>
+					{
+						taken.insert(&target)?;
+						sample.target_path = target.to_path_buf();
+						collision.resolved = Some(target);
+						collisions.push(collision);
+					}
+				}
+			}
+
+			if !rcon.full && sync::is_unchanged(&sample, manifest, journal, rcon)
+			{
+				unchanged+=1;
+				continue;
+			}
+
+			if resumed.is_some_and(|p| p.is_completed(&sample, manifest))
+			{
+				debug!("{:?} was completed by the resumed run", sample.target_path);
+				unchanged+=1;
+				continue;
+			}
+
+			kept.push((kit, sample));
+		}
+
+		report_batch(&mut collisions, rcon.on_collision);
+
+		if rcon.dry
+		{
+			info!("Not writing {:?} samples", kept.len());
+			return Ok(());
+		}
+
+		let jobs: Vec<(&Sample, WriteMode)> = kept.iter().map(|(_, sample)| (sample, mode)).collect();
+		let outcomes = writer::write_samples(&jobs, rcon.on_existing, &soft_links, &copies, journal, rcon.workers());
+
+		for ((kit, sample), (outcome, used)) in kept.iter().zip(outcomes)
+		{
+			manifest.record_outcome(kit, sample, used, &outcome, rcon.hash);
+			summary.count_sample(sample, &outcome, used);
+		}
+
+		Ok(())
+	})?;
+
+	info!("{}", summary);
+
+	Ok((summary, unchanged))
+}
@@ -0,0 +1,376 @@
+extern crate log;
+	use log::*;
+
+use std::collections::*;
+use std::path::{Path, PathBuf};
+use crate::runtimeconfig::RuntimeConfig;
+use crate::catalog::{Kit, Sample};
+use crate::manifest::Manifest;
+use crate::journal::Journal;
+
+pub struct SyncReport
+{
+	pub added: usize, // planned targets that don't exist yet
+	pub updated: usize, // owned targets whose source changed - rewritten
+	pub removed: usize, // owned targets that are not planned anymore
+	pub unchanged: usize, // owned targets that are planned as they are
+	pub foreign: usize // stale targets that were changed or replaced by someone else - kept
+}
+
+impl std::fmt::Debug for SyncReport
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+	    writeln!(f, "{{ [SYNC] added: {:?} updated: {:?} removed: {:?} unchanged: {:?} foreign: {:?} }}",
+    		self.added,
+    		self.updated,
+    		self.removed,
+    		self.unchanged,
+    		self.foreign
+		)
+	}
+}
+
+impl std::fmt::Display for SyncReport
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result
+	{
+		write!(f, "Sync: {} added, {} updated, {} removed, {} unchanged, {} foreign kept", self.added, self.updated, self.removed, self.unchanged, self.foreign)
+	}
+}
+
+/// Compares the planned kits with the files KitCat created in a previous run (by the manifest of the output-directory):
+/// - owned files that are not planned anymore are removed (together with directories that became empty)
+/// - owned files that are planned as they are (same, unchanged source) stay untouched and are dropped from the kits
+/// - owned files whose source changed are removed, so the kits write them again
+///
+/// Files that are not owned by KitCat are never touched. Removed files are moved into the journal's backup.
+/// Nothing is removed on a dry run.
+pub fn prepare(processed_dataset: &mut HashMap<String, Kit>, manifest: &mut Manifest, journal: &mut Journal, rcon: &RuntimeConfig) -> SyncReport
+{
+	let mut report = SyncReport { added: 0, updated: 0, removed: 0, unchanged: 0, foreign: 0 };
+	let mut planned: HashSet<PathBuf> = HashSet::new();
+	let mut emptied: BTreeSet<PathBuf> = BTreeSet::new();
+
+	for kit in processed_dataset.values_mut()
+	{
+		kit.samples.retain(|sample|
+		{
+			let target = manifest.written_path(&sample.target_path); // the renamed copy of a previous run is planned as well
+
+			planned.insert(target.to_path_buf());
+
+			if target.symlink_metadata().is_err()
+			{
+				report.added+=1;
+				return true;
+			}
+
+			if !manifest.owns(&target)
+			{
+				return true; // foreign - left to the --on-existing policy
+			}
+
+			if manifest.is_current(sample)
+			{
+				debug!("{:?} is unchanged", sample.target_path);
+				report.unchanged+=1;
+				return false;
+			}
+
+			report.updated+=1;
+			remove_outdated(sample, &target, journal, rcon);
+
+			true
+		});
+	}
+
+	processed_dataset.retain(|_name, kit| !kit.samples.is_empty());
+
+	let stale: Vec<String> = manifest.entries.keys().filter(|t| !planned.contains(Path::new(t))).cloned().collect();
+
+	for target in stale
+	{
+		let path = PathBuf::from(&target);
+
+		if path.symlink_metadata().is_err()
+		{
+			debug!("Stale {:?} does not exist anymore", target);
+			manifest.entries.remove(&target);
+			continue;
+		}
+
+		if !manifest.owns(&path)
+		{
+			warn!("Stale {:?} was changed after KitCat wrote it - keeping it", target);
+			manifest.entries.remove(&target);
+			report.foreign+=1;
+			continue;
+		}
+
+		info!("Removing stale {:?}", target);
+		report.removed+=1;
+
+		if rcon.dry
+		{
+			continue;
+		}
+
+		match journal.remove(&path)
+		{
+			Err(e) => error!("Removing stale {:?} failed: {:?}", target, e),
+			Ok(_) => { manifest.entries.remove(&target); emptied.insert(path.with_file_name("")); }
+		}
+	}
+
+	remove_empty_directories(emptied, &rcon.output, journal);
+
+	report
+}
+
+/// Drops all samples from the kits that were written by a previous run and whose source did not change since.
+/// Owned targets whose source changed are removed like by prepare, so they are written again. Returns the count of dropped samples.
+pub fn skip_unchanged(processed_dataset: &mut HashMap<String, Kit>, manifest: &Manifest, journal: &mut Journal, rcon: &RuntimeConfig) -> usize
+{
+	let mut unchanged: usize = 0;
+
+	for kit in processed_dataset.values_mut()
+	{
+		kit.samples.retain(|sample|
+		{
+			let current = is_unchanged(sample, manifest, journal, rcon);
+
+			if current
+			{
+				unchanged+=1;
+			}
+
+			!current
+		});
+	}
+
+	processed_dataset.retain(|_name, kit| !kit.samples.is_empty());
+
+	unchanged
+}
+
+/// True if the target of the sample was written by a previous run and it's source did not change since.
+/// An owned target whose source changed is removed, so the sample replaces it instead of being left to the --on-existing policy.
+pub fn is_unchanged(sample: &Sample, manifest: &Manifest, journal: &mut Journal, rcon: &RuntimeConfig) -> bool
+{
+	if manifest.is_current(sample)
+	{
+		debug!("{:?} is unchanged", sample.target_path);
+		return true;
+	}
+
+	let target = manifest.written_path(&sample.target_path);
+
+	if manifest.owns(&target)
+	{
+		remove_outdated(sample, &target, journal, rcon);
+	}
+
+	false
+}
+
+// moves an owned target (the path the sample was written to) whose source changed into the journal's backup (not on a dry run)
+fn remove_outdated(sample: &Sample, target: &Path, journal: &mut Journal, rcon: &RuntimeConfig)
+{
+	info!("Source {:?} of {:?} changed - rewriting", sample.source_path, target);
+
+	if rcon.dry
+	{
+		return;
+	}
+
+	if let Err(e) = journal.remove(target)
+	{
+		error!("Removing outdated {:?} failed: {:?}", target, e);
+	}
+}
+
+// removes the given directories (and their parents) as long as they are empty and inside the output-directory
+fn remove_empty_directories(directories: BTreeSet<PathBuf>, output: &str, journal: &mut Journal)
+{
+	let root = Path::new(output);
+
+	for directory in directories.into_iter().rev() // deepest first
+	{
+		let mut current: &Path = &directory;
+
+		while current.starts_with(root) && current != root
+		{
+			if std::fs::remove_dir(current).is_err() // fails if not empty
+			{
+				break;
+			}
+
+			info!("Removed empty directory {:?}", current);
+			journal.removed_dir(current);
+
+			current = match current.parent()
+			{
+				None => break,
+				Some(p) => p
+			};
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use crate::runtimeconfig;
+	use crate::writer::{self, CopyOptions, ExistingPolicy, SoftLinks, WriteMode, WriteOutcome};
+
+	// input- and output-directory of a test below the temporary directory - the output holds a foreign Kick.wav
+	fn setup(name: &str) -> (PathBuf, PathBuf)
+	{
+		let root = std::env::temp_dir().join(format!("kitcat-sync-{}-{}", name, std::process::id()));
+		let (input, output) = (root.join("in"), root.join("out"));
+
+		let _ = std::fs::remove_dir_all(&root);
+		std::fs::create_dir_all(&input).unwrap();
+		std::fs::create_dir_all(output.join("KitA")).unwrap();
+		std::fs::write(input.join("Kick.wav"), b"kick").unwrap();
+		std::fs::write(output.join("KitA/Kick.wav"), b"foreign").unwrap();
+
+		(input, output)
+	}
+
+	// one run with --on-existing rename: the samples not skipped as unchanged (or by sync) are written and recorded
+	fn run(input: &Path, output: &Path, sync: bool) -> (usize, Vec<WriteOutcome>)
+	{
+		let mut args: Vec<String> = ["kitcat", "-i", &input.to_string_lossy(), "-o", &output.to_string_lossy(), "--on-existing", "rename"].iter().map(|a| a.to_string()).collect();
+
+		if sync
+		{
+			args.push("--sync".to_string());
+		}
+
+		let rcon = runtimeconfig::process_args(args).unwrap();
+		let mut manifest = Manifest::load(&rcon.output).unwrap();
+		let mut journal = Journal::new(&rcon.output);
+		let sample = Sample { source_path: input.join("Kick.wav"), target_path: output.join("KitA/Kick.wav"), fields: HashMap::new() };
+		let mut kits: HashMap<String, Kit> = HashMap::new();
+
+		kits.insert("KitA".to_string(), Kit { name: "KitA".to_string(), samples: vec![sample] });
+
+		let skipped = if sync { prepare(&mut kits, &mut manifest, &mut journal, &rcon).unchanged } else { skip_unchanged(&mut kits, &manifest, &mut journal, &rcon) };
+		let mut outcomes = vec![];
+
+		for sample in kits.values().flat_map(|k| k.samples.iter())
+		{
+			let (outcome, used) = writer::write_sample(sample, WriteMode::Copy, ExistingPolicy::Rename, &SoftLinks::from_config(&rcon), &CopyOptions::from_config(&rcon), &mut journal);
+
+			manifest.record_outcome("KitA", sample, used, &outcome, false);
+			outcomes.push(outcome);
+		}
+
+		manifest.save(&rcon.output).unwrap();
+		(skipped, outcomes)
+	}
+
+	#[test]
+	fn renamed_samples_are_unchanged_on_the_next_run()
+	{
+		let (input, output) = setup("rename");
+
+		let (skipped, outcomes) = run(&input, &output, false);
+
+		assert_eq!(skipped, 0);
+		assert_eq!(outcomes, vec![WriteOutcome::Renamed(output.join("KitA/Kick 2.wav"))]);
+
+		let (skipped, outcomes) = run(&input, &output, false);
+
+		assert_eq!(skipped, 1);
+		assert!(outcomes.is_empty());
+		assert!(!output.join("KitA/Kick 3.wav").exists());
+		assert_eq!(std::fs::read(output.join("KitA/Kick.wav")).unwrap(), b"foreign");
+
+		let _ = std::fs::remove_dir_all(input.parent().unwrap());
+	}
+
+	#[test]
+	fn sync_keeps_the_renamed_copy_and_rewrites_it_if_the_source_changed()
+	{
+		let (input, output) = setup("rename-sync");
+
+		run(&input, &output, true);
+
+		let (unchanged, outcomes) = run(&input, &output, true);
+
+		assert_eq!((unchanged, outcomes.len()), (1, 0));
+		assert_eq!(std::fs::read(output.join("KitA/Kick 2.wav")).unwrap(), b"kick");
+
+		std::fs::write(input.join("Kick.wav"), b"kick, longer").unwrap();
+
+		let (unchanged, outcomes) = run(&input, &output, true);
+
+		assert_eq!(unchanged, 0);
+		assert_eq!(outcomes, vec![WriteOutcome::Renamed(output.join("KitA/Kick 2.wav"))]);
+		assert_eq!(std::fs::read(output.join("KitA/Kick 2.wav")).unwrap(), b"kick, longer");
+		assert_eq!(Manifest::load(&output.to_string_lossy()).unwrap().entries.len(), 1);
+
+		let _ = std::fs::remove_dir_all(input.parent().unwrap());
+	}
+
+	#[test]
+	fn sync_removes_owned_targets_that_are_not_planned_anymore()
+	{
+		let (input, output) = setup("stale");
+		let out = output.to_string_lossy().to_string();
+		let rcon = runtimeconfig::process_args(["kitcat", "-i", &input.to_string_lossy(), "-o", &out, "--sync"].iter().map(|a| a.to_string()).collect()).unwrap();
+		let mut manifest = Manifest::load(&out).unwrap();
+
+		for name in ["Snare", "Hat"]
+		{
+			let sample = Sample { source_path: input.join("Kick.wav"), target_path: output.join("KitB").join(name).with_extension("wav"), fields: HashMap::new() };
+
+			std::fs::create_dir_all(output.join("KitB")).unwrap();
+			std::fs::write(&sample.target_path, name).unwrap();
+			manifest.record("KitB", &sample, WriteMode::Copy, &sample.target_path, false);
+		}
+
+		std::fs::write(output.join("KitB/Hat.wav"), "Hat, edited by hand").unwrap();
+
+		let mut journal = Journal::new(&out);
+		let report = prepare(&mut HashMap::new(), &mut manifest, &mut journal, &rcon);
+
+		assert_eq!((report.removed, report.foreign), (1, 1));
+		assert!(!output.join("KitB/Snare.wav").exists());
+		assert!(output.join("KitB/Hat.wav").exists());
+		assert!(output.join("KitA/Kick.wav").exists()); // never written by KitCat
+		assert!(manifest.entries.is_empty());
+
+		let _ = std::fs::remove_dir_all(input.parent().unwrap());
+	}
+
+	#[test]
+	fn a_dry_sync_removes_nothing_and_empty_directories_go_with_their_last_file()
+	{
+		let (input, output) = setup("dry");
+		let out = output.to_string_lossy().to_string();
+		let args = |dry: bool| ["kitcat", "-i", &input.to_string_lossy(), "-o", &out, "--sync"].iter().map(|a| a.to_string()).chain(dry.then(|| "--dry".to_string())).collect();
+		let sample = Sample { source_path: input.join("Kick.wav"), target_path: output.join("KitB/Deep/Kick.wav"), fields: HashMap::new() };
+		let mut manifest = Manifest::load(&out).unwrap();
+
+		std::fs::create_dir_all(output.join("KitB/Deep")).unwrap();
+		std::fs::write(&sample.target_path, b"kick").unwrap();
+		manifest.record("KitB", &sample, WriteMode::Copy, &sample.target_path, false);
+
+		let report = prepare(&mut HashMap::new(), &mut manifest, &mut Journal::new(&out), &runtimeconfig::process_args(args(true)).unwrap());
+
+		assert_eq!(report.removed, 1);
+		assert!(sample.target_path.exists());
+
+		prepare(&mut HashMap::new(), &mut manifest, &mut Journal::new(&out), &runtimeconfig::process_args(args(false)).unwrap());
+
+		assert!(!output.join("KitB").exists());
+		assert!(output.exists());
+
+		let _ = std::fs::remove_dir_all(input.parent().unwrap());
+	}
+}
@@ -0,0 +1,1245 @@
+extern crate log;
+	use log::*;
+
+extern crate serde;
+	use serde::{Deserialize, Serialize};
+
+#[cfg(target_os = "linux")]
+extern crate libc;
+
+use std::collections::*;
+use std::path::{Component, Path, PathBuf};
+use crate::runtimeconfig::RuntimeConfig;
+use crate::error::{Error, Result};
+use crate::catalog::Sample;
+use crate::journal::Journal;
+use crate::parallel;
+use crate::archive;
+use crate::manifest;
+use crate::interrupt;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WriteMode
+{
+	Hard,
+	Soft,
+	Copy,
+	Reflink, // a copy sharing the blocks of the source (FICLONE on btrfs/XFS) - fails where the filesystem can't clone
+	Auto // per sample: reflink, else hard-link, else copy - the method used is recorded instead
+}
+
+const WM_HARD: &str = "hard";
+const WM_SOFT: &str = "soft";
+const WM_COPY: &str = "copy";
+const WM_REFLINK: &str = "reflink";
+const WM_AUTO: &str = "auto";
+
+impl WriteMode
+{
+	pub fn from_config(rcon: &RuntimeConfig) -> WriteMode
+	{
+		if rcon.copy
+		{
+			WriteMode::Copy
+		}
+		else if rcon.reflink
+		{
+			WriteMode::Reflink
+		}
+		else if rcon.auto
+		{
+			WriteMode::Auto
+		}
+		else if rcon.soft
+		{
+			WriteMode::Soft
+		}
+		else
+		{
+			WriteMode::Hard
+		}
+	}
+
+	pub fn name(&self) -> &'static str
+	{
+		match self
+		{
+			WriteMode::Hard => WM_HARD,
+			WriteMode::Soft => WM_SOFT,
+			WriteMode::Copy => WM_COPY,
+			WriteMode::Reflink => WM_REFLINK,
+			WriteMode::Auto => WM_AUTO
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<WriteMode>
+	{
+		match name
+		{
+			WM_HARD => Some(WriteMode::Hard),
+			WM_SOFT => Some(WriteMode::Soft),
+			WM_COPY => Some(WriteMode::Copy),
+			WM_REFLINK => Some(WriteMode::Reflink),
+			WM_AUTO => Some(WriteMode::Auto),
+			_ => None
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ExistingPolicy
+{
+	Skip,
+	Overwrite,
+	OverwriteIfNewer, // the source was modified after the existing target
+	OverwriteIfDifferent, // size or content of source and existing target differ
+	Rename, // write the sample as "Kick 2.wav"
+	Error
+}
+
+const EP_SKIP: &str = "skip";
+const EP_OVERWRITE: &str = "overwrite";
+const EP_OVERWRITE_IF_NEWER: &str = "overwrite-if-newer";
+const EP_OVERWRITE_IF_DIFFERENT: &str = "overwrite-if-different";
+const EP_RENAME: &str = "rename";
+const EP_ERROR: &str = "error";
+
+impl ExistingPolicy
+{
+	pub const NAMES: [&'static str; 6] = [EP_SKIP, EP_OVERWRITE, EP_OVERWRITE_IF_NEWER, EP_OVERWRITE_IF_DIFFERENT, EP_RENAME, EP_ERROR];
+
+	pub fn name(&self) -> &'static str
+	{
+		match self
+		{
+			ExistingPolicy::Skip => EP_SKIP,
+			ExistingPolicy::Overwrite => EP_OVERWRITE,
+			ExistingPolicy::OverwriteIfNewer => EP_OVERWRITE_IF_NEWER,
+			ExistingPolicy::OverwriteIfDifferent => EP_OVERWRITE_IF_DIFFERENT,
+			ExistingPolicy::Rename => EP_RENAME,
+			ExistingPolicy::Error => EP_ERROR
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<ExistingPolicy>
+	{
+		match name
+		{
+			EP_SKIP => Some(ExistingPolicy::Skip),
+			EP_OVERWRITE => Some(ExistingPolicy::Overwrite),
+			EP_OVERWRITE_IF_NEWER => Some(ExistingPolicy::OverwriteIfNewer),
+			EP_OVERWRITE_IF_DIFFERENT => Some(ExistingPolicy::OverwriteIfDifferent),
+			EP_RENAME => Some(ExistingPolicy::Rename),
+			EP_ERROR => Some(ExistingPolicy::Error),
+			_ => None
+		}
+	}
+}
+
+/// What a soft-link points to.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LinkStyle
+{
+	Absolute, // the absolute path of the source
+	Relative, // the source relative to the directory of the link - survives moving library and output together
+	Anchored // the source below the library-root as given (e.g. a link to the library that is repointed after moving it)
+}
+
+const LS_ABSOLUTE: &str = "absolute";
+const LS_RELATIVE: &str = "relative";
+const LS_ANCHORED: &str = "anchored";
+
+impl LinkStyle
+{
+	pub const NAMES: [&'static str; 3] = [LS_ABSOLUTE, LS_RELATIVE, LS_ANCHORED];
+
+	pub fn name(&self) -> &'static str
+	{
+		match self
+		{
+			LinkStyle::Absolute => LS_ABSOLUTE,
+			LinkStyle::Relative => LS_RELATIVE,
+			LinkStyle::Anchored => LS_ANCHORED
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<LinkStyle>
+	{
+		match name
+		{
+			LS_ABSOLUTE => Some(LinkStyle::Absolute),
+			LS_RELATIVE => Some(LinkStyle::Relative),
+			LS_ANCHORED => Some(LinkStyle::Anchored),
+			_ => None
+		}
+	}
+}
+
+/// Computes the paths soft-links point to.
+pub struct SoftLinks
+{
+	pub style: LinkStyle,
+	pub anchor: PathBuf, // library-root as given - the anchored links point below it
+	resolved: Option<PathBuf> // library-root with all links resolved - the sources lie below it
+}
+
+impl SoftLinks
+{
+	pub fn from_config(rcon: &RuntimeConfig) -> SoftLinks
+	{
+		let anchor = PathBuf::from(&rcon.library_root);
+		let resolved = anchor.canonicalize().ok();
+
+		SoftLinks { style: rcon.link_style, anchor, resolved }
+	}
+
+	/// What the soft-link at the target-path has to point to, to reach the source.
+	pub fn path(&self, source: &Path, target: &Path) -> std::io::Result<PathBuf>
+	{
+		match self.style
+		{
+			LinkStyle::Absolute => Ok(source.to_path_buf()),
+			LinkStyle::Relative =>
+			{
+				let dir = target.with_file_name("").canonicalize()?;
+
+				Ok(relative_path(&dir, source).unwrap_or_else(|| source.to_path_buf()))
+			},
+			LinkStyle::Anchored =>
+			{
+				let below = self.resolved.as_ref().and_then(|r| source.strip_prefix(r).ok()).ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, format!("not below the library-root {:?}", self.anchor)))?;
+
+				Ok(self.anchor.join(below))
+			}
+		}
+	}
+}
+
+// path leading from the (absolute) directory to the (absolute) path - None if they share no root (e.g. different drives)
+fn relative_path(dir: &Path, to: &Path) -> Option<PathBuf>
+{
+	let dir: Vec<Component> = dir.components().collect();
+	let to: Vec<Component> = to.components().collect();
+	let common = dir.iter().zip(to.iter()).take_while(|(a, b)| a == b).count();
+
+	if common == 0
+	{
+		return None;
+	}
+
+	let mut out: PathBuf = dir[common..].iter().map(|_| Component::ParentDir).collect();
+
+	out.extend(to[common..].iter());
+	Some(out)
+}
+
+/// How copies are finished before they are moved to their target-path.
+pub struct CopyOptions
+{
+	pub preserve_mtime: bool, // mtime of the source (of it's archive for members) - the permissions are always kept
+	pub verify: bool // compare the checksums of source and copy
+}
+
+impl CopyOptions
+{
+	pub fn from_config(rcon: &RuntimeConfig) -> CopyOptions
+	{
+		CopyOptions { preserve_mtime: rcon.preserve_mtime, verify: rcon.verify }
+	}
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum WriteOutcome
+{
+	Written,
+	Overwritten,
+	Renamed(PathBuf), // the sample was written to this path instead of it's target-path
+	Skipped,
+	Failed,
+	Interrupted // not started because a stop was requested
+}
+
+pub struct WriteSummary
+{
+	pub written: usize, // including overwritten and renamed samples
+	pub overwritten: usize,
+	pub renamed: usize,
+	pub skipped: usize,
+	pub failed: usize,
+	pub interrupted: usize,
+	pub methods: BTreeMap<&'static str, usize>, // written samples by the method used (copy, hard, reflink, ...)
+	pub completed: BTreeSet<String> // target-paths of the written samples - recorded for --resume
+}
+
+impl std::fmt::Debug for WriteSummary
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result 
+	{
+	    writeln!(f, "{{ [SUMMARY] written: {:?} overwritten: {:?} renamed: {:?} skipped: {:?} failed: {:?} interrupted: {:?} methods: {:?} completed: {:?} }}",
+    		self.written,
+    		self.overwritten,
+    		self.renamed,
+    		self.skipped,
+    		self.failed,
+    		self.interrupted,
+    		self.methods,
+    		self.completed.len()
+		)
+	}
+}
+
+impl WriteSummary
+{
+	pub fn new() -> WriteSummary
+	{
+		WriteSummary { written: 0, overwritten: 0, renamed: 0, skipped: 0, failed: 0, interrupted: 0, methods: BTreeMap::new(), completed: BTreeSet::new() }
+	}
+
+	/// Counts the outcome of the sample - and the method used if it was written.
+	pub fn count_sample(&mut self, sample: &Sample, outcome: &WriteOutcome, method: WriteMode)
+	{
+		if let WriteOutcome::Written | WriteOutcome::Overwritten | WriteOutcome::Renamed(_) = outcome
+		{
+			*self.methods.entry(method.name()).or_insert(0) += 1;
+			self.completed.insert(sample.target_path.to_string_lossy().to_string());
+		}
+
+		self.count(outcome);
+	}
+
+	pub fn count(&mut self, outcome: &WriteOutcome)
+	{
+		match outcome
+		{
+			WriteOutcome::Written => self.written+=1,
+			WriteOutcome::Overwritten => { self.written+=1; self.overwritten+=1 },
+			WriteOutcome::Renamed(_) => { self.written+=1; self.renamed+=1 },
+			WriteOutcome::Skipped => self.skipped+=1,
+			WriteOutcome::Failed => self.failed+=1,
+			WriteOutcome::Interrupted => self.interrupted+=1
+		}
+	}
+
+	pub fn total(&self) -> usize
+	{
+		self.written + self.skipped + self.failed + self.interrupted
+	}
+
+}
+
+impl std::fmt::Display for WriteSummary
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result 
+	{
+		let methods: Vec<String> = self.methods.iter().map(|(method, count)| format!("; {} {}", count, method)).collect();
+		let interrupted = if self.interrupted > 0 { format!(", {} interrupted", self.interrupted) } else { String::new() };
+
+		write!(f, "Wrote {} samples ({} overwritten, {} renamed{}), {} skipped, {} failed{}, {} total", self.written, self.overwritten, self.renamed, methods.concat(), self.skipped, self.failed, interrupted, self.total())
+	}
+}
+
+impl Default for WriteSummary
+{
+	fn default() -> WriteSummary
+	{
+		WriteSummary::new()
+	}
+}
+
+/// Creates the directory of the target-path (if needed) and copies/links the sample into it.
+/// An already existing target-path is handled by the given policy - replaced files are moved into the journal's backup.
+/// Returns the outcome and the method used (the one picked by auto-mode).
+pub fn write_sample(sample: &Sample, mode: WriteMode, on_existing: ExistingPolicy, links: &SoftLinks, copies: &CopyOptions, journal: &mut Journal) -> (WriteOutcome, WriteMode)
+{
+	write_samples(&[(sample, mode)], on_existing, links, copies, journal, 1).remove(0)
+}
+
+/// Writes all samples like write_sample, copying/linking up to workers samples at once.
+/// Directories, existing targets and the journal are handled in order of the samples before and after
+/// the transfers, so logs and outcomes (returned in order of the samples) do not depend on the workers.
+/// The free space is not checked here - a run checks all of it's copies at once by check_space before it writes the first sample.
+pub fn write_samples(samples: &[(&Sample, WriteMode)], on_existing: ExistingPolicy, links: &SoftLinks, copies: &CopyOptions, journal: &mut Journal, workers: usize) -> Vec<(WriteOutcome, WriteMode)>
+{
+	let mut prepared: Vec<Option<std::result::Result<Transfer, WriteOutcome>>> = Vec::with_capacity(samples.len());
+	let mut claimed: HashSet<PathBuf> = HashSet::new(); // target-paths of this batch - not written yet
+	let mut deferred: Vec<usize> = vec![];
+
+	for (i, (sample, mode)) in samples.iter().enumerate()
+	{
+		if claimed.contains(&sample.target_path)
+		{
+			// same target-path twice (e.g. in a hand-edited plan) - written after the batch, so the policy sees the first one
+			deferred.push(i);
+			prepared.push(None);
+			continue;
+		}
+
+		let p = prepare(sample, *mode, on_existing, links, journal, &claimed);
+
+		if let Ok(t) = &p
+		{
+			claimed.insert(t.target.to_path_buf());
+		}
+
+		prepared.push(Some(p));
+	}
+
+	let jobs: Vec<(usize, &Path, &Path, WriteMode)> = prepared.iter().enumerate()
+	.filter_map(|(i, p)| match p { Some(Ok(t)) => Some((i, t.link.as_deref().unwrap_or(&samples[i].0.source_path), t.target.as_path(), samples[i].1)), _ => None })
+	.collect();
+
+	interrupt::install();
+
+	// once a stop is requested the transfers running are finished, the others are not started
+	let units = transfer_units(&jobs);
+	let results = parallel::map(&units, workers, |unit| match unit
+	{
+		_ if interrupt::requested() => vec![],
+		Unit::Single(i, source, target, mode) => vec![(*i, transfer(source, target, *mode, copies))],
+		Unit::Archive(archive, members) =>
+		{
+			let temps: Vec<PathBuf> = members.iter().map(|(_, _, target)| temp_path(target)).collect();
+
+			temps.iter().for_each(remove_leftover);
+			let pairs: Vec<(&Path, &Path)> = members.iter().zip(temps.iter()).map(|((_, member, _), temp)| (member.as_path(), temp.as_path())).collect();
+
+			members.iter().zip(temps.iter()).zip(archive::extract_all(archive, &pairs))
+			.map(|(((i, member, target), temp), extracted)| (*i, complete(&archive.join(member), temp, extracted, target, copies).map(|_| WriteMode::Copy)))
+			.collect()
+		}
+	});
+	let mut transferred: HashMap<usize, std::io::Result<WriteMode>> = results.into_iter().flatten().collect();
+
+	let mut out: Vec<(WriteOutcome, WriteMode)> = prepared.into_iter().enumerate().map(|(i, p)| match p
+	{
+		None => (WriteOutcome::Failed, samples[i].1), // deferred - replaced below
+		Some(Err(outcome)) => (outcome, samples[i].1),
+		Some(Ok(t)) => match transferred.remove(&i)
+		{
+			Some(result) => finish(samples[i].0, samples[i].1, t, result, journal),
+			None =>
+			{
+				debug!("Not writing {:?} - interrupted", t.target);
+				t.backup.iter().for_each(|b| journal.discard(b));
+				(WriteOutcome::Interrupted, samples[i].1)
+			}
+		}
+	}).collect();
+
+	for i in deferred
+	{
+		out[i] = write_sample(samples[i].0, samples[i].1, on_existing, links, copies, journal);
+	}
+
+	out
+}
+
+// a single copy/link - or all samples extracted out of the same archive, which is read only once (auto-mode copies them)
+enum Unit<'a>
+{
+	Single(usize, &'a Path, &'a Path, WriteMode), // job-index, source, target, mode
+	Archive(PathBuf, Vec<(usize, PathBuf, &'a Path)>) // archive, (job-index, member, target)
+}
+
+fn transfer_units<'a>(jobs: &[(usize, &'a Path, &'a Path, WriteMode)]) -> Vec<Unit<'a>>
+{
+	let mut out: Vec<Unit> = vec![];
+	let mut archives: BTreeMap<PathBuf, Vec<(usize, PathBuf, &Path)>> = BTreeMap::new();
+
+	for (i, source, target, mode) in jobs.iter()
+	{
+		match archive::split(source)
+		{
+			Some((archive, member)) if *mode == WriteMode::Copy || *mode == WriteMode::Auto => archives.entry(archive).or_default().push((*i, member, target)),
+			_ => out.push(Unit::Single(*i, source, target, *mode))
+		}
+	}
+
+	out.extend(archives.into_iter().map(|(archive, members)| Unit::Archive(archive, members)));
+	out
+}
+
+/// Creates the directory (and all missing parents), journaling every directory created.
+pub fn create_dirs(path: &Path, journal: &mut Journal) -> std::io::Result<()>
+{
+	if path.exists()
+	{
+		return Ok(());
+	}
+
+	info!("Path {:?} does not exist - trying to create", path);
+
+	let missing: Vec<&Path> = path.ancestors().take_while(|p| !p.exists()).collect();
+
+	std::fs::create_dir_all(path)?;
+
+	for dir in missing.into_iter().rev()
+	{
+		journal.created_dir(dir);
+	}
+
+	Ok(())
+}
+
+// sample whose directory exists and whose (final) target-path is free or backed up to be replaced
+struct Transfer
+{
+	target: PathBuf,
+	link: Option<PathBuf>, // what a soft-link points to
+	backup: Option<PathBuf>, // of the target replaced - journaled once it is
+	outcome: WriteOutcome // if the transfer succeeds
+}
+
+// creates the directory of the target-path and applies the existing-policy - the final outcome is returned if there is nothing to transfer
+fn prepare(sample: &Sample, mode: WriteMode, on_existing: ExistingPolicy, links: &SoftLinks, journal: &mut Journal, claimed: &HashSet<PathBuf>) -> std::result::Result<Transfer, WriteOutcome>
+{
+	let source = sample.source_path.as_path();
+
+	if mode != WriteMode::Copy && mode != WriteMode::Auto && archive::split(source).is_some()
+	{
+		error!("{:?} lies inside an archive - it can only be copied", source);
+		return Err(WriteOutcome::Failed);
+	}
+
+	let mut target = sample.target_path.to_path_buf();
+	let path = target.with_file_name("");
+
+	if let Err(e) = create_dirs(&path, journal)
+	{
+		error!("Creating path {:?} failed: {:?} - skipping sample {:?}", path, e, sample); 
+		return Err(WriteOutcome::Failed);
+	}
+
+	let mut outcome = WriteOutcome::Written;
+	let mut backup: Option<PathBuf> = None;
+
+	if target.symlink_metadata().is_ok() // also true for broken soft-links
+	{
+		let replace = match on_existing
+		{
+			ExistingPolicy::Skip => false,
+			ExistingPolicy::Overwrite => true,
+			ExistingPolicy::OverwriteIfNewer => is_newer(source, &target),
+			ExistingPolicy::OverwriteIfDifferent => !is_same(source, &target),
+			ExistingPolicy::Rename =>
+			{
+				target = free_path(&target, claimed);
+				outcome = WriteOutcome::Renamed(target.to_path_buf());
+				false
+			},
+			ExistingPolicy::Error =>
+			{
+				error!("Target {:?} of {:?} already exists", target, sample.source_path);
+				return Err(WriteOutcome::Failed);
+			}
+		};
+
+		if replace
+		{
+			// the target stays in place until the new one is complete and renamed onto it (never written through - it could be a link to the source of a previous run)
+			match journal.backup(&target)
+			{
+				Err(e) => { error!("Backing up existing target {:?} failed: {:?}", target, e); return Err(WriteOutcome::Failed) },
+				Ok(b) => backup = Some(b)
+			}
+
+			outcome = WriteOutcome::Overwritten;
+		}
+		else if outcome == WriteOutcome::Written
+		{
+			debug!("Target {:?} already exists - skipping ({})", target, on_existing.name());
+			return Err(WriteOutcome::Skipped);
+		}
+	}
+
+	match mode
+	{
+		WriteMode::Copy => debug!("Copying {:?} to {:?}", source, target),
+		WriteMode::Soft => debug!("Linking (soft, {}) {:?} to {:?}", links.style.name(), source, target),
+		WriteMode::Hard => debug!("Linking (hard) {:?} to {:?}", source, target),
+		WriteMode::Reflink => debug!("Cloning {:?} to {:?}", source, target),
+		WriteMode::Auto => debug!("Cloning/linking/copying {:?} to {:?}", source, target)
+	}
+
+	let link = match mode
+	{
+		WriteMode::Soft => match links.path(source, &target)
+		{
+			Ok(l) => Some(l),
+			Err(e) =>
+			{
+				error!("Linking {:?} to {:?} failed: {:?}", source, target, e);
+				backup.iter().for_each(|b| journal.discard(b));
+				return Err(WriteOutcome::Failed);
+			}
+		},
+		_ => None
+	};
+
+	Ok(Transfer { target, link, backup, outcome })
+}
+
+// copies/links the source to a temporary path next to the target-path and renames it to the target-path once it is complete,
+// so an existing target is replaced at once and an interrupted run never leaves a truncated sample.
+// Returns the method used - runs on a worker, so nothing is logged here
+fn transfer(source: &Path, target: &Path, mode: WriteMode, copies: &CopyOptions) -> std::io::Result<WriteMode>
+{
+	let temp = temp_path(target);
+
+	remove_leftover(&temp);
+
+	let written = match mode
+	{
+		WriteMode::Copy => copy(source, &temp, copies).map(|_| mode),
+		WriteMode::Soft => link_soft(source, &temp).map(|_| mode),
+		WriteMode::Hard => hard_link(source, &temp).map(|_| mode),
+		WriteMode::Reflink => reflink(source, &temp).map(|_| mode),
+		WriteMode::Auto => reflink(source, &temp).map(|_| WriteMode::Reflink)
+			.or_else(|_| hard_link(source, &temp).map(|_| WriteMode::Hard))
+			.or_else(|_| copy(source, &temp, copies).map(|_| WriteMode::Copy))
+	};
+
+	let result = written.and_then(|used| rename(&temp, target).map(|_| used));
+
+	if result.is_err()
+	{
+		let _ = std::fs::remove_file(&temp);
+	}
+
+	result
+}
+
+// hidden path next to the target-path a sample is written to
+fn temp_path(target: &Path) -> PathBuf
+{
+	let name = target.file_name().map_or(String::new(), |n| n.to_string_lossy().to_string());
+
+	target.with_file_name(format!(".{}.kitcat-tmp", name))
+}
+
+// temporary file of a killed run
+fn remove_leftover(temp: &PathBuf)
+{
+	if temp.symlink_metadata().is_ok()
+	{
+		let _ = std::fs::remove_file(temp);
+	}
+}
+
+// renames the complete temporary file onto the target-path, replacing an existing one at once.
+// Renaming a hard-link onto another link of the same file does nothing - the temporary one is removed then
+fn rename(temp: &Path, target: &Path) -> std::io::Result<()>
+{
+	std::fs::rename(temp, target)?;
+
+	if temp.symlink_metadata().is_ok()
+	{
+		std::fs::remove_file(temp)?;
+	}
+
+	Ok(())
+}
+
+// copies the source into the temporary file
+fn copy(source: &Path, temp: &Path, copies: &CopyOptions) -> std::io::Result<()>
+{
+	std::fs::File::open(source).and_then(|mut from| std::fs::File::create(temp).and_then(|mut to| std::io::copy(&mut from, &mut to)))?;
+
+	finish_copy(source, temp, copies)
+}
+
+// applies the options to the temporary file extracted out of an archive and renames it to the target-path - it is removed on failure
+fn complete(source: &Path, temp: &Path, written: std::io::Result<()>, target: &Path, copies: &CopyOptions) -> std::io::Result<()>
+{
+	let result = written.and_then(|_| finish_copy(source, temp, copies)).and_then(|_| rename(temp, target));
+
+	if result.is_err()
+	{
+		let _ = std::fs::remove_file(temp);
+	}
+
+	result
+}
+
+fn finish_copy(source: &Path, temp: &Path, copies: &CopyOptions) -> std::io::Result<()>
+{
+	let file = std::fs::OpenOptions::new().write(true).open(temp)?;
+	let meta = archive::file_of(source).metadata()?;
+
+	file.set_permissions(meta.permissions())?; // like std::fs::copy - the file is open already, so read-only ones can still be finished
+
+	if copies.preserve_mtime
+	{
+		file.set_modified(meta.modified()?)?;
+	}
+
+	file.sync_all()?;
+
+	if copies.verify
+	{
+		match (manifest::hash_file(source), manifest::hash_file(temp))
+		{
+			(Some(s), Some(t)) if s == t => {},
+			(Some(s), Some(t)) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("checksum {} of the copy differs from {} of the source", t, s))),
+			_ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "checksums of source and copy could not be computed"))
+		}
+	}
+
+	Ok(())
+}
+
+/// Bytes the copies of a run need on each filesystem - samples are added before the first one is written, so the whole run is checked at once.
+/// Copies out of archives are not counted - their size is only known after reading the archive.
+pub struct SpaceCheck
+{
+	on_existing: ExistingPolicy,
+	needed: BTreeMap<u64, (PathBuf, u64)>, // device -> existing directory on it, bytes to copy
+	existing: HashMap<PathBuf, Option<(PathBuf, u64)>> // directory of a target -> nearest existing ancestor and it's device
+}
+
+impl SpaceCheck
+{
+	pub fn new(on_existing: ExistingPolicy) -> SpaceCheck
+	{
+		SpaceCheck { on_existing, needed: BTreeMap::new(), existing: HashMap::new() }
+	}
+
+	pub fn add(&mut self, sample: &Sample, mode: WriteMode)
+	{
+		let keeps = matches!(self.on_existing, ExistingPolicy::Skip | ExistingPolicy::Error); // existing targets are not replaced
+
+		if (mode != WriteMode::Copy && mode != WriteMode::Auto) || (keeps && sample.target_path.symlink_metadata().is_ok())
+		{
+			return;
+		}
+
+		let (dir, dev) = match self.existing.entry(sample.target_path.with_file_name("")).or_insert_with_key(|d| d.ancestors().find_map(|a| device(a).map(|dev| (a.to_path_buf(), dev))))
+		{
+			Some(d) => d.clone(),
+			None => return
+		};
+
+		let size = match std::fs::metadata(&sample.source_path)
+		{
+			Ok(m) if mode == WriteMode::Copy || device(&sample.source_path) != Some(dev) => m.len(), // auto-mode only copies across filesystems
+			_ => return
+		};
+
+		self.needed.entry(dev).or_insert((dir, 0)).1 += size;
+	}
+
+	/// Fails for the first filesystem the copies won't fit into.
+	pub fn check(&self) -> Result<()>
+	{
+		match self.needed.values().find_map(|(dir, bytes)| free_space(dir).filter(|free| free < bytes).map(|free| (dir, *bytes, free)))
+		{
+			None => Ok(()),
+			Some((dir, needed, available)) => Err(Error::NoSpace { path: dir.to_path_buf(), needed, available })
+		}
+	}
+}
+
+/// Checks the copies of all samples against the free space of their filesystem - before anything is written.
+pub fn check_space(samples: &[(&Sample, WriteMode)], on_existing: ExistingPolicy) -> Result<()>
+{
+	let mut space = SpaceCheck::new(on_existing);
+
+	for (sample, mode) in samples.iter()
+	{
+		space.add(sample, *mode);
+	}
+
+	space.check()
+}
+
+// id of the filesystem the path lies on - None if it does not exist
+fn device(path: &Path) -> Option<u64>
+{
+	let meta = std::fs::metadata(path).ok()?;
+
+	#[cfg(target_family = "unix")]
+	{
+		use std::os::unix::fs::MetadataExt;
+
+		Some(meta.dev())
+	}
+
+	#[cfg(not(target_family = "unix"))]
+	{
+		meta.is_dir().then_some(0)
+	}
+}
+
+// bytes available to unprivileged users on the filesystem of the (existing) directory - None if unknown
+#[cfg(target_os = "linux")]
+#[allow(clippy::unnecessary_cast)] // the types of the fields differ between platforms
+fn free_space(dir: &Path) -> Option<u64>
+{
+	use std::os::unix::io::AsRawFd;
+
+	let file = std::fs::File::open(dir).ok()?;
+	// SAFETY: statvfs is plain old data, all-zero is a valid value
+	let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+
+	// SAFETY: the descriptor is owned by the open file and stat is a valid, writable statvfs
+	if unsafe { libc::fstatvfs(file.as_raw_fd(), &mut stat) } != 0
+	{
+		return None;
+	}
+
+	Some((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn free_space(_dir: &Path) -> Option<u64>
+{
+	None
+}
+
+// clones the source into a new target-file, sharing it's blocks until one of them is modified
+#[cfg(target_os = "linux")]
+fn reflink(source: &Path, target: &Path) -> std::io::Result<()>
+{
+	use std::os::unix::io::AsRawFd;
+
+	let from = std::fs::File::open(source)?;
+	let to = std::fs::OpenOptions::new().write(true).create_new(true).open(target)?;
+
+	// SAFETY: both descriptors are owned by the open files above and stay valid for the duration of the call
+	if unsafe { libc::ioctl(to.as_raw_fd(), libc::FICLONE, from.as_raw_fd()) } == 0
+	{
+		return Ok(());
+	}
+
+	let e = std::io::Error::last_os_error();
+
+	drop(to);
+	let _ = std::fs::remove_file(target);
+
+	Err(e)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_source: &Path, _target: &Path) -> std::io::Result<()>
+{
+	Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "reflinks are only supported on linux"))
+}
+
+// sources reached by --follow-links may be links themselves - the hard-link has to point to the file they resolve to
+fn hard_link(source: &Path, target: &Path) -> std::io::Result<()>
+{
+	match source.symlink_metadata()
+	{
+		Ok(m) if m.file_type().is_symlink() => std::fs::hard_link(source.canonicalize()?, target),
+		_ => std::fs::hard_link(source, target)
+	}
+}
+
+fn finish(sample: &Sample, mode: WriteMode, transfer: Transfer, result: std::io::Result<WriteMode>, journal: &mut Journal) -> (WriteOutcome, WriteMode)
+{
+	let source = sample.source_path.as_path();
+	let target = transfer.target;
+
+	let used = match result
+	{
+		Ok(used) => used,
+		Err(e) =>
+		{
+			transfer.backup.iter().for_each(|b| journal.discard(b));
+
+			match mode
+			{
+				WriteMode::Copy => error!("Copying {:?} to {:?} failed: {:?}", source, target, e),
+				WriteMode::Soft => error!("Creating softlink {:?} to {:?} failed: {:?}", target, source, e),
+				WriteMode::Hard => error!("Creating hardlink {:?} to {:?} failed: {:?}", target, source, e),
+				WriteMode::Reflink => error!("Cloning {:?} to {:?} failed: {:?}", source, target, e),
+				WriteMode::Auto => error!("Cloning, linking and copying {:?} to {:?} failed: {:?}", source, target, e)
+			}
+
+			return (WriteOutcome::Failed, mode);
+		}
+	};
+
+	if mode == WriteMode::Auto
+	{
+		debug!("Wrote {:?} as {}", target, used.name());
+	}
+
+	if let Some(backup) = &transfer.backup
+	{
+		journal.replaced(&target, backup);
+	}
+
+	journal.created(&target);
+	(transfer.outcome, used)
+}
+
+// sources inside an archive are as old as the archive
+fn modified(path: &Path) -> Option<std::time::SystemTime>
+{
+	archive::file_of(path).symlink_metadata().and_then(|m| m.modified()).ok()
+}
+
+fn is_newer(source: &Path, target: &Path) -> bool
+{
+	match (modified(source), modified(target))
+	{
+		(Some(s), Some(t)) => s > t,
+		_ => true
+	}
+}
+
+/// True if the target already is the source (same file, link to it or identical content).
+pub fn is_same(source: &Path, target: &Path) -> bool
+{
+	if std::fs::read_link(target).is_ok_and(|l| l == source)
+	{
+		return true;
+	}
+
+	if is_same_file(source, target)
+	{
+		return true;
+	}
+
+	if archive::split(source).is_some()
+	{
+		return archive::read(source).is_ok_and(|s| std::fs::read(target).is_ok_and(|t| s == t));
+	}
+
+	match (std::fs::metadata(source), std::fs::metadata(target))
+	{
+		(Ok(s), Ok(t)) => s.len() == t.len() && same_content(source, target).unwrap_or(false),
+		_ => false
+	}
+}
+
+/// True if both paths are the same file (e.g. hard-links of each other).
+pub fn is_same_file(a: &Path, b: &Path) -> bool
+{
+	#[cfg(target_family = "unix")]
+	{
+		use std::os::unix::fs::MetadataExt;
+
+		if let (Ok(a), Ok(b)) = (std::fs::metadata(a), std::fs::metadata(b))
+		{
+			return a.dev() == b.dev() && a.ino() == b.ino();
+		}
+	}
+
+	#[cfg(target_family = "windows")]
+	{
+		if let (Ok(a), Ok(b)) = (a.canonicalize(), b.canonicalize())
+		{
+			return a == b;
+		}
+	}
+
+	false
+}
+
+fn same_content(a: &Path, b: &Path) -> std::io::Result<bool>
+{
+	use std::io::Read;
+
+	let mut a = std::io::BufReader::new(std::fs::File::open(a)?);
+	let mut b = std::io::BufReader::new(std::fs::File::open(b)?);
+	let mut buf_a = [0u8; 65536];
+	let mut buf_b = [0u8; 65536];
+
+	loop
+	{
+		let read = a.read(&mut buf_a)?;
+
+		if read == 0
+		{
+			return Ok(b.read(&mut buf_b)? == 0);
+		}
+
+		b.read_exact(&mut buf_b[..read])?;
+
+		if buf_a[..read] != buf_b[..read]
+		{
+			return Ok(false);
+		}
+	}
+}
+
+/// First "name N.ext" (N >= 2) next to the given path that does not exist yet and is not taken.
+pub fn free_path(path: &Path, taken: &HashSet<PathBuf>) -> PathBuf
+{
+	let stem = path.file_stem().map_or(String::new(), |s| s.to_string_lossy().to_string());
+	let extension = path.extension().map_or(String::new(), |e| [".", &e.to_string_lossy()].join(""));
+
+	(2..).map(|i| path.with_file_name(format!("{} {}{}", stem, i, extension)))
+	.find(|p| p.symlink_metadata().is_err() && !taken.contains(p))
+	.unwrap_or_else(|| path.to_path_buf())
+}
+
+/// Creates a soft-link at the target-path pointing to the source (as given - it may be relative to the directory of the link).
+pub fn link_soft(source: &Path, target: &Path) -> std::io::Result<()>
+{
+	#[cfg(target_family = "windows")]
+		let linked = std::os::windows::fs::symlink_file(source, target);
+
+	#[cfg(target_family = "unix")]
+		let linked = std::os::unix::fs::symlink(source, target);
+
+	linked
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn relative(dir: &str, to: &str) -> Option<PathBuf>
+	{
+		relative_path(Path::new(dir), Path::new(to))
+	}
+
+	// empty directory of a test below the temporary directory
+	fn scratch(name: &str) -> PathBuf
+	{
+		let dir = std::env::temp_dir().join(format!("kitcat-writer-{}-{}", name, std::process::id()));
+
+		let _ = std::fs::remove_dir_all(&dir);
+		std::fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	fn options(verify: bool) -> CopyOptions
+	{
+		CopyOptions { preserve_mtime: false, verify }
+	}
+
+	fn sample(source: &Path, target: &Path) -> Sample
+	{
+		Sample { source_path: source.to_path_buf(), target_path: target.to_path_buf(), fields: HashMap::new() }
+	}
+
+	// writes the samples as copies into the journal of a throwaway run, the existing targets handled by the policy
+	fn write(samples: &[Sample], on_existing: ExistingPolicy, dir: &Path) -> Vec<WriteOutcome>
+	{
+		let args = ["kitcat", "-i", &dir.to_string_lossy(), "-o", &dir.join("out").to_string_lossy()].iter().map(|a| a.to_string()).collect();
+		let rcon = crate::runtimeconfig::process_args(args).unwrap();
+		let jobs: Vec<(&Sample, WriteMode)> = samples.iter().map(|s| (s, WriteMode::Copy)).collect();
+
+		write_samples(&jobs, on_existing, &SoftLinks::from_config(&rcon), &options(false), &mut Journal::new(&rcon.output), 2).into_iter().map(|(outcome, _)| outcome).collect()
+	}
+
+	#[test]
+	fn relative_paths_climb_to_the_common_directory()
+	{
+		assert_eq!(relative("/out/KitA", "/lib/Drums/Kick.wav"), Some(PathBuf::from("../../lib/Drums/Kick.wav")));
+		assert_eq!(relative("/lib/Kits/KitA", "/lib/Drums/Kick.wav"), Some(PathBuf::from("../../Drums/Kick.wav")));
+	}
+
+	#[test]
+	fn relative_paths_below_the_directory_do_not_climb()
+	{
+		assert_eq!(relative("/lib", "/lib/Drums/Kick.wav"), Some(PathBuf::from("Drums/Kick.wav")));
+		assert_eq!(relative("/lib/Drums", "/lib/Drums"), Some(PathBuf::new()));
+	}
+
+	#[test]
+	fn trailing_separators_are_no_components()
+	{
+		assert_eq!(relative("/out/KitA/", "/lib/Kick.wav"), relative("/out/KitA", "/lib/Kick.wav"));
+	}
+
+	#[test]
+	fn paths_without_a_common_root_have_no_relative_path()
+	{
+		assert_eq!(relative("out/KitA", "lib/Kick.wav"), None);
+	}
+
+	#[cfg(windows)]
+	#[test]
+	fn paths_on_different_drives_have_no_relative_path()
+	{
+		assert_eq!(relative(r"C:\out\KitA", r"D:\lib\Kick.wav"), None);
+	}
+
+	#[test]
+	fn an_existing_target_stays_untouched_until_the_copy_is_renamed_onto_it()
+	{
+		let dir = scratch("replace");
+		let (source, target) = (dir.join("Kick.wav"), dir.join("KitA.wav"));
+		let temp = temp_path(&target);
+
+		std::fs::write(&source, b"new kick").unwrap();
+		std::fs::write(&target, b"old kick").unwrap();
+
+		copy(&source, &temp, &options(true)).unwrap();
+
+		assert_eq!(std::fs::read(&target).unwrap(), b"old kick");
+		assert_eq!(std::fs::read(&temp).unwrap(), b"new kick");
+
+		rename(&temp, &target).unwrap();
+
+		assert_eq!(std::fs::read(&target).unwrap(), b"new kick");
+		assert!(temp.symlink_metadata().is_err());
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn transfers_replace_the_target_and_leave_no_temporary_file()
+	{
+		let dir = scratch("transfer");
+		let (source, target) = (dir.join("Kick.wav"), dir.join("KitA.wav"));
+
+		std::fs::write(&source, b"kick").unwrap();
+		std::fs::write(&target, b"an older and longer kick").unwrap();
+		std::fs::write(temp_path(&target), b"leftover of a killed run").unwrap();
+
+		assert_eq!(transfer(&source, &target, WriteMode::Copy, &options(false)).unwrap(), WriteMode::Copy);
+		assert_eq!(std::fs::read(&target).unwrap(), b"kick");
+		assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn a_copy_failing_the_verification_leaves_neither_temporary_file_nor_target()
+	{
+		let dir = scratch("verify");
+		let (source, target) = (dir.join("Kick.wav"), dir.join("KitA.wav"));
+		let temp = temp_path(&target);
+
+		std::fs::write(&source, b"kick").unwrap();
+		std::fs::write(&temp, b"kicc").unwrap(); // a copy damaged on it's way
+
+		let error = complete(&source, &temp, Ok(()), &target, &options(true)).unwrap_err();
+
+		assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+		assert!(temp.symlink_metadata().is_err());
+		assert!(target.symlink_metadata().is_err());
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[cfg(unix)]
+	#[test]
+	fn copies_keep_the_permissions_of_their_source()
+	{
+		use std::os::unix::fs::PermissionsExt;
+
+		let dir = scratch("mode");
+		let (source, target) = (dir.join("Kick.wav"), dir.join("KitA.wav"));
+
+		std::fs::write(&source, b"kick").unwrap();
+		std::fs::set_permissions(&source, std::fs::Permissions::from_mode(0o444)).unwrap();
+
+		transfer(&source, &target, WriteMode::Copy, &options(true)).unwrap();
+
+		assert_eq!(std::fs::metadata(&target).unwrap().permissions().mode() & 0o777, 0o444);
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[cfg(target_os = "linux")] // the free space is unknown elsewhere
+	#[test]
+	fn the_space_check_adds_up_the_copies_of_the_whole_run()
+	{
+		let dir = scratch("space");
+		let source = dir.join("Kick.wav");
+
+		std::fs::File::create(&source).unwrap().set_len(1 << 30).unwrap(); // sparse - takes no space itself
+
+		let samples: Vec<Sample> = (0..1 << 17).map(|i| sample(&source, &dir.join(format!("KitA/Kick {}.wav", i)))).collect();
+		let copies: Vec<(&Sample, WriteMode)> = samples.iter().map(|s| (s, WriteMode::Copy)).collect();
+		let links: Vec<(&Sample, WriteMode)> = samples.iter().map(|s| (s, WriteMode::Soft)).collect();
+
+		assert!(matches!(check_space(&copies, ExistingPolicy::Error), Err(Error::NoSpace { needed, .. }) if needed == 1 << 47));
+		assert!(check_space(&copies[..1], ExistingPolicy::Error).is_ok());
+		assert!(check_space(&links, ExistingPolicy::Error).is_ok());
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[cfg(target_os = "linux")] // the free space is unknown elsewhere
+	#[test]
+	fn targets_kept_by_the_policy_need_no_space()
+	{
+		let dir = scratch("space-kept");
+		let (source, target) = (dir.join("Kick.wav"), dir.join("KitA.wav"));
+
+		std::fs::File::create(&source).unwrap().set_len(1 << 30).unwrap();
+		std::fs::write(&target, b"kick").unwrap();
+
+		let mut space = SpaceCheck::new(ExistingPolicy::Skip);
+
+		(0..1 << 17).for_each(|_| space.add(&sample(&source, &target), WriteMode::Copy));
+		assert!(space.check().is_ok());
+
+		let mut space = SpaceCheck::new(ExistingPolicy::Overwrite);
+
+		(0..1 << 17).for_each(|_| space.add(&sample(&source, &target), WriteMode::Copy));
+		assert!(space.check().is_err());
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn skip_and_error_leave_an_existing_target_alone()
+	{
+		let dir = scratch("keep");
+		let (source, target) = (dir.join("Kick.wav"), dir.join("out/KitA/Kick.wav"));
+
+		std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+		std::fs::write(&source, b"new kick").unwrap();
+		std::fs::write(&target, b"old kick").unwrap();
+
+		assert_eq!(write(&[sample(&source, &target)], ExistingPolicy::Skip, &dir), [WriteOutcome::Skipped]);
+		assert_eq!(write(&[sample(&source, &target)], ExistingPolicy::Error, &dir), [WriteOutcome::Failed]);
+		assert_eq!(std::fs::read(&target).unwrap(), b"old kick");
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn conditional_overwrites_compare_content_and_age()
+	{
+		let dir = scratch("conditional");
+		let (source, target) = (dir.join("Kick.wav"), dir.join("out/KitA/Kick.wav"));
+
+		std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+		std::fs::write(&source, b"kick").unwrap();
+		std::fs::write(&target, b"kick").unwrap();
+
+		assert_eq!(write(&[sample(&source, &target)], ExistingPolicy::OverwriteIfDifferent, &dir), [WriteOutcome::Skipped]);
+		assert_eq!(write(&[sample(&source, &target)], ExistingPolicy::OverwriteIfNewer, &dir), [WriteOutcome::Skipped]); // the target was written last
+
+		std::fs::write(&target, b"kicc").unwrap();
+
+		assert_eq!(write(&[sample(&source, &target)], ExistingPolicy::OverwriteIfDifferent, &dir), [WriteOutcome::Overwritten]);
+		assert_eq!(std::fs::read(&target).unwrap(), b"kick");
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+
+	#[test]
+	fn renamed_samples_get_a_name_neither_existing_nor_claimed_by_the_batch()
+	{
+		let dir = scratch("rename");
+		let (kick, snare) = (dir.join("Kick.wav"), dir.join("Snare.wav"));
+		let target = dir.join("out/KitA/Kick.wav");
+
+		std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+		std::fs::write(&kick, b"kick").unwrap();
+		std::fs::write(&snare, b"snare").unwrap();
+		std::fs::write(&target, b"foreign").unwrap();
+
+		let outcomes = write(&[sample(&kick, &target), sample(&snare, &dir.join("out/KitA/Kick 2.wav"))], ExistingPolicy::Rename, &dir);
+
+		assert_eq!(outcomes, [WriteOutcome::Renamed(dir.join("out/KitA/Kick 2.wav")), WriteOutcome::Renamed(dir.join("out/KitA/Kick 2 2.wav"))]);
+		assert_eq!(std::fs::read(dir.join("out/KitA/Kick 2.wav")).unwrap(), b"kick");
+		assert_eq!(std::fs::read(dir.join("out/KitA/Kick 2 2.wav")).unwrap(), b"snare");
+		assert_eq!(std::fs::read(&target).unwrap(), b"foreign");
+
+		let _ = std::fs::remove_dir_all(&dir);
+	}
+}